@@ -0,0 +1,57 @@
+#![no_main]
+
+//! Input layout: a quirks byte, a ROM length byte (in words), the ROM, then two bytes of
+//! keypad state per frame.
+
+use chip8::{Cpu, KeyPad, Op, Quirks, RandomSource};
+use libfuzzer_sys::fuzz_target;
+
+const CYCLES_PER_FRAME: usize = 10;
+
+/// How many bytes from I the instruction reads or writes.
+fn bytes_at_i(op: Op, planes: u8) -> usize {
+    let planes = planes.count_ones() as usize;
+    match op {
+        Op::Drw(_, _, 0) => 32 * planes,
+        Op::Drw(_, _, n) => n as usize * planes,
+        Op::LdBCD(_) => 3,
+        Op::LdRegs(x) | Op::RdMem(x) => x as usize + 1,
+        Op::SaveRange(x, y) | Op::LoadRange(x, y) => (x as i32 - y as i32).unsigned_abs() as usize + 1,
+        Op::Audio => 16,
+        _ => 0,
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let quirks = Quirks::from_bits(data[0]).unwrap();
+    let rom_len = (data[1] as usize * 2).min(data.len() - 2);
+    let (rom, keys) = data[2..].split_at(rom_len);
+
+    let mut cpu = Cpu::with_quirks(quirks);
+    cpu.set_random_source(RandomSource::new(0));
+    cpu.load_program(rom).unwrap();
+    for frame in keys.chunks_exact(2) {
+        let keypad = KeyPad::from_bits(u16::from_le_bytes([frame[0], frame[1]]));
+        for _ in 0..CYCLES_PER_FRAME {
+            let (pc, i, planes) = (cpu.pc(), cpu.i as usize, cpu.screen.selected_planes());
+            if cpu.has_exited() {
+                return;
+            }
+            let op = match cpu.cycle(&keypad) {
+                Ok(op) => op,
+                Err(_) => return,
+            };
+            assert!(cpu.pc() + 2 <= cpu.memory_size(), "pc {:#X} outside memory", cpu.pc());
+            assert!(cpu.stack().len() < 16, "stack pointer {} outside the stack", cpu.stack().len());
+            // A DXYN waiting for the display did not touch memory.
+            if cpu.pc() != pc {
+                let len = bytes_at_i(op, planes);
+                assert!(i + len <= cpu.memory_size(), "{:?} accessed {:#X}..{:#X}, outside memory", op, i, i + len);
+            }
+        }
+        cpu.update_timers();
+    }
+});
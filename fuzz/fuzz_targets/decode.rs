@@ -0,0 +1,15 @@
+#![no_main]
+
+use chip8::{decode, disasm};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for word in data.chunks_exact(2) {
+        let opcode = u16::from_be_bytes([word[0], word[1]]);
+        decode(opcode);
+    }
+    for addr in 0..data.len() {
+        disasm::instruction_at(data, addr);
+    }
+    disasm::reachable(data);
+});
@@ -0,0 +1,384 @@
+use disasm::{self, instruction_at, successors, Syntax, LOAD_ADDRESS};
+use opcodes::Op;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write}
+};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    /// Address just past the last instruction.
+    pub end: usize,
+    pub successors: Vec<usize>,
+    pub calls: Vec<usize>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Issue {
+    InvalidOpcode { address: usize, opcode: u16 },
+    OutsideRom { address: usize },
+    /// A `JP V0, addr` whose target depends on V0 at run time.
+    ComputedJump { address: usize, base: u16 },
+    /// A store through I, set in the same block, that overwrites instructions.
+    CodeWrite { address: usize, target: usize, len: usize },
+}
+
+impl Issue {
+    pub fn address(&self) -> usize {
+        match *self {
+            Issue::InvalidOpcode { address, .. } | Issue::OutsideRom { address }
+                | Issue::ComputedJump { address, .. } | Issue::CodeWrite { address, .. } => address,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match *self {
+            Issue::InvalidOpcode { .. } => "invalid_opcode",
+            Issue::OutsideRom { .. } => "outside_rom",
+            Issue::ComputedJump { .. } => "computed_jump",
+            Issue::CodeWrite { .. } => "code_write",
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Issue::InvalidOpcode { address, opcode } =>
+                write!(f, "{:04X}: invalid opcode {:04X}", address, opcode),
+            Issue::OutsideRom { address } => write!(f, "{:04X}: reached outside the ROM", address),
+            Issue::ComputedJump { address, base } =>
+                write!(f, "{:04X}: computed jump from {:03X}", address, base),
+            Issue::CodeWrite { address, target, .. } =>
+                write!(f, "{:04X}: overwrites code at {:04X}", address, target),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RegionKind {
+    Code,
+    Data,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub kind: RegionKind,
+}
+
+pub struct Analysis {
+    pub code: BTreeMap<usize, (Op, usize)>,
+    pub blocks: BTreeMap<usize, Block>,
+    /// Subroutine entry points, `0x200` included, and the subroutines they call.
+    pub call_graph: BTreeMap<usize, BTreeSet<usize>>,
+    pub regions: Vec<Region>,
+    pub issues: Vec<Issue>,
+    rom: Vec<u8>,
+}
+
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let mut code = BTreeMap::new();
+    let mut issues = Vec::new();
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![LOAD_ADDRESS];
+    while let Some(addr) = pending.pop() {
+        if !visited.insert(addr) {
+            continue;
+        }
+        match instruction_at(rom, addr) {
+            Some((op, len)) => {
+                if let Op::JpRegI(base) = op {
+                    issues.push(Issue::ComputedJump { address: addr, base });
+                }
+                code.insert(addr, (op, len));
+                pending.extend(successors(rom, op, addr, len));
+            }
+            None => match addr.checked_sub(LOAD_ADDRESS).and_then(|i| rom.get(i..i + 2)) {
+                Some(bytes) => issues.push(Issue::InvalidOpcode {
+                    address: addr,
+                    opcode: (bytes[0] as u16) << 8 | bytes[1] as u16,
+                }),
+                None => issues.push(Issue::OutsideRom { address: addr }),
+            },
+        }
+    }
+
+    let blocks = blocks(rom, &code);
+    let call_graph = call_graph(&blocks);
+    let code_bytes: BTreeSet<usize> = code.iter()
+        .flat_map(|(&addr, &(_, len))| addr..addr + len)
+        .collect();
+    issues.extend(code_writes(rom, &code, &blocks, &code_bytes));
+    issues.sort_by_key(Issue::address);
+
+    let mut regions: Vec<Region> = Vec::new();
+    for addr in LOAD_ADDRESS..LOAD_ADDRESS + rom.len() {
+        let kind = if code_bytes.contains(&addr) { RegionKind::Code } else { RegionKind::Data };
+        match regions.last_mut() {
+            Some(region) if region.kind == kind => region.end = addr + 1,
+            _ => regions.push(Region { start: addr, end: addr + 1, kind }),
+        }
+    }
+
+    Analysis { code, blocks, call_graph, regions, issues, rom: rom.to_vec() }
+}
+
+fn blocks(rom: &[u8], code: &BTreeMap<usize, (Op, usize)>) -> BTreeMap<usize, Block> {
+    let mut leaders = BTreeSet::new();
+    leaders.insert(LOAD_ADDRESS);
+    for (&addr, &(op, len)) in code {
+        let next = successors(rom, op, addr, len);
+        if next != [addr + len] {
+            leaders.extend(next);
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in leaders.iter().filter(|addr| code.contains_key(addr)) {
+        let mut addr = start;
+        let block = loop {
+            let (op, len) = code[&addr];
+            let next = addr + len;
+            let targets = successors(rom, op, addr, len);
+            if targets != [next] || leaders.contains(&next) || !code.contains_key(&next) {
+                let (successors, calls) = match op {
+                    Op::Call(target) => (vec![next], vec![target as usize]),
+                    _ => (targets, vec![]),
+                };
+                break Block { start, end: next, successors, calls };
+            }
+            addr = next;
+        };
+        blocks.insert(start, block);
+    }
+    blocks
+}
+
+fn call_graph(blocks: &BTreeMap<usize, Block>) -> BTreeMap<usize, BTreeSet<usize>> {
+    let mut entries: BTreeSet<usize> = blocks.values().flat_map(|b| b.calls.iter().cloned()).collect();
+    entries.insert(LOAD_ADDRESS);
+    entries.iter()
+        .filter(|entry| blocks.contains_key(entry))
+        .map(|&entry| {
+            let mut callees = BTreeSet::new();
+            let mut seen = BTreeSet::new();
+            let mut pending = vec![entry];
+            while let Some(start) = pending.pop() {
+                if !seen.insert(start) {
+                    continue;
+                }
+                if let Some(block) = blocks.get(&start) {
+                    callees.extend(block.calls.iter().cloned());
+                    pending.extend(block.successors.iter().cloned());
+                }
+            }
+            (entry, callees)
+        })
+        .collect()
+}
+
+fn code_writes(rom: &[u8], code: &BTreeMap<usize, (Op, usize)>, blocks: &BTreeMap<usize, Block>,
+               code_bytes: &BTreeSet<usize>) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for block in blocks.values() {
+        let mut i = None;
+        for (&addr, &(op, _)) in code.range(block.start..block.end) {
+            let written = match op {
+                Op::LdBCD(_) => 3,
+                Op::LdRegs(x) => x as usize + 1,
+                Op::SaveRange(x, y) => (x as isize - y as isize).unsigned_abs() + 1,
+                _ => 0,
+            };
+            if let (Some(target), true) = (i, written > 0) {
+                if (target..target + written).any(|a| code_bytes.contains(&a)) {
+                    issues.push(Issue::CodeWrite { address: addr, target, len: written });
+                }
+            }
+            i = match op {
+                Op::LdI(target) => Some(target as usize),
+                Op::LdILong => {
+                    let offset = addr - LOAD_ADDRESS + 2;
+                    Some((rom[offset] as usize) << 8 | rom[offset + 1] as usize)
+                }
+                Op::AddToI(_) | Op::LdChr(_) | Op::LdHf(_) | Op::LdRegs(_) | Op::RdMem(_) => None,
+                _ => i,
+            };
+        }
+    }
+    issues
+}
+
+impl Analysis {
+    /// The control-flow graph in graphviz format, one node per basic block. Calls are
+    /// dashed edges and blocks with issues are red.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph rom {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (&addr, &(op, len)) in self.code.range(block.start..block.end) {
+                let bytes = &self.rom[addr - LOAD_ADDRESS..addr - LOAD_ADDRESS + len];
+                let text = disasm::mnemonic(op, bytes, Syntax::Chipper, &BTreeMap::new());
+                write!(label, "{:04X}  {}\\l", addr, text).unwrap();
+            }
+            let issues: Vec<&Issue> = self.issues.iter()
+                .filter(|issue| issue.address() >= block.start && issue.address() < block.end)
+                .collect();
+            for issue in &issues {
+                write!(label, "{}\\l", issue).unwrap();
+            }
+            let color = if issues.is_empty() { "" } else { ", color=red" };
+            writeln!(out, "    b{:03X} [label=\"{}\"{}];", block.start, label, color).unwrap();
+        }
+        for issue in &self.issues {
+            if let Issue::InvalidOpcode { .. } | Issue::OutsideRom { .. } = *issue {
+                writeln!(out, "    b{:03X} [label=\"{}\", color=red];", issue.address(), issue).unwrap();
+            }
+        }
+        for block in self.blocks.values() {
+            for target in &block.successors {
+                writeln!(out, "    b{:03X} -> b{:03X};", block.start, target).unwrap();
+            }
+            for target in &block.calls {
+                writeln!(out, "    b{:03X} -> b{:03X} [style=dashed];", block.start, target).unwrap();
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let list = |values: &mut dyn Iterator<Item = &usize>| {
+            values.map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+        };
+        let blocks: Vec<String> = self.blocks.values()
+            .map(|b| format!(
+                "    {{\"start\": {}, \"end\": {}, \"successors\": [{}], \"calls\": [{}]}}",
+                b.start, b.end, list(&mut b.successors.iter()), list(&mut b.calls.iter()),
+            ))
+            .collect();
+        let subroutines: Vec<String> = self.call_graph.iter()
+            .map(|(entry, callees)| {
+                format!("    {{\"entry\": {}, \"calls\": [{}]}}", entry, list(&mut callees.iter()))
+            })
+            .collect();
+        let regions: Vec<String> = self.regions.iter()
+            .map(|r| {
+                let kind = match r.kind {
+                    RegionKind::Code => "code",
+                    RegionKind::Data => "data",
+                };
+                format!("    {{\"start\": {}, \"end\": {}, \"kind\": \"{}\"}}", r.start, r.end, kind)
+            })
+            .collect();
+        let issues: Vec<String> = self.issues.iter()
+            .map(|issue| {
+                let detail = match *issue {
+                    Issue::InvalidOpcode { opcode, .. } => format!(", \"opcode\": {}", opcode),
+                    Issue::OutsideRom { .. } => String::new(),
+                    Issue::ComputedJump { base, .. } => format!(", \"base\": {}", base),
+                    Issue::CodeWrite { target, len, .. } =>
+                        format!(", \"target\": {}, \"len\": {}", target, len),
+                };
+                format!("    {{\"kind\": \"{}\", \"address\": {}{}}}", issue.kind(), issue.address(), detail)
+            })
+            .collect();
+        format!(
+            "{{\n  \"blocks\": {},\n  \"subroutines\": {},\n  \"regions\": {},\n  \"issues\": {}\n}}\n",
+            json_array(&blocks), json_array(&subroutines), json_array(&regions), json_array(&issues),
+        )
+    }
+}
+
+fn json_array(items: &[String]) -> String {
+    if items.is_empty() {
+        return "[]".to_string();
+    }
+    format!("[\n{}\n  ]", items.join(",\n"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 200: CALL 20A     202: SE V0, 1    204: JP 208     206: CLS
+    // 208: JP 208       20A: LD I, 20E   20C: LD [I], V1 20E: RET
+    // 210: data
+    const ROM: [u8; 18] = [
+        0x22, 0x0A, 0x30, 0x01, 0x12, 0x08, 0x00, 0xE0, 0x12, 0x08, 0xA2, 0x0E, 0xF1, 0x55,
+        0x00, 0xEE, 0xAB, 0xCD,
+    ];
+
+    #[test]
+    fn basic_blocks() {
+        let analysis = analyze(&ROM);
+        let starts: Vec<usize> = analysis.blocks.keys().cloned().collect();
+        assert_eq!(vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20A], starts);
+        assert_eq!(Block { start: 0x200, end: 0x202, successors: vec![0x202], calls: vec![0x20A] },
+                   analysis.blocks[&0x200]);
+        assert_eq!(vec![0x204, 0x206], analysis.blocks[&0x202].successors);
+        assert_eq!(vec![0x208], analysis.blocks[&0x206].successors);
+        assert_eq!(0x210, analysis.blocks[&0x20A].end);
+    }
+
+    #[test]
+    fn call_graph_and_regions() {
+        let analysis = analyze(&ROM);
+        let mut expected = BTreeMap::new();
+        expected.insert(0x200, vec![0x20A].into_iter().collect());
+        expected.insert(0x20A, BTreeSet::new());
+        assert_eq!(expected, analysis.call_graph);
+        assert_eq!(vec![
+            Region { start: 0x200, end: 0x210, kind: RegionKind::Code },
+            Region { start: 0x210, end: 0x212, kind: RegionKind::Data },
+        ], analysis.regions);
+    }
+
+    #[test]
+    fn issues() {
+        assert_eq!(vec![Issue::CodeWrite { address: 0x20C, target: 0x20E, len: 2 }], analyze(&ROM).issues);
+
+        let rom = [0x30, 0x00, 0xB3, 0x00, 0x12, 0x06, 0xFF, 0xFF];
+        assert_eq!(vec![
+            Issue::ComputedJump { address: 0x202, base: 0x300 },
+            Issue::InvalidOpcode { address: 0x206, opcode: 0xFFFF },
+        ], analyze(&rom).issues);
+        assert_eq!(vec![Issue::OutsideRom { address: 0x202 }], analyze(&[0x00, 0xE0]).issues);
+    }
+
+    #[test]
+    fn dot_output() {
+        let dot = analyze(&[0x30, 0x00, 0x12, 0x00, 0xFF, 0xFF]).to_dot();
+        assert!(dot.starts_with("digraph rom {\n"));
+        assert!(dot.contains("    b200 [label=\"0200  SE V0, #00\\l\"];\n"));
+        assert!(dot.contains("    b204 [label=\"0204: invalid opcode FFFF\", color=red];\n"));
+        assert!(dot.contains("    b200 -> b202;\n    b200 -> b204;\n"));
+    }
+
+    #[test]
+    fn json_output() {
+        let json = analyze(&[0x22, 0x04, 0x00, 0xFD, 0x00, 0xEE]).to_json();
+        assert_eq!("\
+{
+  \"blocks\": [
+    {\"start\": 512, \"end\": 514, \"successors\": [514], \"calls\": [516]},
+    {\"start\": 514, \"end\": 516, \"successors\": [], \"calls\": []},
+    {\"start\": 516, \"end\": 518, \"successors\": [], \"calls\": []}
+  ],
+  \"subroutines\": [
+    {\"entry\": 512, \"calls\": [516]},
+    {\"entry\": 516, \"calls\": []}
+  ],
+  \"regions\": [
+    {\"start\": 512, \"end\": 518, \"kind\": \"code\"}
+  ],
+  \"issues\": []
+}
+", json);
+    }
+}
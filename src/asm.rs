@@ -0,0 +1,805 @@
+use disasm::LOAD_ADDRESS;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    iter::Peekable,
+    str::CharIndices
+};
+
+const MAX_PROGRAM_SIZE: usize = 0x10000 - LOAD_ADDRESS;
+
+const DIRECTIVES: &[&str] = &[
+    "=", "EQU", "OPTION", "ALIGN", "DEFINE", "UNDEF", "USED", "XREF", "END", "DB", "DW", "DA",
+    "IFDEF", "IFUND", "ELSE", "ENDIF",
+];
+
+const CHIPPER_MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR",
+    "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH",
+];
+
+const ROBSON_MNEMONICS: &[&str] = &[
+    "CLS", "RTS", "JMP", "JMI", "JSR", "SKEQ", "SKNE", "MOV", "ADD", "OR", "AND", "XOR", "SUB",
+    "SHR", "RSB", "SHL", "RANDOM", "SPRITE", "SKPR", "SKUP", "MVI", "ADI", "LDR", "STR", "SDELAY",
+    "GDELAY", "SSOUND", "KEY", "FONT", "XFONT", "BCD", "HALT",
+];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Operand {
+    Register(u8),
+    Range(u8, u8),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Expr(String),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Operand {
+        let upper = text.trim().to_uppercase();
+        if let Some(register) = register(&upper) {
+            return Operand::Register(register);
+        }
+        match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::DelayTimer,
+            "ST" => Operand::SoundTimer,
+            "K" => Operand::Key,
+            "F" => Operand::Font,
+            "HF" => Operand::BigFont,
+            "B" => Operand::Bcd,
+            "R" => Operand::Flags,
+            _ => {
+                let mut range = upper.splitn(2, '-').map(register);
+                match (range.next(), range.next()) {
+                    (Some(Some(x)), Some(Some(y))) => Operand::Range(x, y),
+                    _ => Operand::Expr(text.trim().to_string()),
+                }
+            }
+        }
+    }
+}
+
+fn register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(digit), None) | (Some('R'), Some(digit), None) =>
+            digit.to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Instruction(String, Vec<Operand>),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Ascii(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    line: usize,
+    address: usize,
+    item: Item,
+}
+
+struct Symbols {
+    values: HashMap<String, i64>,
+}
+
+impl Symbols {
+    fn get(&self, name: &str) -> Option<i64> {
+        self.values.get(&name.to_uppercase()).cloned()
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        if self.values.insert(name.to_uppercase(), value).is_some() {
+            return Err(format!("symbol {} is already defined", name));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Dialect {
+    Chipper,
+    /// Paul Robson's assembler (MOV, JSR, SKEQ...), which evaluates expressions right to left.
+    Robson,
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Dialect> {
+        match name.to_lowercase().as_str() {
+            "chipper" => Some(Dialect::Chipper),
+            "robson" => Some(Dialect::Robson),
+            _ => None,
+        }
+    }
+
+    /// Robson's dialect if the source uses one of its mnemonics that CHIPPER lacks.
+    pub fn detect(source: &str) -> Dialect {
+        let robson_only = |word: &str| {
+            let word = word.to_uppercase();
+            ROBSON_MNEMONICS.contains(&word.as_str()) && !CHIPPER_MNEMONICS.contains(&word.as_str())
+        };
+        // The second word covers labels written without a colon.
+        let robson = source.lines()
+            .any(|raw| split_label(strip_comment(raw)).1.split_whitespace().take(2).any(robson_only));
+        if robson { Dialect::Robson } else { Dialect::Chipper }
+    }
+}
+
+#[derive(Default)]
+pub struct Assembler {
+    /// Detected from the source when not set.
+    dialect: Option<Dialect>,
+    defines: Vec<String>,
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler::default()
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> Assembler {
+        self.dialect = Some(dialect);
+        self
+    }
+
+    pub fn define(mut self, name: &str) -> Assembler {
+        self.defines.push(name.to_uppercase());
+        self
+    }
+
+    pub fn assemble(&self, source: &str) -> Result<Vec<u8>, AsmError> {
+        let dialect = self.dialect.unwrap_or_else(|| Dialect::detect(source));
+        let mut defines = self.defines.clone();
+        let mut symbols = Symbols { values: HashMap::new() };
+        let mut deferred = Vec::new();
+        let mut statements = Vec::new();
+        let mut conditions: Vec<(bool, bool)> = Vec::new();
+        let mut address = LOAD_ADDRESS;
+        let mut align = true;
+        let mut pending_labels: Vec<(usize, &str)> = Vec::new();
+
+        for (index, raw) in source.lines().enumerate() {
+            let line = index + 1;
+            let error = |message: String| AsmError { line, message };
+            let text = strip_comment(raw);
+            let (label, rest) = split_label(text);
+            // CHIPPER ignores labels that are not plain names, 15PUZZLE.SRC relies on it.
+            let label = label.filter(|label| is_identifier(label));
+            let mut words = rest.splitn(2, char::is_whitespace);
+            let first_word = words.next().unwrap_or("");
+            let first = first_word.to_uppercase();
+            let args = words.next().unwrap_or("").trim();
+
+            let active = conditions.iter().all(|&(active, _)| active);
+            if let (true, Some(label)) = (active, label) {
+                if let "IFDEF" | "IFUND" | "ELSE" | "ENDIF" = first.as_str() {
+                    pending_labels.push((line, label));
+                }
+            }
+
+            match first.as_str() {
+                "IFDEF" | "IFUND" => {
+                    let defined = defines.contains(&args.to_uppercase());
+                    let holds = defined == (first == "IFDEF");
+                    conditions.push((active && holds, active && !holds));
+                    continue;
+                }
+                "ELSE" => {
+                    let condition = conditions.last_mut().ok_or_else(|| error("ELSE without IF".into()))?;
+                    *condition = (condition.1, false);
+                    continue;
+                }
+                "ENDIF" => {
+                    conditions.pop().ok_or_else(|| error("ENDIF without IF".into()))?;
+                    continue;
+                }
+                _ => (),
+            }
+            if !conditions.iter().all(|&(active, _)| active) {
+                continue;
+            }
+
+            let (name, first, args) = match (label, first.as_str()) {
+                (None, _) if !is_keyword(&first, dialect) && !args.is_empty() => {
+                    let mut words = args.splitn(2, char::is_whitespace);
+                    let second = words.next().unwrap_or("").to_uppercase();
+                    let rest = words.next().unwrap_or("").trim();
+                    (Some(first_word), second, rest)
+                }
+                (label, _) => (label, first, args),
+            };
+
+            if first == "=" || first == "EQU" {
+                let name = name.ok_or_else(|| error(format!("{} needs a symbol name", first)))?;
+                match evaluate(args, address, &symbols, dialect) {
+                    Ok(value) => symbols.define(name, value).map_err(&error)?,
+                    Err(_) => deferred.push((line, name.to_string(), args.to_string(), address)),
+                }
+                continue;
+            }
+
+            let item = match first.as_str() {
+                "" => None,
+                "OPTION" => {
+                    match args.to_uppercase().as_str() {
+                        "BINARY" | "CHIP8" | "CHIP48" | "SCHIP10" | "SCHIP11" | "HPASM" | "STRING" => (),
+                        option => return Err(error(format!("unknown option: {}", option))),
+                    }
+                    None
+                }
+                "ALIGN" => {
+                    align = match args.to_uppercase().as_str() {
+                        "ON" => true,
+                        "OFF" => false,
+                        value => return Err(error(format!("ALIGN expects ON or OFF, got {}", value))),
+                    };
+                    None
+                }
+                "DEFINE" => {
+                    defines.push(args.to_uppercase());
+                    None
+                }
+                "UNDEF" => {
+                    defines.retain(|d| *d != args.to_uppercase());
+                    None
+                }
+                "USED" | "XREF" | "END" => None,
+                "DB" => Some(Item::Bytes(split_operands(args))),
+                "DW" => Some(Item::Words(split_operands(args))),
+                "DA" => Some(Item::Ascii(parse_string(args).map_err(&error)?)),
+                mnemonic if is_mnemonic(mnemonic, dialect) => Some(Item::Instruction(
+                    mnemonic.to_string(),
+                    split_operands(args).iter().map(|o| Operand::parse(o)).collect(),
+                )),
+                mnemonic => return Err(error(format!("unknown instruction: {}", mnemonic))),
+            };
+
+            if let Some(item) = item {
+                let aligned = match item {
+                    Item::Instruction(..) | Item::Words(_) => align,
+                    _ => false,
+                };
+                if aligned && address % 2 == 1 {
+                    statements.push(Statement { line, address, item: Item::Bytes(vec!["0".into()]) });
+                    address += 1;
+                }
+                for (line, label) in pending_labels.drain(..).chain(name.map(|name| (line, name))) {
+                    symbols.define(label, address as i64).map_err(|message| AsmError { line, message })?;
+                }
+                let size = match item {
+                    Item::Instruction(..) => 2,
+                    Item::Bytes(ref values) => values.len(),
+                    Item::Words(ref values) => values.len() * 2,
+                    Item::Ascii(ref bytes) => bytes.len(),
+                };
+                statements.push(Statement { line, address, item });
+                address += size;
+                if address - LOAD_ADDRESS > MAX_PROGRAM_SIZE {
+                    return Err(error("program does not fit in memory".into()));
+                }
+            } else if let Some(name) = name {
+                pending_labels.push((line, name));
+            }
+        }
+        for (line, label) in pending_labels {
+            symbols.define(label, address as i64).map_err(|message| AsmError { line, message })?;
+        }
+
+        if !conditions.is_empty() {
+            return Err(AsmError { line: source.lines().count(), message: "missing ENDIF".into() });
+        }
+
+        while !deferred.is_empty() {
+            let before = deferred.len();
+            let mut first_error = None;
+            let mut remaining = Vec::new();
+            for (line, name, expr, address) in deferred {
+                match evaluate(&expr, address, &symbols, dialect) {
+                    Ok(value) => symbols.define(&name, value)
+                        .map_err(|message| AsmError { line, message })?,
+                    Err(message) => {
+                        first_error.get_or_insert(AsmError { line, message });
+                        remaining.push((line, name, expr, address));
+                    }
+                }
+            }
+            if remaining.len() == before {
+                return Err(first_error.unwrap());
+            }
+            deferred = remaining;
+        }
+
+        let mut output = Vec::with_capacity(address - LOAD_ADDRESS);
+        for statement in &statements {
+            let error = |message: String| AsmError { line: statement.line, message };
+            let value = |expr: &str| evaluate(expr, statement.address, &symbols, dialect).map_err(&error);
+            match statement.item {
+                Item::Instruction(ref mnemonic, ref operands) => {
+                    let opcode = encode(mnemonic, operands, &|expr| value(expr).map_err(|e| e.message))
+                        .map_err(&error)?;
+                    output.extend_from_slice(&[(opcode >> 8) as u8, opcode as u8]);
+                }
+                Item::Bytes(ref values) => for expr in values {
+                    output.push(check_range(value(expr)?, 8).map_err(&error)? as u8);
+                },
+                Item::Words(ref values) => for expr in values {
+                    let word = check_range(value(expr)?, 16).map_err(&error)?;
+                    output.extend_from_slice(&[(word >> 8) as u8, word as u8]);
+                },
+                Item::Ascii(ref bytes) => output.extend_from_slice(bytes),
+            }
+        }
+        Ok(output)
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+fn split_label(text: &str) -> (Option<&str>, &str) {
+    let trimmed = text.trim_start();
+    let token_end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    match trimmed[..token_end].find(':') {
+        Some(colon) if colon > 0 => (Some(&trimmed[..colon]), trimmed[colon + 1..].trim()),
+        _ => (None, trimmed.trim_end()),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_keyword(word: &str, dialect: Dialect) -> bool {
+    is_mnemonic(word, dialect) || DIRECTIVES.contains(&word)
+}
+
+fn is_mnemonic(word: &str, dialect: Dialect) -> bool {
+    match dialect {
+        Dialect::Chipper => CHIPPER_MNEMONICS.contains(&word),
+        Dialect::Robson => ROBSON_MNEMONICS.contains(&word),
+    }
+}
+
+fn split_operands(args: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in args.chars() {
+        match c {
+            ',' if !quoted => operands.push(current.trim().to_string()),
+            _ => {
+                quoted ^= c == '\'';
+                current.push(c);
+                continue;
+            }
+        }
+        current.clear();
+    }
+    if !current.trim().is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+fn parse_string(args: &str) -> Result<Vec<u8>, String> {
+    let args = args.trim();
+    if args.len() < 2 || !args.starts_with('\'') || !args.ends_with('\'') {
+        return Err(format!("DA expects a quoted string, got {}", args));
+    }
+    Ok(args[1..args.len() - 1].replace("''", "'").into_bytes())
+}
+
+fn check_range(value: i64, bits: u32) -> Result<u32, String> {
+    let max = (1i64 << bits) - 1;
+    let min = -(1i64 << (bits - 1));
+    if value < min || value > max {
+        return Err(format!("value {} does not fit in {} bits", value, bits));
+    }
+    Ok((value & max) as u32)
+}
+
+fn encode(mnemonic: &str, operands: &[Operand], value: &dyn Fn(&str) -> Result<i64, String>)
+          -> Result<u16, String> {
+    use self::Operand::*;
+
+    let expr = |bits: u32, text: &str| value(text).and_then(|v| check_range(v, bits)).map(|v| v as u16);
+    let addr = |text: &str| {
+        let v = value(text)?;
+        if !(0..=0xFFF).contains(&v) {
+            return Err(format!("address {:#X} is out of range", v));
+        }
+        Ok(v as u16)
+    };
+    let nibble = |text: &str| {
+        let v = value(text)?;
+        if !(0..=0xF).contains(&v) {
+            return Err(format!("value {} does not fit in 4 bits", v));
+        }
+        Ok(v as u16)
+    };
+    let xy = |base: u16, x: u8, y: u8| base | (x as u16) << 8 | (y as u16) << 4;
+    let xkk = |base: u16, x: u8, kk: &str| expr(8, kk).map(|kk| base | (x as u16) << 8 | kk);
+    let fx = |low: u16, x: u8| 0xF000 | (x as u16) << 8 | low;
+
+    let opcode = match (mnemonic, operands) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) | ("RTS", []) => 0x00EE,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) | ("HALT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("SCD", [Expr(n)]) => 0x00C0 | nibble(n)?,
+        ("SYS", [Expr(a)]) => addr(a)?,
+        ("JP", [Expr(a)]) | ("JMP", [Expr(a)]) => 0x1000 | addr(a)?,
+        ("JP", [Register(0), Expr(a)]) | ("JMI", [Expr(a)]) => 0xB000 | addr(a)?,
+        ("CALL", [Expr(a)]) | ("JSR", [Expr(a)]) => 0x2000 | addr(a)?,
+        ("SE", [Register(x), Register(y)]) | ("SKEQ", [Register(x), Register(y)]) => xy(0x5000, *x, *y),
+        ("SE", [Register(x), Expr(kk)]) | ("SKEQ", [Register(x), Expr(kk)]) => xkk(0x3000, *x, kk)?,
+        ("SNE", [Register(x), Register(y)]) | ("SKNE", [Register(x), Register(y)]) => xy(0x9000, *x, *y),
+        ("SNE", [Register(x), Expr(kk)]) | ("SKNE", [Register(x), Expr(kk)]) => xkk(0x4000, *x, kk)?,
+        ("LD", [Register(x), Register(y)]) | ("MOV", [Register(x), Register(y)]) => xy(0x8000, *x, *y),
+        ("LD", [Register(x), Expr(kk)]) | ("MOV", [Register(x), Expr(kk)]) => xkk(0x6000, *x, kk)?,
+        ("LD", [I, Expr(a)]) | ("MVI", [Expr(a)]) => 0xA000 | addr(a)?,
+        ("LD", [Register(x), DelayTimer]) | ("GDELAY", [Register(x)]) => fx(0x07, *x),
+        ("LD", [Register(x), Key]) | ("KEY", [Register(x)]) => fx(0x0A, *x),
+        ("LD", [DelayTimer, Register(x)]) | ("SDELAY", [Register(x)]) => fx(0x15, *x),
+        ("LD", [SoundTimer, Register(x)]) | ("SSOUND", [Register(x)]) => fx(0x18, *x),
+        ("LD", [Font, Register(x)]) | ("FONT", [Register(x)]) => fx(0x29, *x),
+        ("LD", [BigFont, Register(x)]) | ("XFONT", [Register(x)]) => fx(0x30, *x),
+        ("LD", [Bcd, Register(x)]) | ("BCD", [Register(x)]) => fx(0x33, *x),
+        ("LD", [IndirectI, Register(x)]) | ("STR", [Range(0, x)]) => fx(0x55, *x),
+        ("LD", [Register(x), IndirectI]) | ("LDR", [Range(0, x)]) => fx(0x65, *x),
+        ("LD", [Flags, Register(x)]) => fx(0x75, *x),
+        ("LD", [Register(x), Flags]) => fx(0x85, *x),
+        ("ADD", [Register(x), Register(y)]) => xy(0x8004, *x, *y),
+        ("ADD", [Register(x), Expr(kk)]) => xkk(0x7000, *x, kk)?,
+        ("ADD", [I, Register(x)]) | ("ADI", [Register(x)]) => fx(0x1E, *x),
+        ("OR", [Register(x), Register(y)]) => xy(0x8001, *x, *y),
+        ("AND", [Register(x), Register(y)]) => xy(0x8002, *x, *y),
+        ("XOR", [Register(x), Register(y)]) => xy(0x8003, *x, *y),
+        ("SUB", [Register(x), Register(y)]) => xy(0x8005, *x, *y),
+        ("SHR", [Register(x)]) => xy(0x8006, *x, 0),
+        ("SHR", [Register(x), Register(y)]) => xy(0x8006, *x, *y),
+        ("SUBN", [Register(x), Register(y)]) | ("RSB", [Register(x), Register(y)]) => xy(0x8007, *x, *y),
+        ("SHL", [Register(x)]) => xy(0x800E, *x, 0),
+        ("SHL", [Register(x), Register(y)]) => xy(0x800E, *x, *y),
+        ("RND", [Register(x), Expr(kk)]) | ("RANDOM", [Register(x), Expr(kk)]) => xkk(0xC000, *x, kk)?,
+        ("DRW", [Register(x), Register(y), Expr(n)]) | ("SPRITE", [Register(x), Register(y), Expr(n)]) =>
+            xy(0xD000, *x, *y) | nibble(n)?,
+        ("SKP", [Register(x)]) | ("SKPR", [Register(x)]) => 0xE09E | (*x as u16) << 8,
+        ("SKNP", [Register(x)]) | ("SKUP", [Register(x)]) => 0xE0A1 | (*x as u16) << 8,
+        _ => return Err(format!("invalid operands for {}", mnemonic)),
+    };
+    Ok(opcode)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    right_to_left: bool,
+    address: usize,
+    symbols: &'a Symbols,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Token<'a> {
+    Number(i64),
+    Symbol(&'a str),
+    Operator(&'a str),
+}
+
+/// Consumes the characters matching `f` and returns the byte index after them.
+fn skip_while(chars: &mut Peekable<CharIndices>, len: usize, f: fn(char) -> bool) -> usize {
+    while let Some(&(i, c)) = chars.peek() {
+        if !f(c) {
+            return i;
+        }
+        chars.next();
+    }
+    len
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let token = if c == '#' || c == '$' || c.is_ascii_digit() {
+            let end = skip_while(&mut chars, expr.len(), |c| c.is_ascii_alphanumeric() || c == '.');
+            Token::Number(parse_number(&expr[start..end])?)
+        } else if c == '\'' {
+            let len = expr[start + 1..].find('\'').ok_or("unterminated character constant")?;
+            let text = &expr[start + 1..start + 1 + len];
+            while chars.next().is_some_and(|(i, _)| i < start + 1 + len) {}
+            let mut text_chars = text.chars();
+            match (text_chars.next(), text_chars.next()) {
+                (Some(c), None) => Token::Number(c as i64),
+                _ => return Err(format!("invalid character constant: '{}'", text)),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = skip_while(&mut chars, expr.len(), |c| c.is_ascii_alphanumeric() || c == '_');
+            Token::Symbol(&expr[start..end])
+        } else {
+            let mut end = start + c.len_utf8();
+            if expr[start..].starts_with("<<") || expr[start..].starts_with(">>") {
+                chars.next();
+                end += 1;
+            }
+            match &expr[start..end] {
+                op @ "+" | op @ "-" | op @ "*" | op @ "/" | op @ "%" | op @ "&" | op @ "|"
+                    | op @ "^" | op @ "~" | op @ "!" | op @ "<" | op @ ">" | op @ "<<"
+                    | op @ ">>" | op @ "\\" | op @ "(" | op @ ")" | op @ "?" | op @ "."
+                    => Token::Operator(op),
+                op => return Err(format!("unexpected character: {}", op)),
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid number: {}", text);
+    if let Some(hex) = text.strip_prefix('#') {
+        i64::from_str_radix(hex, 16).map_err(|_| invalid())
+    } else if let Some(binary) = text.strip_prefix('$') {
+        i64::from_str_radix(&binary.replace('.', "0"), 2).map_err(|_| invalid())
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).map_err(|_| invalid())
+    } else {
+        text.parse().map_err(|_| invalid())
+    }
+}
+
+fn evaluate(expr: &str, address: usize, symbols: &Symbols, dialect: Dialect) -> Result<i64, String> {
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        return Err("missing value".to_string());
+    }
+    let right_to_left = dialect == Dialect::Robson;
+    let mut parser = Parser { tokens, pos: 0, right_to_left, address, symbols };
+    let value = parser.binary(0)?;
+    if parser.pos != parser.tokens.len() {
+        return Err(format!("unexpected token in expression: {}", expr));
+    }
+    Ok(value)
+}
+
+// CHIPPER's `\` divides with the lowest precedence, so `END - START \ 4` counts entries.
+const PRECEDENCE: &[&[&str]] = &[
+    &["\\"], &["|"], &["^"], &["&"], &["<", ">", "<<", ">>"], &["+", "-"], &["*", "/", "%"]
+];
+
+fn overflow() -> String {
+    "expression overflows".to_string()
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Operator(op)) = self.peek() {
+            if !PRECEDENCE[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(if self.right_to_left { level } else { level + 1 })?;
+            left = match op {
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<" | "<<" => left << (right & 63),
+                ">" | ">>" => left >> (right & 63),
+                "+" => left.checked_add(right).ok_or_else(overflow)?,
+                "-" => left.checked_sub(right).ok_or_else(overflow)?,
+                "*" => left.checked_mul(right).ok_or_else(overflow)?,
+                _ if right == 0 => return Err("division by zero".to_string()),
+                "/" | "\\" => left.checked_div(right).ok_or_else(overflow)?,
+                _ => left.checked_rem(right).ok_or_else(overflow)?,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        let token = self.peek().ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(n),
+            Token::Symbol(name) => self.symbols.get(name)
+                .ok_or_else(|| format!("undefined symbol: {}", name)),
+            Token::Operator("?") | Token::Operator(".") => Ok(self.address as i64),
+            Token::Operator("-") => self.unary()?.checked_neg().ok_or_else(overflow),
+            Token::Operator("+") => self.unary(),
+            Token::Operator("~") | Token::Operator("!") => self.unary().map(|v| !v),
+            Token::Operator("(") => {
+                let value = self.binary(0)?;
+                match self.peek() {
+                    Some(Token::Operator(")")) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing )".to_string()),
+                }
+            }
+            Token::Operator(op) => Err(format!("unexpected operator: {}", op)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use disasm;
+
+    fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+        Assembler::new().assemble(source)
+    }
+
+    #[test]
+    fn instructions() {
+        let source = "\
+    CLS
+    LD V1, #2A
+    LD I, 500
+    DRW V1, V2, 5
+    LD [I], VF
+    SHR V3
+    JP V0, #123
+    SCD 4
+";
+        assert_eq!(Ok(vec![0x00, 0xE0, 0x61, 0x2A, 0xA1, 0xF4, 0xD1, 0x25, 0xFF, 0x55,
+                           0x83, 0x06, 0xB1, 0x23, 0x00, 0xC4]), assemble(source));
+    }
+
+    #[test]
+    fn labels_and_equ() {
+        let source = "\
+SIZE    EQU 2 * 3
+START:  JP DONE
+        DB SIZE, -1
+DONE    LD V0, START & #FF
+";
+        assert_eq!(Ok(vec![0x12, 0x04, 0x06, 0xFF, 0x60, 0x00]), assemble(source));
+    }
+
+    #[test]
+    fn align_pads_instructions() {
+        assert_eq!(Ok(vec![0xFF, 0x00, 0x12, 0x02]), assemble("DB #FF\nLOOP:\nJP LOOP"));
+        assert_eq!(Ok(vec![0xFF, 0x12, 0x01]), assemble("ALIGN OFF\nDB #FF\nLOOP:\nJP LOOP"));
+    }
+
+    #[test]
+    fn data_directives() {
+        assert_eq!(Ok(vec![0x12, 0x34, 0x80, b'I', b'\'', b's']),
+                   assemble("DW #1234\nDB $1.......\nDA 'I''s'"));
+    }
+
+    #[test]
+    fn expressions() {
+        let symbols = Symbols { values: HashMap::new() };
+        let eval = |expr| evaluate(expr, 0x200, &symbols, Dialect::Chipper);
+        assert_eq!(Ok(39), eval("2 < 4 | 1 < 2 | 3"));
+        assert_eq!(Ok(2), eval("10 - 2 \\ 4"));
+        assert_eq!(Ok(0x202), eval("? + 2"));
+        assert_eq!(Ok(-6), eval("-(1 + 2) * 2"));
+        assert_eq!(Ok(32), evaluate("31 - 0 - 1", 0x200, &symbols, Dialect::Robson));
+        assert!(eval("FOO").is_err());
+    }
+
+    #[test]
+    fn conditionals() {
+        let source = "IFDEF SUPER\nHIGH\nELSE\nLOW\nENDIF";
+        assert_eq!(Ok(vec![0x00, 0xFE]), assemble(source));
+        assert_eq!(Ok(vec![0x00, 0xFF]), Assembler::new().define("super").assemble(source));
+    }
+
+    #[test]
+    fn robson_mnemonics() {
+        let source = "loop: mov r0,5\nskeq v0,5\njmp .-2\nldr v0-v2\nhalt";
+        assert_eq!(Ok(vec![0x60, 0x05, 0x30, 0x05, 0x12, 0x02, 0xF2, 0x65, 0x00, 0xFD]),
+                   Assembler::new().dialect(Dialect::Robson).assemble(source));
+        assert_eq!(Dialect::Robson, Dialect::detect(source));
+        assert_eq!(Ok(vec![0x60, 0x05]), assemble("mov v0, 5"));
+        assert!(Assembler::new().dialect(Dialect::Chipper).assemble("mov v0, 5").is_err());
+        assert_eq!(Dialect::Chipper, Dialect::detect("; mov v0, 5\nLD V0, 5"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err(AsmError { line: 2, message: "unknown instruction: FOO".to_string() }),
+                   assemble("CLS\n  FOO\n"));
+        assert_eq!(Err(AsmError { line: 1, message: "value 256 does not fit in 8 bits".to_string() }),
+                   assemble("LD V0, 256"));
+        assert_eq!(Err(AsmError { line: 2, message: "symbol A is already defined".to_string() }),
+                   assemble("A:\nA: CLS"));
+        assert!(assemble("JP NOWHERE").is_err());
+        assert_eq!(Err(AsmError { line: 1, message: "unexpected character: é".to_string() }),
+                   assemble("LD V0, é"));
+        assert_eq!(Ok(vec![0xE9]), assemble("DB 'é' & #FF"));
+        assert_eq!(Err(AsmError { line: 1, message: "expression overflows".to_string() }),
+                   assemble("DB 9999999999 * 9999999999"));
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        let roms: &[&[u8]] = &[
+            include_bytes!("../roms/BC_test.ch8"),
+            include_bytes!("../roms/invaders.rom"),
+            include_bytes!("../roms/blinky.rom"),
+        ];
+        for rom in roms {
+            let listing = disasm::disassemble(rom, disasm::Syntax::Chipper);
+            assert_eq!(*rom, assemble(&listing).unwrap().as_slice());
+        }
+    }
+
+    #[test]
+    fn bundled_sources() {
+        let sources: &[(&str, &[u8], Dialect)] = &[
+            (include_str!("../roms/sources/15PUZZLE.SRC"), include_bytes!("../roms/15puzzle.rom"), Dialect::Chipper),
+            (include_str!("../roms/sources/BLINKY.SRC"), include_bytes!("../roms/blinky.rom"), Dialect::Chipper),
+            (include_str!("../roms/sources/BREAKOUT.SRC"), include_bytes!("../roms/breakout.rom"), Dialect::Chipper),
+            (include_str!("../roms/sources/BRIX.SRC"), include_bytes!("../roms/brix.rom"), Dialect::Chipper),
+            (include_str!("../roms/sources/MAZE.SRC"), include_bytes!("../roms/maze.rom"), Dialect::Chipper),
+            (include_str!("../roms/sources/PONG.SRC"), include_bytes!("../roms/pong.rom"), Dialect::Chipper),
+            (include_str!("../roms/sources/PONG2.SRC"), include_bytes!("../roms/pong2.rom"), Dialect::Chipper),
+            (include_str!("../roms/sources/SYZYGY.SRC"), include_bytes!("../roms/syzygy.rom"), Dialect::Chipper),
+            (include_str!("../roms/sources/UFO.SRC"), include_bytes!("../roms/ufo.rom"), Dialect::Chipper),
+            (include_str!("../roms/sources/VBRIX.SRC"), include_bytes!("../roms/vbrix.rom"), Dialect::Robson),
+        ];
+        for &(source, rom, dialect) in sources {
+            assert_eq!(dialect, Dialect::detect(source));
+            assert_eq!(rom, Assembler::new().assemble(source).unwrap().as_slice());
+        }
+    }
+}
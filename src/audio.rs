@@ -0,0 +1,97 @@
+use std::f32::consts::PI;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" | "saw" => Some(Waveform::Sawtooth),
+            _ => None,
+        }
+    }
+
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (phase * 2.0 * PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tone {
+    waveform: Waveform,
+    frequency: f32,
+    volume: f32,
+    phase: f32,
+}
+
+impl Tone {
+    pub fn new(waveform: Waveform, frequency: f32, volume: f32) -> Tone {
+        Tone { waveform, frequency, volume: volume.clamp(0.0, 1.0), phase: 0.0 }
+    }
+
+    pub fn fill(&mut self, out: &mut [f32], sample_rate: u32) {
+        let step = self.frequency / sample_rate as f32;
+        for sample in out.iter_mut() {
+            *sample = self.waveform.sample(self.phase) * self.volume;
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone::new(Waveform::Square, 440.0, 0.25)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_name() {
+        assert_eq!(Some(Waveform::Sine), Waveform::from_name("Sine"));
+        assert_eq!(Some(Waveform::Sawtooth), Waveform::from_name("saw"));
+        assert_eq!(None, Waveform::from_name("noise"));
+    }
+
+    #[test]
+    fn square_wave() {
+        let mut tone = Tone::new(Waveform::Square, 1.0, 0.5);
+        let mut out = [0.0; 4];
+        tone.fill(&mut out, 4);
+        assert_eq!([0.5, 0.5, -0.5, -0.5], out);
+    }
+
+    #[test]
+    fn volume_is_clamped() {
+        let mut tone = Tone::new(Waveform::Sawtooth, 100.0, 3.0);
+        let mut out = [0.0; 64];
+        tone.fill(&mut out, 44100);
+        assert!(out.iter().all(|s| s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn phase_continues() {
+        let mut tone = Tone::new(Waveform::Triangle, 1.0, 1.0);
+        let mut first = [0.0; 2];
+        let mut second = [0.0; 2];
+        tone.fill(&mut first, 4);
+        tone.fill(&mut second, 4);
+        assert_eq!([-1.0, 0.0], first);
+        assert_eq!([1.0, 0.0], second);
+    }
+}
@@ -0,0 +1,325 @@
+use audio::Tone;
+use image::Palette;
+use scheduler::FRAME_RATE;
+
+use std::{
+    collections::HashMap,
+    io::{self, Write}
+};
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAME_RATE) as usize;
+const MIN_CODE_SIZE: u8 = 2;
+const MAX_CODE: u16 = 4096;
+const MAX_DELAY: u64 = 0xFFFF;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CaptureFormat {
+    Gif,
+    /// Numbered PNG files and a WAV of the buzzer.
+    Frames,
+}
+
+impl CaptureFormat {
+    pub fn from_name(name: &str) -> Option<CaptureFormat> {
+        match name {
+            "gif" => Some(CaptureFormat::Gif),
+            "frames" => Some(CaptureFormat::Frames),
+            _ => None,
+        }
+    }
+}
+
+/// Time of a frame boundary in hundredths of a second, the unit of GIF delays.
+fn centiseconds(frame: u64) -> u64 {
+    (frame * 100 + FRAME_RATE as u64 / 2) / FRAME_RATE as u64
+}
+
+/// Writes a looping animated GIF with one image per emulated frame.
+///
+/// GIF delays are counted in hundredths of a second, so frames last 1 or 2 of them in turn
+/// and the total matches 60 fps exactly. Runs of identical frames become a single image.
+pub struct GifEncoder<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    pending: Option<(Vec<u8>, u64)>,
+    frame: u64,
+}
+
+impl<W: Write> GifEncoder<W> {
+    pub fn new(mut writer: W, width: usize, height: usize, palette: &Palette) -> io::Result<GifEncoder<W>> {
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&(width as u16).to_le_bytes())?;
+        writer.write_all(&(height as u16).to_le_bytes())?;
+        // A global color table of 2^(1 + 1) entries.
+        writer.write_all(&[0x80 | 0x01, 0, 0])?;
+        for color in palette {
+            writer.write_all(color)?;
+        }
+        writer.write_all(&[0x21, 0xFF, 0x0B])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+        Ok(GifEncoder { writer, width, height, pending: None, frame: 0 })
+    }
+
+    /// Adds a frame of palette indices, `width` by `height`.
+    pub fn add_frame(&mut self, pixels: Vec<u8>) -> io::Result<()> {
+        assert_eq!(self.width * self.height, pixels.len());
+        let repeat = match self.pending {
+            Some((ref previous, start)) =>
+                *previous == pixels && centiseconds(self.frame + 1) - centiseconds(start) <= MAX_DELAY,
+            None => false,
+        };
+        if !repeat {
+            self.flush()?;
+            self.pending = Some((pixels, self.frame));
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        self.writer.write_all(&[0x3B])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let (pixels, start) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let delay = (centiseconds(self.frame) - centiseconds(start)) as u16;
+        self.writer.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.writer.write_all(&delay.to_le_bytes())?;
+        self.writer.write_all(&[0x00, 0x00])?;
+        self.writer.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.writer.write_all(&(self.width as u16).to_le_bytes())?;
+        self.writer.write_all(&(self.height as u16).to_le_bytes())?;
+        self.writer.write_all(&[0x00, MIN_CODE_SIZE])?;
+        for block in lzw(&pixels).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0x00])
+    }
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.current |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.current as u8);
+            self.current >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.current as u8);
+        }
+        self.bytes
+    }
+}
+
+/// GIF flavored LZW: variable code sizes up to 12 bits, a clear code when the table is full.
+fn lzw(pixels: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << MIN_CODE_SIZE;
+    let end = clear + 1;
+    let mut out = BitWriter { bytes: Vec::new(), current: 0, bits: 0 };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = MIN_CODE_SIZE + 1;
+    let mut next = end + 1;
+    out.write(clear, size);
+
+    let mut pixels = pixels.iter();
+    let mut prefix = match pixels.next() {
+        Some(&pixel) => pixel as u16,
+        None => {
+            out.write(end, size);
+            return out.finish();
+        }
+    };
+    for &pixel in pixels {
+        if let Some(&code) = table.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+        out.write(prefix, size);
+        if next < MAX_CODE {
+            if next == 1 << size {
+                size += 1;
+            }
+            table.insert((prefix, pixel), next);
+            next += 1;
+        } else {
+            out.write(clear, size);
+            table.clear();
+            size = MIN_CODE_SIZE + 1;
+            next = end + 1;
+        }
+        prefix = pixel as u16;
+    }
+    out.write(prefix, size);
+    if next == 1 << size && size < 12 {
+        size += 1;
+    }
+    out.write(end, size);
+    out.finish()
+}
+
+/// Renders the buzzer of every emulated frame to 16-bit mono PCM.
+pub struct WavRecorder {
+    tone: Tone,
+    samples: Vec<i16>,
+}
+
+impl WavRecorder {
+    pub fn new(tone: Tone) -> WavRecorder {
+        WavRecorder { tone, samples: Vec::new() }
+    }
+
+    pub fn add_frame(&mut self, sound: bool) {
+        let mut buffer = [0.0; SAMPLES_PER_FRAME];
+        if sound {
+            self.tone.fill(&mut buffer, SAMPLE_RATE);
+        }
+        self.samples.extend(buffer.iter().map(|&s| (s * i16::MAX as f32) as i16));
+    }
+
+    pub fn finish<W: Write>(self, mut writer: W) -> io::Result<W> {
+        let data_len = self.samples.len() as u32 * 2;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(36 + data_len).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&data_len.to_le_bytes())?;
+        for sample in self.samples {
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use audio::Waveform;
+    use image::DEFAULT_PALETTE;
+
+    /// Decodes the output of `lzw`, as a GIF viewer would.
+    fn unlzw(data: &[u8]) -> Vec<u8> {
+        let clear = 1u16 << MIN_CODE_SIZE;
+        let end = clear + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..clear).map(|c| vec![c as u8]).collect();
+            table.push(vec![]);
+            table.push(vec![]);
+        };
+        reset(&mut table);
+        let (mut size, mut bit, mut previous): (u8, usize, Option<u16>) = (MIN_CODE_SIZE + 1, 0, None);
+        let mut out = Vec::new();
+        loop {
+            let mut code = 0u16;
+            for n in 0..size as usize {
+                let b = bit + n;
+                code |= (((data[b / 8] >> (b % 8)) & 1) as u16) << n;
+            }
+            bit += size as usize;
+            if code == clear {
+                reset(&mut table);
+                size = MIN_CODE_SIZE + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match (table.get(code as usize), previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(p)) => {
+                    let mut entry = table[p as usize].clone();
+                    entry.push(entry[0]);
+                    entry
+                }
+                (None, None) => panic!("bad code"),
+            };
+            if let Some(p) = previous {
+                let mut added = table[p as usize].clone();
+                added.push(entry[0]);
+                table.push(added);
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            out.extend(&entry);
+            previous = Some(code);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        let mut seed = 7u32;
+        let noise: Vec<u8> = (0..20_000).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8 & 3
+        }).collect();
+        for pixels in [vec![], vec![1], vec![0; 10_000], noise] {
+            assert_eq!(pixels, unlzw(&lzw(&pixels)));
+        }
+    }
+
+    #[test]
+    fn gif_timing() {
+        assert_eq!([0, 2, 3, 5, 7, 8, 10], [0, 1, 2, 3, 4, 5, 6].map(centiseconds));
+
+        let mut gif = GifEncoder::new(Vec::new(), 2, 1, &DEFAULT_PALETTE).unwrap();
+        for pixels in [[0, 1], [0, 1], [1, 1], [1, 0]] {
+            gif.add_frame(pixels.to_vec()).unwrap();
+        }
+        let bytes = gif.finish().unwrap();
+        assert_eq!(b"GIF89a", &bytes[..6]);
+        assert_eq!(0x3B, *bytes.last().unwrap());
+        let delays: Vec<u16> = bytes.windows(4)
+            .enumerate()
+            .filter(|(_, w)| *w == [0x21, 0xF9, 0x04, 0x00])
+            .map(|(i, _)| u16::from_le_bytes([bytes[i + 4], bytes[i + 5]]))
+            .collect();
+        assert_eq!(vec![3, 2, 2], delays);
+    }
+
+    #[test]
+    fn wav() {
+        let mut wav = WavRecorder::new(Tone::new(Waveform::Square, 441.0, 0.5));
+        wav.add_frame(false);
+        wav.add_frame(true);
+        let bytes = wav.finish(Vec::new()).unwrap();
+        assert_eq!(44 + 2 * 2 * SAMPLES_PER_FRAME, bytes.len());
+        assert_eq!(b"RIFF", &bytes[..4]);
+        assert_eq!(b"WAVEfmt ", &bytes[8..16]);
+        assert_eq!(SAMPLE_RATE, u32::from_le_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]));
+        assert!(bytes[44..44 + 2 * SAMPLES_PER_FRAME].iter().all(|&b| b == 0));
+        assert!(bytes[44 + 2 * SAMPLES_PER_FRAME..].iter().any(|&b| b != 0));
+    }
+}
@@ -0,0 +1,194 @@
+use chip8::{
+    analysis,
+    asm::{Assembler, Dialect},
+    disasm::{self, Syntax},
+    headless::InputScript,
+    lockstep::{self, Lockstep, Outcome},
+    octo,
+    trace, Cpu, KeyPad, Quirks, RandomSource, Speed
+};
+
+use std::{
+    fs::{self, File},
+    path::Path
+};
+
+use {parse_args, parse_nonzero, parse_number, read_rom, start, USAGE};
+
+pub type Command = fn(&mut dyn Iterator<Item = String>) -> Result<(), String>;
+
+pub fn find(name: &str) -> Option<Command> {
+    match name {
+        "analyze" => Some(analyze),
+        "asm" => Some(asm),
+        "diff" => Some(diff),
+        "disasm" => Some(disasm),
+        "octo" => Some(octo),
+        "run" => Some(run),
+        _ => None,
+    }
+}
+
+/// The same as running without a subcommand, `chip8_emulator run --headless ...` reads better.
+pub fn run(args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+    let options = parse_args(args).map_err(|e| format!("{}\n{}", e, USAGE))?;
+    start(&options)
+}
+
+pub fn disasm(args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+    let mut syntax = Syntax::Chipper;
+    let mut rom_name = None;
+    for arg in args {
+        match arg.as_str() {
+            "--octo" => syntax = Syntax::Octo,
+            _ if rom_name.is_none() => rom_name = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    let rom = read_rom(&rom_name.ok_or("missing ROM file")?)?;
+    print!("{}", disasm::disassemble(&rom, syntax));
+    Ok(())
+}
+
+pub fn analyze(args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+    let mut json = false;
+    let mut rom_name = None;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--dot" => json = false,
+            _ if rom_name.is_none() => rom_name = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    let analysis = analysis::analyze(&read_rom(&rom_name.ok_or("missing ROM file")?)?);
+    if json {
+        print!("{}", analysis.to_json());
+    } else {
+        print!("{}", analysis.to_dot());
+    }
+    for issue in &analysis.issues {
+        eprintln!("{}", issue);
+    }
+    Ok(())
+}
+
+pub fn asm(args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+    let mut assembler = Assembler::new();
+    let mut source_name = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(args.next().ok_or("-o expects a file name")?),
+            "--dialect" => {
+                let name = args.next().ok_or("--dialect expects chipper or robson")?;
+                let dialect = Dialect::from_name(&name)
+                    .ok_or_else(|| format!("unknown dialect: {}", name))?;
+                assembler = assembler.dialect(dialect);
+            }
+            "-D" | "--define" => assembler = assembler.define(&args.next().ok_or("-D expects a symbol")?),
+            _ if source_name.is_none() => source_name = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    let source_name = source_name.ok_or("missing source file")?;
+    let source = fs::read(&source_name).map_err(|e| format!("{}: {}", source_name, e))?;
+    let rom = assembler.assemble(&String::from_utf8_lossy(&source))
+        .map_err(|e| format!("{}: {}", source_name, e))?;
+    let output = output.unwrap_or_else(|| output_name(&source_name, "rom"));
+    fs::write(&output, rom).map_err(|e| format!("{}: {}", output, e))
+}
+
+pub fn octo(args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+    let mut source_name = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(args.next().ok_or("-o expects a file name")?),
+            _ if source_name.is_none() => source_name = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    let source_name = source_name.ok_or("missing source file")?;
+    // Compiled whatever the extension, read_rom would pass anything but .8o through.
+    let source = fs::read(&source_name).map_err(|e| format!("{}: {}", source_name, e))?;
+    let rom = octo::compile(&String::from_utf8_lossy(&source))
+        .map_err(|e| format!("{}: {}", source_name, e))?;
+    let output = output.unwrap_or_else(|| output_name(&source_name, "ch8"));
+    fs::write(&output, rom).map_err(|e| format!("{}: {}", output, e))
+}
+
+/// Runs the ROM with two quirk sets, or against a binary trace, until they disagree.
+pub fn diff(args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+    let quirks = |name: Option<String>| {
+        let name = name.ok_or("expected a quirks preset name")?;
+        Quirks::from_name(&name).map(|q| (name.clone(), q))
+            .ok_or_else(|| format!("unknown quirks preset: {}", name))
+    };
+    let mut left = ("default".to_string(), Quirks::default());
+    let mut right = None;
+    let mut trace_name = None;
+    let mut frames = 600;
+    let mut speed = Speed::default();
+    let mut seed = 0;
+    let mut input = InputScript::default();
+    let mut rom_name = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--left" => left = quirks(args.next())?,
+            "--right" => right = Some(quirks(args.next())?),
+            "--trace" => trace_name = Some(args.next().ok_or("--trace expects a file name")?),
+            "--frames" => frames = parse_number(&arg, args.next())?,
+            "--ipf" => speed = Speed::InstructionsPerFrame(parse_nonzero(&arg, args.next())?),
+            "--seed" => seed = parse_number(&arg, args.next())?,
+            "--input" => {
+                let path = args.next().ok_or("--input expects a file name")?;
+                let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+                input = InputScript::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+            }
+            _ if rom_name.is_none() => rom_name = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    let rom = read_rom(&rom_name.ok_or("missing ROM file")?)?;
+    let cpu = |quirks| -> Result<Cpu, String> {
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.set_random_source(RandomSource::new(seed));
+        cpu.load_program(&rom)?;
+        Ok(cpu)
+    };
+
+    if let Some(path) = trace_name {
+        if right.is_some() {
+            return Err("--right and --trace are exclusive".to_string());
+        }
+        let entries = File::open(&path).and_then(trace::read_binary)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        let cycles = lockstep::compare_trace(&mut cpu(left.1)?, &left.0, &entries, &input)
+            .map_err(|divergence| divergence.to_string().trim_end().to_string())?;
+        println!("no divergence in {} cycles", cycles);
+        return Ok(());
+    }
+
+    let right = right.ok_or("expected --right or --trace")?;
+    let mut lockstep = Lockstep::new((&left.0, cpu(left.1)?), (&right.0, cpu(right.1)?));
+    let mut keypad = KeyPad::new();
+    for frame in 0..frames {
+        input.apply(frame, &mut keypad);
+        match lockstep.run_frame(&keypad, speed.cycles_in_frame(frame)) {
+            Outcome::Running => (),
+            Outcome::Stopped(fault) => {
+                let reason = fault.map_or_else(|| "exited".to_string(), |e| format!("faulted: {}", e));
+                println!("both machines {} after {} cycles", reason, lockstep.cycle());
+                return Ok(());
+            }
+            Outcome::Diverged(divergence) => return Err(divergence.to_string().trim_end().to_string()),
+        }
+    }
+    println!("no divergence in {} cycles", lockstep.cycle());
+    Ok(())
+}
+
+fn output_name(source_name: &str, extension: &str) -> String {
+    Path::new(source_name).with_extension(extension).to_string_lossy().into_owned()
+}
@@ -1,10 +1,22 @@
-use super::{opcodes::*, screen::Screen, keypad::KeyPad};
-
-use std::u16;
+use super::{
+    opcodes::*,
+    screen::Screen,
+    keypad::KeyPad,
+    quirks::Quirks,
+    random::RandomSource,
+    state::{self, StateError, StateHeader, StateReader, StateWriter}
+};
 
 use rand::prelude::*;
 
-const MEM_SIZE: usize = 4096;
+use std::{
+    error::Error,
+    fmt
+};
+
+/// Memory with the XO-CHIP quirk, other machines only address the first `CHIP8_MEM_SIZE` bytes.
+pub const MEM_SIZE: usize = 65536;
+pub const CHIP8_MEM_SIZE: usize = 4096;
 
 const DIGIT_SPRITES: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -25,6 +37,52 @@ const DIGIT_SPRITES: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+const BIG_DIGIT_SPRITES_ADDR: usize = 80;
+
+const BIG_DIGIT_SPRITES: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CpuError {
+    InvalidOpcode { pc: usize, opcode: u16 },
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds { addr: usize },
+    InvalidDigit { digit: u8 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::InvalidOpcode { pc, opcode } =>
+                write!(f, "invalid opcode {:04X} at {:#05X}", opcode, pc),
+            CpuError::StackOverflow => write!(f, "stack overflow"),
+            CpuError::StackUnderflow => write!(f, "return with an empty stack"),
+            CpuError::MemoryOutOfBounds { addr } =>
+                write!(f, "memory access out of bounds at {:#X}", addr),
+            CpuError::InvalidDigit { digit } => write!(f, "no font sprite for {:#X}", digit),
+        }
+    }
+}
+
+impl Error for CpuError {}
+
 pub struct Cpu {
     v: [u8; 16],
     pub i: u16,
@@ -35,10 +93,29 @@ pub struct Cpu {
     sp: usize,
     memory: [u8; MEM_SIZE],
     pub screen: Screen,
+    quirks: Quirks,
+    rpl_flags: [u8; 16],
+    exited: bool,
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    rom_hash: u64,
+    rng: RandomSource,
+    /// Set when a frame starts, cleared by DXYN under the display wait quirk.
+    vblank: bool,
+}
+
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
 }
 
 impl Cpu {
     pub fn new() -> Cpu {
+        Cpu::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Cpu {
         let mut cpu = Cpu {
             v: [0; 16],
             i: 0x200,
@@ -49,50 +126,232 @@ impl Cpu {
             sp: 0,
             memory: [0; MEM_SIZE],
             screen: Screen::new(),
+            quirks,
+            rpl_flags: [0; 16],
+            exited: false,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            rom_hash: state::rom_hash(&[]),
+            rng: RandomSource::new(random()),
+            vblank: false,
         };
 
         cpu.memory[0..80].copy_from_slice(&DIGIT_SPRITES);
+        cpu.memory[BIG_DIGIT_SPRITES_ADDR..BIG_DIGIT_SPRITES_ADDR + 160]
+            .copy_from_slice(&BIG_DIGIT_SPRITES);
 
         cpu
     }
 
-    pub fn cycle(&mut self, keypad: &KeyPad) {
-        //eprintln!("self.delay_timer = {:#?}", self.delay_timer);
-        let op = self.fetch_opcode();
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_random_source(&mut self, rng: RandomSource) {
+        self.rng = rng;
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[1..=self.sp]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory[..self.memory_size()]
+    }
+
+    pub fn memory_size(&self) -> usize {
+        if self.quirks.xo_chip { MEM_SIZE } else { CHIP8_MEM_SIZE }
+    }
+
+    pub fn write_memory(&mut self, addr: usize, data: &[u8]) -> Result<(), CpuError> {
+        self.check_memory_range(addr, data.len())?;
+        self.memory[addr..addr + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn cycle(&mut self, keypad: &KeyPad) -> Result<Op, CpuError> {
+        let op = self.fetch_opcode()?;
         self.pc += 2;
-        self.compute_op(op, keypad);
+        self.compute_op(op, keypad)?;
+        // Running off the end of memory is reported by the instruction that did it.
+        self.check_memory_range(self.pc, 2)?;
+        Ok(op)
     }
 
-    fn fetch_opcode(&self) -> Op {
-        let addr = self.pc as usize;
-        let data = (self.memory[addr] as u16) << 8 | (self.memory[addr + 1]) as u16;
-        decode(data)
+    pub fn run_frame(&mut self, keypad: &KeyPad, cycles: usize) -> Result<(), CpuError> {
+        for _ in 0..cycles {
+            if self.exited {
+                break;
+            }
+            self.cycle(keypad)?;
+        }
+        self.update_timers();
+        Ok(())
+    }
+
+    pub fn opcode(&self) -> Result<u16, CpuError> {
+        let addr = self.pc;
+        self.check_memory_range(addr, 2)?;
+        Ok((self.memory[addr] as u16) << 8 | (self.memory[addr + 1]) as u16)
+    }
+
+    pub fn fetch_opcode(&self) -> Result<Op, CpuError> {
+        let data = self.opcode()?;
+        decode(data).ok_or(CpuError::InvalidOpcode { pc: self.pc, opcode: data })
     }
 
     pub fn load_program(&mut self, program: &[u8]) -> Result<(), String> {
-        if program.len() > MEM_SIZE - 0x200 {
+        if program.len() > self.memory_size() - 0x200 {
             return Err("Program is too big !".to_string());
         }
         self.memory[0x200..].iter_mut().zip(program)
             .for_each(|(dst, src)| *dst = *src);
+        self.rom_hash = state::rom_hash(program);
+        Ok(())
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        StateHeader::new(self.rom_hash, &self.screen).write(&mut w);
+        w.bytes(&self.v);
+        w.u16(self.i);
+        w.u8(self.sound_timer);
+        w.u8(self.delay_timer);
+        self.stack.iter().for_each(|addr| w.u16(*addr));
+        w.u32(self.pc as u32);
+        w.u8(self.sp as u8);
+        w.u8(self.quirks.to_bits());
+        w.bytes(&self.rpl_flags);
+        w.bool(self.exited);
+        w.bytes(&self.audio_pattern);
+        w.u8(self.pitch);
+        self.rng.save(&mut w);
+        w.bool(self.vblank);
+        self.screen.save(&mut w);
+        w.bytes(&self.memory);
+        w.into_inner()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data);
+        let header = StateHeader::read(&mut r)?;
+        if header.rom_hash != self.rom_hash {
+            return Err(StateError::RomMismatch { expected: self.rom_hash, found: header.rom_hash });
+        }
+
+        let mut v = [0; 16];
+        v.copy_from_slice(r.bytes(16)?);
+        let i = r.u16()?;
+        let sound_timer = r.u8()?;
+        let delay_timer = r.u8()?;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        let pc = r.u32()? as usize;
+        if pc >= MEM_SIZE {
+            return Err(StateError::InvalidField("pc"));
+        }
+        let sp = r.u8()? as usize;
+        if sp >= stack.len() {
+            return Err(StateError::InvalidField("sp"));
+        }
+        let quirks = Quirks::from_bits(r.u8()?).ok_or(StateError::InvalidField("quirks"))?;
+        let mut rpl_flags = [0; 16];
+        rpl_flags.copy_from_slice(r.bytes(16)?);
+        let exited = r.bool("exited")?;
+        let mut audio_pattern = [0; 16];
+        audio_pattern.copy_from_slice(r.bytes(16)?);
+        let pitch = r.u8()?;
+        let rng = RandomSource::load(&mut r)?;
+        let vblank = r.bool("vblank")?;
+        let screen = Screen::load(&mut r)?;
+        let memory = r.bytes(MEM_SIZE)?;
+        if !r.is_empty() {
+            return Err(StateError::InvalidField("trailing data"));
+        }
+
+        self.v = v;
+        self.i = i;
+        self.sound_timer = sound_timer;
+        self.delay_timer = delay_timer;
+        self.stack = stack;
+        self.pc = pc;
+        self.sp = sp;
+        self.quirks = quirks;
+        self.rpl_flags = rpl_flags;
+        self.exited = exited;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.rng = rng;
+        self.vblank = vblank;
+        self.screen = screen;
+        self.memory.copy_from_slice(memory);
         Ok(())
     }
 
     pub fn update_timers(&mut self) {
         self.delay_timer = self.delay_timer.saturating_sub(1);
         self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.rng.tick();
+        self.vblank = true;
     }
 
     fn reg<T: Into<usize>>(&self, register: T) -> u8 {
         self.v[register.into()]
     }
 
-    fn compute_op(&mut self, op: Op, key_pad: &KeyPad) {
+
+    fn check_memory_range(&self, start: usize, len: usize) -> Result<(), CpuError> {
+        let size = self.memory_size();
+        if start + len > size {
+            return Err(CpuError::MemoryOutOfBounds { addr: start.max(size) });
+        }
+        Ok(())
+    }
+
+    fn compute_op(&mut self, op: Op, key_pad: &KeyPad) -> Result<(), CpuError> {
         match op {
             Op::Cls => self.screen.clear(),
-            Op::Ret => self.return_from_subroutine(),
+            Op::Ret => self.return_from_subroutine()?,
             Op::Jp(n) => self.pc = n as usize,
-            Op::Call(n) => self.call(n),
+            Op::Call(n) => self.call(n)?,
             Op::Se(reg, val) => self.skip_equals(reg, val),
             Op::Sne(reg, val) => self.skip_not_equals(reg, val),
             Op::SeReg(r1, r2) => self.skip_reg_equals(r1, r2),
@@ -104,69 +363,97 @@ impl Cpu {
             Op::And(r1, r2) => self.and(r1, r2),
             Op::Xor(r1, r2) => self.xor(r1, r2),
             Op::AddReg(r1, r2) => self.add_reg(r1, r2),
-            Op::Sub(r1, r2) => self.sub(r1, r2),
-            Op::Shr(reg) => self.shr(reg),
-            Op::Subn(r1, r2) => self.sub(r2, r1),
-            Op::Shl(reg) => self.shl(reg),
+            Op::Sub(r1, r2) => self.sub(r1, r1, r2),
+            Op::Shr(x, y) => self.shr(x, y),
+            Op::Subn(r1, r2) => self.sub(r1, r2, r1),
+            Op::Shl(x, y) => self.shl(x, y),
             Op::LdI(val) => self.i = val,
             Op::JpRegI(addr) => self.jp_reg_i(addr),
             Op::Rnd(reg, mask) => self.rnd(reg, mask),
-            Op::Drw(x, y, size) => self.draw(x, y, size),
-            Op::Skp(key) => self.skip_if_pressed(key, key_pad),
-            Op::Sknp(key) => self.skip_if_not_pressed(key, key_pad),
+            Op::Drw(x, y, size) => self.wait_and_draw(x, y, size)?,
+            Op::Skp(reg) => self.skip_if_pressed(reg, key_pad),
+            Op::Sknp(reg) => self.skip_if_not_pressed(reg, key_pad),
             Op::LdDT(reg) => self.v[reg as usize] = self.delay_timer,
-            Op::LdKb(reg) => self.wait_key_press(reg, &key_pad),
+            Op::LdKb(reg) => self.wait_key_press(reg, key_pad),
             Op::SetDT(reg) => self.delay_timer = self.reg(reg),
             Op::SetST(reg) => self.sound_timer = self.reg(reg),
-            Op::AddToI(reg) => self.add_reg_to_i(reg),
-            Op::LdChr(reg) => self.load_chr_sprite_addr(reg),
-            Op::LdBCD(reg) => self.load_bcd(reg),
-            Op::LdRegs(x) => self.load_registers(x),
-            Op::RdMem(x) => self.read_memory(x),
+            Op::AddToI(reg) => self.add_reg_to_i(reg)?,
+            Op::LdChr(reg) => self.load_chr_sprite_addr(reg)?,
+            Op::LdBCD(reg) => self.load_bcd(reg)?,
+            Op::LdRegs(x) => self.load_registers(x)?,
+            Op::RdMem(x) => self.read_memory(x)?,
+            Op::Scd(n) => self.screen.scroll_down(n as usize),
+            Op::Scu(n) => self.screen.scroll_up(n as usize),
+            Op::Scr => self.screen.scroll_right(),
+            Op::Scl => self.screen.scroll_left(),
+            Op::Exit => self.exit(),
+            Op::Low => self.screen.set_hires(false),
+            Op::High => self.screen.set_hires(true),
+            Op::LdHf(reg) => self.load_big_chr_sprite_addr(reg)?,
+            Op::SaveFlags(x) => self.save_flags(x),
+            Op::LoadFlags(x) => self.load_flags(x),
+            Op::SaveRange(x, y) => self.save_range(x, y)?,
+            Op::LoadRange(x, y) => self.load_range(x, y)?,
+            Op::LdILong => self.load_i_long()?,
+            Op::Plane(n) => self.screen.select_planes(n),
+            Op::Audio => self.load_audio_pattern()?,
+            Op::Pitch(reg) => self.pitch = self.reg(reg),
         }
+        Ok(())
     }
 
-    fn return_from_subroutine(&mut self) {
+    fn return_from_subroutine(&mut self) -> Result<(), CpuError> {
         if self.sp == 0 {
-            panic!("Invalid state: sp = 0 !");
+            return Err(CpuError::StackUnderflow);
         }
         self.pc = self.stack[self.sp] as usize;
         self.sp -= 1;
+        Ok(())
     }
 
-    fn call(&mut self, address: u16) {
-        if self.pc > MEM_SIZE as usize {
-            panic!("Invald pc value: {}", self.pc);
+    fn call(&mut self, address: u16) -> Result<(), CpuError> {
+        if self.pc >= self.memory_size() {
+            return Err(CpuError::MemoryOutOfBounds { addr: self.pc });
+        }
+        if address as usize >= self.memory_size() {
+            return Err(CpuError::MemoryOutOfBounds { addr: address as usize });
         }
-        if address >= MEM_SIZE as u16 {
-            panic!("Invalid jump address: {}", address)
+        if self.sp + 1 >= self.stack.len() {
+            return Err(CpuError::StackOverflow);
         }
         self.sp += 1;
         self.stack[self.sp] = self.pc as u16;
         self.pc = address as usize;
+        Ok(())
+    }
+
+    fn skip(&mut self) {
+        let next = (self.memory.get(self.pc), self.memory.get(self.pc + 1));
+        let long = self.quirks.xo_chip && next == (Some(&0xF0), Some(&0x00));
+        self.pc += if long { 4 } else { 2 };
     }
 
     fn skip_equals(&mut self, reg: u8, v2: u8) {
         if self.reg(reg) == v2 {
-            self.pc += 2
+            self.skip()
         }
     }
 
     fn skip_not_equals(&mut self, reg: u8, v2: u8) {
         if self.reg(reg) != v2 {
-            self.pc += 2
+            self.skip()
         }
     }
 
     fn skip_reg_equals(&mut self, r1: u8, r2: u8) {
         if self.reg(r1) == self.reg(r2) {
-            self.pc += 2
+            self.skip()
         }
     }
 
     fn skip_reg_not_equals(&mut self, r1: u8, r2: u8) {
         if self.reg(r1) != self.reg(r2) {
-            self.pc += 2
+            self.skip()
         }
     }
 
@@ -184,14 +471,27 @@ impl Cpu {
 
     fn or(&mut self, r1: u8, r2: u8) {
         self.v[r1 as usize] = self.reg(r1) | self.reg(r2);
+        self.reset_vf_after_logic();
     }
 
     fn and(&mut self, r1: u8, r2: u8) {
         self.v[r1 as usize] = self.reg(r1) & self.reg(r2);
+        self.reset_vf_after_logic();
     }
 
     fn xor(&mut self, r1: u8, r2: u8) {
         self.v[r1 as usize] = self.reg(r1) ^ self.reg(r2);
+        self.reset_vf_after_logic();
+    }
+
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
+    }
+
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy { self.reg(y) } else { self.reg(x) }
     }
 
     fn add_reg(&mut self, r1: u8, r2: u8) {
@@ -200,62 +500,96 @@ impl Cpu {
         self.v[0xF] = (result > 255) as u8;
     }
 
-    fn sub(&mut self, r1: u8, r2: u8) {
-        self.v[0xF] = (self.reg(r1) > self.reg(r2)) as u8;
-        self.v[r1 as usize] = (self.reg(r1) as i8 - self.reg(r2) as i8) as u8;
+    /// `dest = minuend - subtrahend`, VF is 1 when it does not borrow.
+    fn sub(&mut self, dest: u8, minuend: u8, subtrahend: u8) {
+        let (a, b) = (self.reg(minuend), self.reg(subtrahend));
+        self.v[dest as usize] = a.wrapping_sub(b);
+        self.v[0xF] = (a >= b) as u8;
     }
 
-    fn shr(&mut self, register: u8) {
-        self.v[0xF] = self.reg(register) & 1;
-        self.v[register as usize] = self.reg(register) >> 1;
+    fn shr(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
+        self.v[x as usize] = value >> 1;
+        self.v[0xF] = value & 1;
     }
 
-    fn shl(&mut self, register: u8) {
-        self.v[0xF] = if (self.reg(register) & 0b10000000) != 0 { 1 } else { 0 } ;
-        self.v[register as usize] = self.reg(register) << 1;
+    fn shl(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
+        self.v[x as usize] = value << 1;
+        self.v[0xF] = if (value & 0b10000000) != 0 { 1 } else { 0 } ;
     }
 
     fn jp_reg_i(&mut self, addr: u16) {
-        let address = self.v[0] as u16 + addr;
+        let offset_reg = if self.quirks.jump_uses_vx { (addr >> 8) as u8 } else { 0 };
+        let address = self.reg(offset_reg) as u16 + addr;
         self.pc = address as usize;
     }
 
     fn rnd(&mut self, reg: u8, mask: u8) {
-        let random_val = random::<u8>() & mask;
+        let random_val = self.rng.next_byte() & mask;
         self.v[reg as usize] = random_val;
     }
 
-    pub fn draw(&mut self, x: u8, y: u8, size: u8) {
+    fn wait_and_draw(&mut self, x: u8, y: u8, size: u8) -> Result<(), CpuError> {
+        if self.quirks.display_wait {
+            if !self.vblank {
+                self.pc -= 2;
+                return Ok(());
+            }
+            self.vblank = false;
+        }
+        self.draw(x, y, size)
+    }
+
+    pub fn draw(&mut self, x: u8, y: u8, size: u8) -> Result<(), CpuError> {
         let x = self.reg(x);
         let y = self.reg(y);
+        let (width, height) = (self.screen.width(), self.screen.height());
+
+        let (columns, lines) = if size == 0 { (16, 16) } else { (8, size as usize) };
+        let bytes_per_line = columns / 8;
+
+        let planes: Vec<u8> = [1, 2].iter().cloned()
+            .filter(|p| self.screen.selected_planes() & p != 0)
+            .collect();
+        let sprite_len = lines * bytes_per_line;
 
         let address = self.i as usize;
-        let sprite = &self.memory[address.. address + size as usize];
+        self.check_memory_range(address, sprite_len * planes.len())?;
         self.v[0xF] = 0;
 
-        for (line, byte) in sprite.iter().enumerate() {
-            for offset in 0..8 {
-                let screen_x = x as usize + offset as usize;
-                let screen_y = y as usize + line;
-                if screen_x < 64 && screen_y < 32 {
-                    let on = (byte & (128 >> offset)) != 0;
-                    if self.screen.set_pixel_value(screen_x, screen_y, on) {
+        let (x, y) = (x as usize % width, y as usize % height);
+        let wraps = self.quirks.draw_wraps;
+
+        for (n, plane) in planes.into_iter().enumerate() {
+            let sprite = address + n * sprite_len;
+            for line in 0..lines {
+                let row = &self.memory[sprite + line * bytes_per_line..sprite + (line + 1) * bytes_per_line];
+                let bits = row.iter().fold(0u16, |acc, byte| (acc << 8) | *byte as u16);
+                for offset in 0..columns {
+                    let screen_x = x + offset;
+                    let screen_y = y + line;
+                    // Only set bits are XORed, pixels under 0 bits are left alone.
+                    let on = (bits & (1 << (columns - 1 - offset))) != 0;
+                    if on && (wraps || (screen_x < width && screen_y < height))
+                        && self.screen.set_plane_pixel_value(plane, screen_x, screen_y, true) {
                         self.v[0xF] = 1;
                     }
                 }
             }
         }
+        Ok(())
     }
 
-    fn skip_if_pressed(&mut self, key: u8, pad: &KeyPad) {
-        if pad.is_pressed(key) {
-            self.pc += 2;
+    fn skip_if_pressed(&mut self, reg: u8, pad: &KeyPad) {
+        if pad.is_pressed(self.reg(reg)) {
+            self.skip();
         }
     }
 
-    fn skip_if_not_pressed(&mut self, key: u8, pad: &KeyPad) {
-        if !pad.is_pressed(key) {
-            self.pc += 2;
+    fn skip_if_not_pressed(&mut self, reg: u8, pad: &KeyPad) {
+        if !pad.is_pressed(self.reg(reg)) {
+            self.skip();
         }
     }
 
@@ -267,57 +601,133 @@ impl Cpu {
         }
     }
 
-    fn add_reg_to_i(&mut self, reg: u8) {
+    fn add_reg_to_i(&mut self, reg: u8) -> Result<(), CpuError> {
+        let addr = self.i as usize + self.reg(reg) as usize;
         self.i = self.i.checked_add(self.reg(reg) as u16)
-            .expect("Invalid i value !");
+            .ok_or(CpuError::MemoryOutOfBounds { addr })?;
+        Ok(())
     }
 
-    fn load_chr_sprite_addr(&mut self, reg: u8) {
+    fn load_chr_sprite_addr(&mut self, reg: u8) -> Result<(), CpuError> {
         let digit = self.reg(reg);
-        if digit >= 0xF {
-            panic!("No sprite for digit > 0xF");
+        if digit > 0xF {
+            return Err(CpuError::InvalidDigit { digit });
         }
         self.i = (digit * 5) as u16;
+        Ok(())
     }
 
-    fn load_bcd(&mut self, reg: u8) {
+    fn load_big_chr_sprite_addr(&mut self, reg: u8) -> Result<(), CpuError> {
+        let digit = self.reg(reg);
+        if digit > 0xF {
+            return Err(CpuError::InvalidDigit { digit });
+        }
+        self.i = (BIG_DIGIT_SPRITES_ADDR + digit as usize * 10) as u16;
+        Ok(())
+    }
+
+    fn load_bcd(&mut self, reg: u8) -> Result<(), CpuError> {
+        let address = self.i as usize;
+        self.check_memory_range(address, 3)?;
         let mut n = self.reg(reg);
         for i in 0..3 {
-            self.memory[(self.i + (2 - i)) as usize] = n % 10;
-            n = n / 10;
+            self.memory[address + 2 - i] = n % 10;
+            n /= 10;
         }
+        Ok(())
     }
 
-    fn load_registers(&mut self, x: u8) {
-        let x = x as u16;
-        if x > 0xF {
-            panic!("Register {} doesn't exist.", x);
+    fn load_registers(&mut self, x: u8) -> Result<(), CpuError> {
+        let x = x as usize;
+        let address = self.i as usize;
+        self.check_memory_range(address, x + 1)?;
+        for off in 0..=x {
+            self.memory[address + off] = self.v[off];
         }
-        if self.i + x >= MEM_SIZE as u16 {
-            panic!("Cannot write {} bytes from memory address {}.", x + 1, self.i);
+        self.increment_i_after_load_store(x)
+    }
+
+    fn increment_i_after_load_store(&mut self, x: usize) -> Result<(), CpuError> {
+        if self.quirks.load_store_increments_i {
+            let increment = if self.quirks.load_store_increments_i_by_x { x } else { x + 1 };
+            let addr = self.i as usize + increment;
+            self.i = self.i.checked_add(increment as u16)
+                .ok_or(CpuError::MemoryOutOfBounds { addr })?;
         }
+        Ok(())
+    }
+
+    fn read_memory(&mut self, x: u8) -> Result<(), CpuError> {
+        let x = x as usize;
+        let address = self.i as usize;
+        self.check_memory_range(address, x + 1)?;
         for off in 0..=x {
-            self.memory[(self.i + off) as usize] = self.v[off as usize];
+            self.v[off] = self.memory[address + off];
         }
+        self.increment_i_after_load_store(x)
+    }
+
+    fn exit(&mut self) {
+        self.exited = true;
+        self.pc -= 2;
     }
 
-    fn read_memory(&mut self, x: u8) {
+    fn save_flags(&mut self, x: u8) {
         let x = x as usize;
-        if x > 0xF {
-            panic!("Register {} doesn't exist.", x);
+        self.rpl_flags[..=x].copy_from_slice(&self.v[..=x]);
+    }
+
+    fn load_flags(&mut self, x: u8) {
+        let x = x as usize;
+        self.v[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+    }
+
+    fn register_range(x: u8, y: u8) -> Vec<usize> {
+        if x <= y {
+            (x as usize..=y as usize).collect()
+        } else {
+            (y as usize..=x as usize).rev().collect()
         }
-        if self.i as usize + x >= MEM_SIZE {
-            panic!("Cannot read {} bytes from memory address {}.", x + 1, self.i);
+    }
+
+    fn save_range(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        let registers = Cpu::register_range(x, y);
+        let address = self.i as usize;
+        self.check_memory_range(address, registers.len())?;
+        for (off, reg) in registers.into_iter().enumerate() {
+            self.memory[address + off] = self.v[reg];
         }
-        for off in 0..=x {
-            self.v[off] = self.memory[self.i as usize + off];
+        Ok(())
+    }
+
+    fn load_range(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        let registers = Cpu::register_range(x, y);
+        let address = self.i as usize;
+        self.check_memory_range(address, registers.len())?;
+        for (off, reg) in registers.into_iter().enumerate() {
+            self.v[reg] = self.memory[address + off];
         }
+        Ok(())
+    }
+
+    fn load_i_long(&mut self) -> Result<(), CpuError> {
+        let addr = self.pc;
+        self.check_memory_range(addr, 2)?;
+        self.i = (self.memory[addr] as u16) << 8 | self.memory[addr + 1] as u16;
+        self.pc += 2;
+        Ok(())
+    }
+
+    fn load_audio_pattern(&mut self) -> Result<(), CpuError> {
+        let address = self.i as usize;
+        self.check_memory_range(address, 16)?;
+        self.audio_pattern.copy_from_slice(&self.memory[address..address + 16]);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use sdl2::keyboard::Scancode;
     use super::*;
 
     #[test]
@@ -331,13 +741,9 @@ mod test {
         assert_eq!(0x200, cpu.pc);
         assert_eq!(0, cpu.sp);
 
-        for i in 80..MEM_SIZE {
-            if i < 80 {
-                assert_eq!(DIGIT_SPRITES[i], cpu.memory[i])
-            } else {
-                assert_eq!(0, cpu.memory[i]);
-            }
-        }
+        assert_eq!(&DIGIT_SPRITES[..], &cpu.memory[..80]);
+        assert_eq!(&BIG_DIGIT_SPRITES[..], &cpu.memory[80..240]);
+        assert!(cpu.memory[240..].iter().all(|b| *b == 0));
     }
 
     #[test]
@@ -350,6 +756,34 @@ mod test {
         assert_eq!(4, cpu.delay_timer);
     }
 
+    #[test]
+    fn is_sound_active() {
+        let mut cpu = Cpu::new();
+        assert!(!cpu.is_sound_active());
+        cpu.sound_timer = 1;
+        assert!(cpu.is_sound_active());
+        cpu.update_timers();
+        assert!(!cpu.is_sound_active());
+    }
+
+    #[test]
+    fn stack() {
+        let mut cpu = Cpu::new();
+        assert!(cpu.stack().is_empty());
+        cpu.call(0x300).unwrap();
+        cpu.call(0x400).unwrap();
+        assert_eq!(&[0x200, 0x300], cpu.stack());
+    }
+
+    #[test]
+    fn write_memory() {
+        let mut cpu = Cpu::new();
+        cpu.write_memory(0x300, &[1, 2]).unwrap();
+        assert_eq!(&[1, 2], &cpu.memory()[0x300..0x302]);
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: CHIP8_MEM_SIZE }),
+                   cpu.write_memory(CHIP8_MEM_SIZE - 1, &[1, 2]));
+    }
+
     #[test]
     fn update_timers_when_zero() {
         let mut cpu = Cpu::new();
@@ -374,7 +808,7 @@ mod test {
         let program = [0x00, 0xE0, 0x00, 0xEE];
         let mut cpu = Cpu::new();
         cpu.load_program(&program).unwrap();
-        let op = cpu.fetch_opcode();
+        let op = cpu.fetch_opcode().unwrap();
         assert_eq!(Op::Cls, op);
     }
 
@@ -384,24 +818,24 @@ mod test {
         cpu.stack[0] = 5;
         cpu.stack[1] = 6;
         cpu.sp = 1;
-        cpu.compute_op(Op::Ret, &KeyPad::new());
+        cpu.compute_op(Op::Ret, &KeyPad::new()).unwrap();
         assert_eq!(6, cpu.pc);
         assert_eq!(0, cpu.sp);
     }
 
     #[test]
-    #[should_panic]
     fn ret_when_sp_is_0() {
         let mut cpu = Cpu::new();
         cpu.stack[0] = 5;
         cpu.sp = 0;
-        cpu.compute_op(Op::Ret, &KeyPad::new());
+        assert_eq!(Err(CpuError::StackUnderflow),
+                   cpu.compute_op(Op::Ret, &KeyPad::new()));
     }
 
     #[test]
     fn jp() {
         let mut cpu = Cpu::new();
-        cpu.compute_op(Op::Jp(5), &KeyPad::new());
+        cpu.compute_op(Op::Jp(5), &KeyPad::new()).unwrap();
         assert_eq!(5, cpu.pc)
     }
 
@@ -409,33 +843,32 @@ mod test {
     fn call() {
         let mut cpu = Cpu::new();
         cpu.pc = 55;
-        cpu.compute_op(Op::Call(75), &KeyPad::new());
+        cpu.compute_op(Op::Call(75), &KeyPad::new()).unwrap();
         assert_eq!(75, cpu.pc);
         assert_eq!(1, cpu.sp);
         assert_eq!(55, cpu.stack[1]);
     }
 
     #[test]
-    #[should_panic]
     fn call_invalid_pc() {
         let mut cpu = Cpu::new();
-        cpu.pc = 5000;
-        cpu.compute_op(Op::Call(0), &KeyPad::new());
+        cpu.pc = MEM_SIZE + 1;
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: MEM_SIZE + 1 }),
+                   cpu.compute_op(Op::Call(0), &KeyPad::new()));
     }
 
     #[test]
-    #[should_panic]
-    fn call_invalid_address() {
+    fn call_high_address() {
         let mut cpu = Cpu::new();
-        cpu.pc = 0x200;
-        cpu.compute_op(Op::Call(MEM_SIZE as u16), &KeyPad::new());
+        cpu.compute_op(Op::Call(0xFFF), &KeyPad::new()).unwrap();
+        assert_eq!(0xFFF, cpu.pc);
     }
 
     #[test]
     fn se() {
         let mut cpu = Cpu::new();
         cpu.v[1] = 5;
-        cpu.compute_op(Op::Se(1, 5), &KeyPad::new());
+        cpu.compute_op(Op::Se(1, 5), &KeyPad::new()).unwrap();
         assert_eq!(0x202, cpu.pc);
     }
 
@@ -443,7 +876,7 @@ mod test {
     fn se_not_equals() {
         let mut cpu = Cpu::new();
         cpu.v[1] = 5;
-        cpu.compute_op(Op::Se(1, 6), &KeyPad::new());
+        cpu.compute_op(Op::Se(1, 6), &KeyPad::new()).unwrap();
         assert_eq!(0x200, cpu.pc);
     }
 
@@ -451,7 +884,7 @@ mod test {
     fn sne() {
         let mut cpu = Cpu::new();
         cpu.v[1] = 5;
-        cpu.compute_op(Op::Sne(1, 6), &KeyPad::new());
+        cpu.compute_op(Op::Sne(1, 6), &KeyPad::new()).unwrap();
         assert_eq!(0x202, cpu.pc);
     }
 
@@ -459,7 +892,7 @@ mod test {
     fn sne_equals() {
         let mut cpu = Cpu::new();
         cpu.v[1] = 5;
-        cpu.compute_op(Op::Sne(1, 5), &KeyPad::new());
+        cpu.compute_op(Op::Sne(1, 5), &KeyPad::new()).unwrap();
         assert_eq!(0x200, cpu.pc);
     }
 
@@ -468,7 +901,7 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[0] = 5;
         cpu.v[5] = 5;
-        cpu.compute_op(Op::SeReg(0, 5), &KeyPad::new());
+        cpu.compute_op(Op::SeReg(0, 5), &KeyPad::new()).unwrap();
         assert_eq!(0x202, cpu.pc);
     }
 
@@ -477,7 +910,7 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[0] = 5;
         cpu.v[5] = 6;
-        cpu.compute_op(Op::SeReg(0, 5), &KeyPad::new());
+        cpu.compute_op(Op::SeReg(0, 5), &KeyPad::new()).unwrap();
     }
 
     #[test]
@@ -485,7 +918,7 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[0] = 5;
         cpu.v[5] = 6;
-        cpu.compute_op(Op::SneReg(0, 5), &KeyPad::new());
+        cpu.compute_op(Op::SneReg(0, 5), &KeyPad::new()).unwrap();
     }
 
     #[test]
@@ -493,13 +926,13 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[0] = 5;
         cpu.v[5] = 5;
-        cpu.compute_op(Op::SneReg(0, 5), &KeyPad::new());
+        cpu.compute_op(Op::SneReg(0, 5), &KeyPad::new()).unwrap();
     }
 
     #[test]
     fn ld() {
         let mut cpu = Cpu::new();
-        cpu.compute_op(Op::Ld(6, 124), &KeyPad::new());
+        cpu.compute_op(Op::Ld(6, 124), &KeyPad::new()).unwrap();
         assert_eq!(124, cpu.v[6]);
     }
 
@@ -507,7 +940,7 @@ mod test {
     fn add() {
         let mut cpu = Cpu::new();
         cpu.v[9] = 10;
-        cpu.compute_op(Op::Add(9, 10), &KeyPad::new());
+        cpu.compute_op(Op::Add(9, 10), &KeyPad::new()).unwrap();
         assert_eq!(20, cpu.v[9]);
     }
 
@@ -515,7 +948,7 @@ mod test {
     fn load_reg() {
         let mut cpu = Cpu::new();
         cpu.v[5] = 11;
-        cpu.compute_op(Op::LdReg(1, 5), &KeyPad::new());
+        cpu.compute_op(Op::LdReg(1, 5), &KeyPad::new()).unwrap();
         assert_eq!(11, cpu.v[1]);
     }
 
@@ -524,7 +957,7 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[0xA] = 0b10100;
         cpu.v[0xB] = 0b01010;
-        cpu.compute_op(Op::Or(0xA, 0xB), &KeyPad::new());
+        cpu.compute_op(Op::Or(0xA, 0xB), &KeyPad::new()).unwrap();
         assert_eq!(0b11110, cpu.v[0xA]);
     }
 
@@ -533,7 +966,7 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[3] = 0b10011;
         cpu.v[4] = 0b01110;
-        cpu.compute_op(Op::And(3, 4), &KeyPad::new());
+        cpu.compute_op(Op::And(3, 4), &KeyPad::new()).unwrap();
         assert_eq!(0b00010, cpu.v[3]);
     }
 
@@ -542,7 +975,7 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[0] = 0b110;
         cpu.v[1] = 0b101;
-        cpu.compute_op(Op::Xor(0, 1), &KeyPad::new());
+        cpu.compute_op(Op::Xor(0, 1), &KeyPad::new()).unwrap();
         assert_eq!(0b011, cpu.v[0]);
     }
 
@@ -551,7 +984,7 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[1] = 5;
         cpu.v[2] = 6;
-        cpu.compute_op(Op::AddReg(1, 2), &KeyPad::new());
+        cpu.compute_op(Op::AddReg(1, 2), &KeyPad::new()).unwrap();
         assert_eq!(11, cpu.v[1]);
         assert_eq!(0, cpu.v[0xF]);
     }
@@ -561,7 +994,7 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[1] = 128;
         cpu.v[2] = 128;
-        cpu.compute_op(Op::AddReg(1, 2), &KeyPad::new());
+        cpu.compute_op(Op::AddReg(1, 2), &KeyPad::new()).unwrap();
         assert_eq!(0, cpu.v[1]);
         assert_eq!(1, cpu.v[0xF]);
     }
@@ -571,7 +1004,7 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[1] = 5;
         cpu.v[2] = 6;
-        cpu.compute_op(Op::Sub(2, 1), &KeyPad::new());
+        cpu.compute_op(Op::Sub(2, 1), &KeyPad::new()).unwrap();
         assert_eq!(1, cpu.v[2]);
         assert_eq!(1, cpu.v[0xF]);
     }
@@ -581,16 +1014,44 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[1] = 5;
         cpu.v[2] = 6;
-        cpu.compute_op(Op::Sub(1, 2), &KeyPad::new());
+        cpu.compute_op(Op::Sub(1, 2), &KeyPad::new()).unwrap();
+        assert_eq!(255, cpu.v[1]);
         assert_eq!(6, cpu.v[2]);
         assert_eq!(0, cpu.v[0xF]);
     }
 
+    #[test]
+    fn sub_equal_does_not_borrow() {
+        let mut cpu = Cpu::new();
+        cpu.v[1] = 7;
+        cpu.v[2] = 7;
+        cpu.compute_op(Op::Sub(1, 2), &KeyPad::new()).unwrap();
+        assert_eq!(0, cpu.v[1]);
+        assert_eq!(1, cpu.v[0xF]);
+        cpu.v[1] = 7;
+        cpu.compute_op(Op::Subn(1, 2), &KeyPad::new()).unwrap();
+        assert_eq!(0, cpu.v[1]);
+        assert_eq!(1, cpu.v[0xF]);
+    }
+
+    #[test]
+    fn sub_into_vf_keeps_the_flag() {
+        let mut cpu = Cpu::new();
+        cpu.v[0xF] = 9;
+        cpu.v[1] = 5;
+        cpu.compute_op(Op::Sub(0xF, 1), &KeyPad::new()).unwrap();
+        assert_eq!(1, cpu.v[0xF]);
+        cpu.v[0xF] = 5;
+        cpu.v[1] = 9;
+        cpu.compute_op(Op::Subn(0xF, 1), &KeyPad::new()).unwrap();
+        assert_eq!(1, cpu.v[0xF]);
+    }
+
     #[test]
     fn shr() {
         let mut cpu = Cpu::new();
         cpu.v[6] = 0b110;
-        cpu.compute_op(Op::Shr(6), &KeyPad::new());
+        cpu.compute_op(Op::Shr(6, 0), &KeyPad::new()).unwrap();
         assert_eq!(0b011, cpu.v[6]);
         assert_eq!(0, cpu.v[0xF]);
     }
@@ -599,7 +1060,7 @@ mod test {
     fn shr_carry() {
         let mut cpu = Cpu::new();
         cpu.v[6] = 0b101;
-        cpu.compute_op(Op::Shr(6), &KeyPad::new());
+        cpu.compute_op(Op::Shr(6, 0), &KeyPad::new()).unwrap();
         assert_eq!(0b010, cpu.v[6]);
         assert_eq!(1, cpu.v[0xF]);
     }
@@ -609,8 +1070,9 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[1] = 5;
         cpu.v[2] = 6;
-        cpu.compute_op(Op::Subn(1, 2), &KeyPad::new());
-        assert_eq!(1, cpu.v[2]);
+        cpu.compute_op(Op::Subn(1, 2), &KeyPad::new()).unwrap();
+        assert_eq!(1, cpu.v[1]);
+        assert_eq!(6, cpu.v[2]);
         assert_eq!(1, cpu.v[0xF]);
     }
 
@@ -619,8 +1081,9 @@ mod test {
         let mut cpu = Cpu::new();
         cpu.v[1] = 5;
         cpu.v[2] = 6;
-        cpu.compute_op(Op::Subn(2, 1), &KeyPad::new());
-        assert_eq!(6, cpu.v[2]);
+        cpu.compute_op(Op::Subn(2, 1), &KeyPad::new()).unwrap();
+        assert_eq!(5, cpu.v[1]);
+        assert_eq!(255, cpu.v[2]);
         assert_eq!(0, cpu.v[0xF]);
     }
 
@@ -628,7 +1091,7 @@ mod test {
     fn shl() {
         let mut cpu = Cpu::new();
         cpu.v[1] = 0b01111111;
-        cpu.compute_op(Op::Shl(1), &KeyPad::new());
+        cpu.compute_op(Op::Shl(1, 0), &KeyPad::new()).unwrap();
         assert_eq!(0b11111110, cpu.v[1]);
         assert_eq!(0, cpu.v[0xF]);
     }
@@ -637,7 +1100,7 @@ mod test {
     fn shl_carry() {
         let mut cpu = Cpu::new();
         cpu.v[1] = 0b11111111;
-        cpu.compute_op(Op::Shl(1), &KeyPad::new());
+        cpu.compute_op(Op::Shl(1, 0), &KeyPad::new()).unwrap();
         assert_eq!(0b11111110, cpu.v[1]);
         assert_eq!(1, cpu.v[0xF]);
     }
@@ -645,7 +1108,7 @@ mod test {
     #[test]
     fn ldi() {
         let mut cpu = Cpu::new();
-        cpu.compute_op(Op::LdI(123), &KeyPad::new());
+        cpu.compute_op(Op::LdI(123), &KeyPad::new()).unwrap();
         assert_eq!(123, cpu.i);
     }
 
@@ -653,7 +1116,7 @@ mod test {
     fn jp_reg_i() {
         let mut cpu = Cpu::new();
         cpu.v[0] = 4;
-        cpu.compute_op(Op::JpRegI(5), &KeyPad::new());
+        cpu.compute_op(Op::JpRegI(5), &KeyPad::new()).unwrap();
         assert_eq!(9, cpu.pc);
     }
 
@@ -661,8 +1124,9 @@ mod test {
     fn skp() {
         let mut cpu = Cpu::new();
         let mut pad = KeyPad::new();
-        pad.key_down(Scancode::X);
-        cpu.compute_op(Op::Skp(0), &pad);
+        pad.key_down(0xA);
+        cpu.v[3] = 0xA;
+        cpu.compute_op(Op::Skp(3), &pad).unwrap();
         assert_eq!(0x202, cpu.pc)
     }
 
@@ -670,8 +1134,9 @@ mod test {
     fn skp_not_pressed() {
         let mut cpu = Cpu::new();
         let mut pad = KeyPad::new();
-        pad.key_down(Scancode::X);
-        cpu.compute_op(Op::Skp(1), &pad);
+        pad.key_down(3);
+        cpu.v[3] = 0xA;
+        cpu.compute_op(Op::Skp(3), &pad).unwrap();
         assert_eq!(0x200, cpu.pc)
     }
 
@@ -679,8 +1144,9 @@ mod test {
     fn sknp() {
         let mut cpu = Cpu::new();
         let mut pad = KeyPad::new();
-        pad.key_down(Scancode::X);
-        cpu.compute_op(Op::Sknp(1), &pad);
+        pad.key_down(3);
+        cpu.v[3] = 0xA;
+        cpu.compute_op(Op::Sknp(3), &pad).unwrap();
         assert_eq!(0x202, cpu.pc)
     }
 
@@ -688,8 +1154,9 @@ mod test {
     fn skp_pressed() {
         let mut cpu = Cpu::new();
         let mut pad = KeyPad::new();
-        pad.key_down(Scancode::X);
-        cpu.compute_op(Op::Sknp(0), &pad);
+        pad.key_down(0xA);
+        cpu.v[3] = 0xA;
+        cpu.compute_op(Op::Sknp(3), &pad).unwrap();
         assert_eq!(0x200, cpu.pc)
     }
 
@@ -697,7 +1164,7 @@ mod test {
     fn ld_dt() {
         let mut cpu = Cpu::new();
         cpu.delay_timer = 21;
-        cpu.compute_op(Op::LdDT(6), &KeyPad::new());
+        cpu.compute_op(Op::LdDT(6), &KeyPad::new()).unwrap();
         assert_eq!(21, cpu.v[6]);
     }
 
@@ -706,13 +1173,13 @@ mod test {
         let mut cpu = Cpu::new();
         let mut kb = KeyPad::new();
 
-        cpu.compute_op(Op::LdKb(5), &kb);
+        cpu.compute_op(Op::LdKb(5), &kb).unwrap();
         // simulate pc update during full cycle
         cpu.pc += 2;
         assert_eq!(0x200, cpu.pc);
         assert_eq!(0, cpu.v[5]);
-        kb.key_down(Scancode::W);
-        cpu.compute_op(Op::LdKb(5), &kb);
+        kb.key_down(0xA);
+        cpu.compute_op(Op::LdKb(5), &kb).unwrap();
         // simulate pc update during full cycle
         cpu.pc += 2;
         assert_eq!(0xA, cpu.v[5]);
@@ -723,7 +1190,7 @@ mod test {
     fn set_dt() {
         let mut cpu = Cpu::new();
         cpu.v[0xD] = 34;
-        cpu.compute_op(Op::SetDT(0xD), &KeyPad::new());
+        cpu.compute_op(Op::SetDT(0xD), &KeyPad::new()).unwrap();
         assert_eq!(34, cpu.delay_timer);
     }
 
@@ -731,7 +1198,7 @@ mod test {
     fn set_set() {
         let mut cpu = Cpu::new();
         cpu.v[0xC] = 68;
-        cpu.compute_op(Op::SetST(0xC), &KeyPad::new());
+        cpu.compute_op(Op::SetST(0xC), &KeyPad::new()).unwrap();
         assert_eq!(68, cpu.sound_timer);
     }
 
@@ -739,42 +1206,40 @@ mod test {
     fn add_to_i() {
         let mut cpu = Cpu::new();
         cpu.v[3] = 5;
-        cpu.compute_op(Op::AddToI(0x3), &KeyPad::new());
+        cpu.compute_op(Op::AddToI(0x3), &KeyPad::new()).unwrap();
         assert_eq!(0x205, cpu.i);
     }
 
     #[test]
-    #[should_panic]
     fn add_to_i_overflow() {
-        use std::u16;
-
         let mut cpu = Cpu::new();
         cpu.i = u16::MAX;
         cpu.v[3] = 1;
-        cpu.compute_op(Op::AddToI(0x3), &KeyPad::new());
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: u16::MAX as usize + 1 }),
+                   cpu.compute_op(Op::AddToI(0x3), &KeyPad::new()));
     }
 
     #[test]
     fn load_chr_sprite_addr() {
         let mut cpu = Cpu::new();
         cpu.v[0] = 2;
-        cpu.compute_op(Op::LdChr(0), &KeyPad::new());
+        cpu.compute_op(Op::LdChr(0), &KeyPad::new()).unwrap();
         assert_eq!(10, cpu.i);
     }
 
     #[test]
-    #[should_panic]
     fn load_chr_sprite_addr_non_existing() {
         let mut cpu = Cpu::new();
         cpu.v[0] = 16;
-        cpu.compute_op(Op::LdChr(0), &KeyPad::new());
+        assert_eq!(Err(CpuError::InvalidDigit { digit: 16 }),
+                   cpu.compute_op(Op::LdChr(0), &KeyPad::new()));
     }
 
     #[test]
     fn ld_bcd() {
         let mut cpu = Cpu::new();
         cpu.v[3] = 123;
-        cpu.compute_op(Op::LdBCD(3), &KeyPad::new());
+        cpu.compute_op(Op::LdBCD(3), &KeyPad::new()).unwrap();
         assert_eq!(1, cpu.memory[cpu.i as usize]);
         assert_eq!(2, cpu.memory[(cpu.i + 1) as usize]);
         assert_eq!(3, cpu.memory[(cpu.i + 2) as usize]);
@@ -787,7 +1252,7 @@ mod test {
         cpu.v[1] = 2;
         cpu.v[2] = 3;
         cpu.v[3] = 4;
-        cpu.compute_op(Op::LdRegs(3), &KeyPad::new());
+        cpu.compute_op(Op::LdRegs(3), &KeyPad::new()).unwrap();
         assert_eq!(1, cpu.memory[cpu.i as usize]);
         assert_eq!(2, cpu.memory[(cpu.i + 1) as usize]);
         assert_eq!(3, cpu.memory[(cpu.i + 2) as usize]);
@@ -795,11 +1260,11 @@ mod test {
     }
 
     #[test]
-    #[should_panic]
     fn load_registers_overflow() {
         let mut cpu = Cpu::new();
-        cpu.i = MEM_SIZE as u16 - 1;
-        cpu.compute_op(Op::LdRegs(1), &KeyPad::new());
+        cpu.i = (CHIP8_MEM_SIZE - 1) as u16;
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: CHIP8_MEM_SIZE }),
+                   cpu.compute_op(Op::LdRegs(1), &KeyPad::new()));
     }
 
     #[test]
@@ -809,7 +1274,7 @@ mod test {
         cpu.memory[0x201] = 2;
         cpu.memory[0x202] = 3;
         cpu.memory[0x203] = 4;
-        cpu.compute_op(Op::RdMem(3), &KeyPad::new());
+        cpu.compute_op(Op::RdMem(3), &KeyPad::new()).unwrap();
         assert_eq!(1, cpu.v[0]);
         assert_eq!(2, cpu.v[1]);
         assert_eq!(3, cpu.v[2]);
@@ -817,10 +1282,485 @@ mod test {
     }
 
     #[test]
-    #[should_panic]
     fn read_memory_overflow() {
         let mut cpu = Cpu::new();
-        cpu.i = MEM_SIZE as u16 - 1;
-        cpu.compute_op(Op::RdMem(1), &KeyPad::new());
+        cpu.i = (CHIP8_MEM_SIZE - 1) as u16;
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: CHIP8_MEM_SIZE }),
+                   cpu.compute_op(Op::RdMem(1), &KeyPad::new()));
+    }
+
+    #[test]
+    fn load_store_increment_overflow() {
+        let mut cpu = Cpu::with_quirks(Quirks { xo_chip: true, ..Quirks::COSMAC_VIP });
+        cpu.i = (MEM_SIZE - 1) as u16;
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: MEM_SIZE }),
+                   cpu.compute_op(Op::LdRegs(0), &KeyPad::new()));
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: MEM_SIZE }),
+                   cpu.compute_op(Op::RdMem(0), &KeyPad::new()));
+    }
+
+    #[test]
+    fn run_off_the_end_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.pc = CHIP8_MEM_SIZE - 2;
+        cpu.memory[CHIP8_MEM_SIZE - 2..CHIP8_MEM_SIZE].copy_from_slice(&[0x60, 0x00]);
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: CHIP8_MEM_SIZE }), cpu.cycle(&KeyPad::new()));
+
+        cpu.pc = CHIP8_MEM_SIZE - 2;
+        cpu.memory[CHIP8_MEM_SIZE - 2..CHIP8_MEM_SIZE].copy_from_slice(&[0x22, 0x00]);
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: CHIP8_MEM_SIZE }), cpu.cycle(&KeyPad::new()));
+        assert!(cpu.stack().is_empty());
+    }
+
+    #[test]
+    fn call_stack_overflow() {
+        let mut cpu = Cpu::new();
+        cpu.sp = 15;
+        assert_eq!(Err(CpuError::StackOverflow),
+                   cpu.compute_op(Op::Call(0x300), &KeyPad::new()));
+        assert_eq!(15, cpu.sp);
+    }
+
+    #[test]
+    fn sub_no_overflow_panic() {
+        let mut cpu = Cpu::new();
+        cpu.v[1] = 127;
+        cpu.v[2] = 128;
+        cpu.compute_op(Op::Sub(1, 2), &KeyPad::new()).unwrap();
+        assert_eq!(255, cpu.v[1]);
+        assert_eq!(0, cpu.v[0xF]);
+    }
+
+    #[test]
+    fn load_chr_sprite_addr_f() {
+        let mut cpu = Cpu::new();
+        cpu.v[0] = 0xF;
+        cpu.compute_op(Op::LdChr(0), &KeyPad::new()).unwrap();
+        assert_eq!(75, cpu.i);
+    }
+
+    #[test]
+    fn draw_out_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.i = (CHIP8_MEM_SIZE - 2) as u16;
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: CHIP8_MEM_SIZE }),
+                   cpu.compute_op(Op::Drw(0, 0, 5), &KeyPad::new()));
+    }
+
+    #[test]
+    fn ld_bcd_out_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.i = (CHIP8_MEM_SIZE - 1) as u16;
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: CHIP8_MEM_SIZE }),
+                   cpu.compute_op(Op::LdBCD(0), &KeyPad::new()));
+    }
+
+    #[test]
+    fn cycle_invalid_opcode() {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&[0xFF, 0xFF]).unwrap();
+        assert_eq!(Err(CpuError::InvalidOpcode { pc: 0x200, opcode: 0xFFFF }),
+                   cpu.cycle(&KeyPad::new()));
+    }
+
+    #[test]
+    fn cycle_pc_out_of_memory() {
+        let mut cpu = Cpu::new();
+        cpu.pc = CHIP8_MEM_SIZE - 1;
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: CHIP8_MEM_SIZE }),
+                   cpu.cycle(&KeyPad::new()));
+    }
+
+    #[test]
+    fn shr_uses_vy_quirk() {
+        let mut cpu = Cpu::with_quirks(Quirks { shift_uses_vy: true, ..Quirks::default() });
+        cpu.v[1] = 0b100;
+        cpu.v[2] = 0b011;
+        cpu.compute_op(Op::Shr(1, 2), &KeyPad::new()).unwrap();
+        assert_eq!(0b001, cpu.v[1]);
+        assert_eq!(1, cpu.v[0xF]);
+    }
+
+    #[test]
+    fn shl_flag_overrides_vf() {
+        let mut cpu = Cpu::new();
+        cpu.v[0xF] = 0b10000001;
+        cpu.compute_op(Op::Shl(0xF, 0), &KeyPad::new()).unwrap();
+        assert_eq!(1, cpu.v[0xF]);
+    }
+
+    #[test]
+    fn logic_resets_vf_quirk() {
+        let mut cpu = Cpu::with_quirks(Quirks::COSMAC_VIP);
+        cpu.v[0xF] = 1;
+        cpu.compute_op(Op::Or(0, 1), &KeyPad::new()).unwrap();
+        assert_eq!(0, cpu.v[0xF]);
+    }
+
+    #[test]
+    fn jp_reg_i_uses_vx_quirk() {
+        let mut cpu = Cpu::with_quirks(Quirks::CHIP_48);
+        cpu.v[0] = 1;
+        cpu.v[3] = 4;
+        cpu.compute_op(Op::JpRegI(0x345), &KeyPad::new()).unwrap();
+        assert_eq!(0x349, cpu.pc);
+    }
+
+    #[test]
+    fn load_store_increments_i_quirk() {
+        let mut cpu = Cpu::with_quirks(Quirks::COSMAC_VIP);
+        cpu.i = 0x300;
+        cpu.compute_op(Op::LdRegs(3), &KeyPad::new()).unwrap();
+        assert_eq!(0x304, cpu.i);
+        cpu.compute_op(Op::RdMem(0), &KeyPad::new()).unwrap();
+        assert_eq!(0x305, cpu.i);
+
+        let mut cpu = Cpu::with_quirks(Quirks::CHIP_48);
+        cpu.i = 0x300;
+        cpu.compute_op(Op::LdRegs(3), &KeyPad::new()).unwrap();
+        assert_eq!(0x303, cpu.i);
+
+        let mut cpu = Cpu::with_quirks(Quirks::SUPER_CHIP);
+        cpu.i = 0x300;
+        cpu.compute_op(Op::LdRegs(3), &KeyPad::new()).unwrap();
+        assert_eq!(0x300, cpu.i);
+    }
+
+    #[test]
+    fn cosmac_vip_random_varies_within_a_frame() {
+        let mut cpu = Cpu::new();
+        cpu.set_random_source(RandomSource::cosmac_vip(1234));
+        cpu.load_program(&[0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF]).unwrap();
+        cpu.run_frame(&KeyPad::new(), 3).unwrap();
+        let v = cpu.registers();
+        assert!(v[0] != v[1] && v[1] != v[2] && v[0] != v[2], "{:?}", &v[..3]);
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        // 200: DRW V0, V0, 1    202: DRW V0, V0, 1    204: JP 204
+        let program = [0xD0, 0x01, 0xD0, 0x01, 0x12, 0x04];
+        let mut cpu = Cpu::with_quirks(Quirks::COSMAC_VIP);
+        cpu.load_program(&program).unwrap();
+        cpu.run_frame(&KeyPad::new(), 10).unwrap();
+        assert_eq!(0x200, cpu.pc);
+        cpu.run_frame(&KeyPad::new(), 10).unwrap();
+        assert_eq!(0x202, cpu.pc);
+        cpu.run_frame(&KeyPad::new(), 10).unwrap();
+        assert_eq!(0x204, cpu.pc);
+
+        let mut cpu = Cpu::new();
+        cpu.load_program(&program).unwrap();
+        cpu.run_frame(&KeyPad::new(), 10).unwrap();
+        assert_eq!(0x204, cpu.pc);
+    }
+
+    #[test]
+    fn draw_clips() {
+        let mut cpu = Cpu::new();
+        cpu.v[0] = 60;
+        cpu.v[1] = 31;
+        cpu.i = 0;
+        cpu.compute_op(Op::Drw(0, 1, 2), &KeyPad::new()).unwrap();
+        assert_eq!(4, cpu.screen.pixels().filter(|p| p.on()).count());
+    }
+
+    #[test]
+    fn draw_wraps_quirk() {
+        let mut cpu = Cpu::with_quirks(Quirks { draw_wraps: true, ..Quirks::default() });
+        cpu.v[0] = 60;
+        cpu.v[1] = 31;
+        cpu.i = 0;
+        cpu.compute_op(Op::Drw(0, 1, 2), &KeyPad::new()).unwrap();
+        assert_eq!(6, cpu.screen.pixels().filter(|p| p.on()).count());
+        assert!(cpu.screen.pixels().any(|p| p.on() && p.x() == 60 && p.y() == 0));
+    }
+
+    #[test]
+    fn draw_wraps_start_position() {
+        let mut cpu = Cpu::new();
+        cpu.v[0] = 64 + 2;
+        cpu.v[1] = 32 + 3;
+        cpu.i = 0;
+        cpu.compute_op(Op::Drw(0, 1, 1), &KeyPad::new()).unwrap();
+        assert!(cpu.screen.pixels().any(|p| p.on() && p.x() == 2 && p.y() == 3));
+    }
+
+    #[test]
+    fn high_low() {
+        let mut cpu = Cpu::new();
+        cpu.compute_op(Op::High, &KeyPad::new()).unwrap();
+        assert!(cpu.screen.is_hires());
+        cpu.compute_op(Op::Low, &KeyPad::new()).unwrap();
+        assert!(!cpu.screen.is_hires());
+    }
+
+    #[test]
+    fn draw_hires() {
+        let mut cpu = Cpu::new();
+        cpu.compute_op(Op::High, &KeyPad::new()).unwrap();
+        cpu.v[0] = 120;
+        cpu.v[1] = 60;
+        cpu.i = 0;
+        cpu.compute_op(Op::Drw(0, 1, 1), &KeyPad::new()).unwrap();
+        assert!(cpu.screen.is_on(123, 60));
+        assert!(!cpu.screen.is_on(124, 60));
+    }
+
+    #[test]
+    fn draw_16x16() {
+        let mut cpu = Cpu::new();
+        cpu.compute_op(Op::High, &KeyPad::new()).unwrap();
+        cpu.memory[0x300..0x320].iter_mut().for_each(|b| *b = 0xFF);
+        cpu.i = 0x300;
+        cpu.compute_op(Op::Drw(0, 1, 0), &KeyPad::new()).unwrap();
+        assert_eq!(256, cpu.screen.pixels().filter(|p| p.on()).count());
+        assert_eq!(0, cpu.v[0xF]);
+        cpu.compute_op(Op::Drw(0, 1, 0), &KeyPad::new()).unwrap();
+        assert_eq!(0, cpu.screen.pixels().filter(|p| p.on()).count());
+        assert_eq!(1, cpu.v[0xF]);
+    }
+
+    #[test]
+    fn draw_keeps_pixels_under_zero_bits() {
+        let mut cpu = Cpu::new();
+        cpu.screen.set_pixel_value(0, 0, true);
+        cpu.screen.set_pixel_value(1, 0, true);
+        cpu.write_memory(0x300, &[0b0100_0000]).unwrap();
+        cpu.i = 0x300;
+        cpu.compute_op(Op::Drw(0, 0, 1), &KeyPad::new()).unwrap();
+        assert!(cpu.screen.is_on(0, 0));
+        assert!(!cpu.screen.is_on(1, 0));
+        assert_eq!(1, cpu.v[0xF]);
+
+        cpu.compute_op(Op::Drw(0, 0, 1), &KeyPad::new()).unwrap();
+        assert!(cpu.screen.is_on(0, 0) && cpu.screen.is_on(1, 0));
+        assert_eq!(0, cpu.v[0xF]);
+    }
+
+    #[test]
+    fn scroll() {
+        let mut cpu = Cpu::new();
+        cpu.screen.set_pixel_value(8, 8, true);
+        cpu.compute_op(Op::Scd(2), &KeyPad::new()).unwrap();
+        cpu.compute_op(Op::Scr, &KeyPad::new()).unwrap();
+        assert!(cpu.screen.is_on(12, 10));
+        cpu.compute_op(Op::Scl, &KeyPad::new()).unwrap();
+        assert!(cpu.screen.is_on(8, 10));
+        cpu.compute_op(Op::Scu(3), &KeyPad::new()).unwrap();
+        assert!(cpu.screen.is_on(8, 7));
+    }
+
+    #[test]
+    fn exit() {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&[0x00, 0xFD]).unwrap();
+        cpu.cycle(&KeyPad::new()).unwrap();
+        assert!(cpu.has_exited());
+        assert_eq!(0x200, cpu.pc);
+    }
+
+    #[test]
+    fn ld_hf() {
+        let mut cpu = Cpu::new();
+        cpu.v[0] = 2;
+        cpu.compute_op(Op::LdHf(0), &KeyPad::new()).unwrap();
+        assert_eq!(100, cpu.i);
+    }
+
+    #[test]
+    fn save_load_flags() {
+        let mut cpu = Cpu::new();
+        cpu.v[0] = 1;
+        cpu.v[1] = 2;
+        cpu.v[2] = 3;
+        cpu.compute_op(Op::SaveFlags(1), &KeyPad::new()).unwrap();
+        cpu.v = [0; 16];
+        cpu.compute_op(Op::LoadFlags(2), &KeyPad::new()).unwrap();
+        assert_eq!([1, 2, 0], cpu.v[..3]);
+    }
+
+    #[test]
+    fn save_load_range() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0x300;
+        cpu.v[2] = 1;
+        cpu.v[3] = 2;
+        cpu.v[4] = 3;
+        cpu.compute_op(Op::SaveRange(2, 4), &KeyPad::new()).unwrap();
+        assert_eq!([1, 2, 3], cpu.memory[0x300..0x303]);
+        assert_eq!(0x300, cpu.i);
+        cpu.compute_op(Op::LoadRange(7, 5), &KeyPad::new()).unwrap();
+        assert_eq!([3, 2, 1], cpu.v[5..8]);
+    }
+
+    #[test]
+    fn ld_i_long() {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&[0xF0, 0x00, 0xAB, 0xCD, 0x00, 0xE0]).unwrap();
+        cpu.cycle(&KeyPad::new()).unwrap();
+        assert_eq!(0xABCD, cpu.i);
+        assert_eq!(0x204, cpu.pc);
+    }
+
+    #[test]
+    fn skip_ld_i_long() {
+        let program = [0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD];
+        let mut cpu = Cpu::with_quirks(Quirks::XO_CHIP);
+        cpu.load_program(&program).unwrap();
+        cpu.cycle(&KeyPad::new()).unwrap();
+        assert_eq!(0x206, cpu.pc);
+
+        let mut cpu = Cpu::new();
+        cpu.load_program(&program).unwrap();
+        cpu.cycle(&KeyPad::new()).unwrap();
+        assert_eq!(0x204, cpu.pc);
+    }
+
+    #[test]
+    fn xo_chip_memory() {
+        let mut cpu = Cpu::new();
+        assert_eq!(CHIP8_MEM_SIZE, cpu.memory().len());
+        assert_eq!(Err(CpuError::MemoryOutOfBounds { addr: 0x1000 }), cpu.write_memory(0x1000, &[1]));
+        assert!(cpu.load_program(&[0; CHIP8_MEM_SIZE - 0x1FF]).is_err());
+
+        let mut cpu = Cpu::with_quirks(Quirks::XO_CHIP);
+        assert_eq!(MEM_SIZE, cpu.memory().len());
+        cpu.write_memory(0x1000, &[1]).unwrap();
+        cpu.i = 0x1000;
+        cpu.compute_op(Op::RdMem(0), &KeyPad::new()).unwrap();
+        assert_eq!(1, cpu.v[0]);
+    }
+
+    #[test]
+    fn draw_two_planes() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x300] = 0x80;
+        cpu.memory[0x301] = 0xC0;
+        cpu.i = 0x300;
+        cpu.compute_op(Op::Plane(3), &KeyPad::new()).unwrap();
+        cpu.compute_op(Op::Drw(0, 0, 1), &KeyPad::new()).unwrap();
+        let colors: Vec<u8> = cpu.screen.pixels().take(2).map(|p| p.color()).collect();
+        assert_eq!(vec![3, 2], colors);
+    }
+
+    #[test]
+    fn draw_no_plane() {
+        let mut cpu = Cpu::new();
+        cpu.i = 0;
+        cpu.compute_op(Op::Plane(0), &KeyPad::new()).unwrap();
+        cpu.compute_op(Op::Drw(0, 0, 5), &KeyPad::new()).unwrap();
+        assert_eq!(0, cpu.screen.pixels().filter(|p| p.on()).count());
+    }
+
+    #[test]
+    fn audio_and_pitch() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        cpu.i = 0x300;
+        cpu.v[1] = 120;
+        cpu.compute_op(Op::Audio, &KeyPad::new()).unwrap();
+        cpu.compute_op(Op::Pitch(1), &KeyPad::new()).unwrap();
+        assert_eq!(&[0xAA; 16], cpu.audio_pattern());
+        assert_eq!(120, cpu.pitch());
+    }
+
+    #[test]
+    fn save_load_state() {
+        let mut cpu = Cpu::with_quirks(Quirks::COSMAC_VIP);
+        cpu.load_program(&[0x60, 0x05, 0xA0, 0x00, 0xD0, 0x05, 0x22, 0x00]).unwrap();
+        for _ in 0..4 {
+            cpu.cycle(&KeyPad::new()).unwrap();
+        }
+        cpu.delay_timer = 7;
+        let data = cpu.save_state();
+
+        let mut restored = Cpu::new();
+        restored.load_program(&[0x60, 0x05, 0xA0, 0x00, 0xD0, 0x05, 0x22, 0x00]).unwrap();
+        restored.load_state(&data).unwrap();
+        assert_eq!(cpu.v, restored.v);
+        assert_eq!(cpu.i, restored.i);
+        assert_eq!(cpu.pc, restored.pc);
+        assert_eq!(cpu.sp, restored.sp);
+        assert_eq!(cpu.stack, restored.stack);
+        assert_eq!(7, restored.delay_timer);
+        assert_eq!(Quirks::COSMAC_VIP, restored.quirks());
+        assert_eq!(&cpu.memory[..], &restored.memory[..]);
+        assert!(cpu.screen.pixels().eq(restored.screen.pixels()));
+    }
+
+    #[test]
+    fn load_state_other_rom() {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&[0x00, 0xE0]).unwrap();
+        let data = cpu.save_state();
+        let mut other = Cpu::new();
+        other.load_program(&[0x00, 0xEE]).unwrap();
+        match other.load_state(&data) {
+            Err(StateError::RomMismatch { .. }) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn load_state_truncated() {
+        let mut cpu = Cpu::new();
+        let data = cpu.save_state();
+        assert_eq!(Err(StateError::Truncated), cpu.load_state(&data[..data.len() - 1]));
+    }
+
+    #[test]
+    fn load_state_invalid_sp() {
+        let mut cpu = Cpu::new();
+        let mut data = cpu.save_state();
+        let sp_offset = 4 + 2 + 8 + 256 + 16 + 2 + 1 + 1 + 32 + 4;
+        data[sp_offset] = 16;
+        assert_eq!(Err(StateError::InvalidField("sp")), cpu.load_state(&data));
+        assert_eq!(0, cpu.sp);
+    }
+
+    #[test]
+    fn rnd_seeded() {
+        let mut a = Cpu::new();
+        let mut b = Cpu::new();
+        a.set_random_source(RandomSource::new(7));
+        b.set_random_source(RandomSource::new(7));
+        for _ in 0..16 {
+            a.compute_op(Op::Rnd(0, 0xFF), &KeyPad::new()).unwrap();
+            b.compute_op(Op::Rnd(0, 0xFF), &KeyPad::new()).unwrap();
+            assert_eq!(a.v[0], b.v[0]);
+        }
+    }
+
+    #[test]
+    fn rnd_mask() {
+        let mut cpu = Cpu::new();
+        for _ in 0..16 {
+            cpu.compute_op(Op::Rnd(0, 0x0F), &KeyPad::new()).unwrap();
+            assert_eq!(0, cpu.v[0] & 0xF0);
+        }
+    }
+
+    #[test]
+    fn save_load_state_random_source() {
+        let mut cpu = Cpu::new();
+        cpu.set_random_source(RandomSource::new(99));
+        cpu.compute_op(Op::Rnd(0, 0xFF), &KeyPad::new()).unwrap();
+        let data = cpu.save_state();
+        cpu.compute_op(Op::Rnd(0, 0xFF), &KeyPad::new()).unwrap();
+        let expected = cpu.v[0];
+        cpu.load_state(&data).unwrap();
+        cpu.compute_op(Op::Rnd(0, 0xFF), &KeyPad::new()).unwrap();
+        assert_eq!(expected, cpu.v[0]);
+    }
+
+    #[test]
+    fn run_frame() {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        cpu.delay_timer = 2;
+        cpu.run_frame(&KeyPad::new(), 5).unwrap();
+        assert_eq!(3, cpu.v[0]);
+        assert_eq!(1, cpu.delay_timer);
     }
 }
@@ -0,0 +1,420 @@
+use cpu::{Cpu, CpuError};
+use keypad::KeyPad;
+use opcodes::Op;
+use scheduler::{InstructionClock, Speed};
+
+use std::{
+    fmt::Write as FmtWrite,
+    io::{self, BufRead, Write}
+};
+
+/// `continue` gives up after this many instructions, since a game loop never halts.
+const CONTINUE_LIMIT: usize = 1_000_000;
+
+const HELP: &str = "\
+step [N]             run N instructions (1 by default)
+continue             run until a breakpoint, a fault, an endless jump, a key wait
+                     or 1000000 instructions
+break ADDR           stop before the instruction at ADDR
+break op PATTERN     stop before opcodes matching PATTERN, e.g. D??? or 8?06
+delete ADDR|PATTERN  remove a breakpoint
+breakpoints          list breakpoints
+regs                 print registers and timers
+stack                print the call stack
+op                   print the decoded instruction at PC
+x ADDR [LEN]         hexdump LEN bytes of memory (64 by default)
+set ADDR BYTE...     write bytes to memory
+press KEY            hold a keypad key down
+release KEY          release a keypad key
+screen               print the screen
+quit                 leave the debugger";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct OpcodePattern {
+    value: u16,
+    mask: u16,
+}
+
+impl OpcodePattern {
+    pub fn parse(pattern: &str) -> Option<OpcodePattern> {
+        if pattern.chars().count() != 4 {
+            return None;
+        }
+        let mut value = 0;
+        let mut mask = 0;
+        for c in pattern.chars() {
+            value <<= 4;
+            mask <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                value |= digit as u16;
+                mask |= 0xF;
+            } else if !c.is_ascii_alphabetic() && c != '?' {
+                return None;
+            }
+        }
+        Some(OpcodePattern { value, mask })
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Break(usize),
+    BreakOpcode(OpcodePattern),
+    Delete(String),
+    Breakpoints,
+    Registers,
+    Stack,
+    Op,
+    Dump(usize, usize),
+    Set(usize, Vec<u8>),
+    Press(u8),
+    Release(u8),
+    Screen,
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["s"] | ["step"] => Command::Step(1),
+            ["s", n] | ["step", n] =>
+                Command::Step(n.parse().map_err(|_| format!("invalid count: {}", n))?),
+            ["c"] | ["continue"] => Command::Continue,
+            ["b", "op", pattern] | ["break", "op", pattern] => Command::BreakOpcode(
+                OpcodePattern::parse(pattern)
+                    .ok_or_else(|| format!("invalid opcode pattern: {}", pattern))?),
+            ["b", addr] | ["break", addr] => Command::Break(parse_hex(addr)?),
+            ["d", what] | ["delete", what] => Command::Delete(what.to_string()),
+            ["breakpoints"] => Command::Breakpoints,
+            ["r"] | ["regs"] => Command::Registers,
+            ["stack"] => Command::Stack,
+            ["op"] => Command::Op,
+            ["x", addr] => Command::Dump(parse_hex(addr)?, 64),
+            ["x", addr, len] => Command::Dump(
+                parse_hex(addr)?, len.parse().map_err(|_| format!("invalid length: {}", len))?),
+            ["set", addr, ref bytes @ ..] if !bytes.is_empty() => {
+                let bytes = bytes.iter()
+                    .map(|b| u8::from_str_radix(b, 16).map_err(|_| format!("invalid byte: {}", b)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                Command::Set(parse_hex(addr)?, bytes)
+            }
+            ["press", key] => Command::Press(parse_key(key)?),
+            ["release", key] => Command::Release(parse_key(key)?),
+            ["screen"] => Command::Screen,
+            ["h"] | ["help"] => Command::Help,
+            ["q"] | ["quit"] => Command::Quit,
+            _ => return Err(format!("unknown command: {} (try help)", line.trim())),
+        };
+        Ok(command)
+    }
+}
+
+fn parse_hex(value: &str) -> Result<usize, String> {
+    let digits = value.trim_start_matches("0x");
+    usize::from_str_radix(digits, 16).map_err(|_| format!("invalid address: {}", value))
+}
+
+fn parse_key(value: &str) -> Result<u8, String> {
+    u8::from_str_radix(value, 16).ok().filter(|&k| k <= 0xF)
+        .ok_or_else(|| format!("invalid key: {}", value))
+}
+
+pub struct Debugger {
+    clock: InstructionClock,
+    keypad: KeyPad,
+    breakpoints: Vec<usize>,
+    patterns: Vec<OpcodePattern>,
+    last_command: Option<Command>,
+}
+
+impl Debugger {
+    pub fn new(speed: Speed) -> Debugger {
+        Debugger {
+            clock: InstructionClock::new(speed),
+            keypad: KeyPad::new(),
+            breakpoints: Vec::new(),
+            patterns: Vec::new(),
+            last_command: None,
+        }
+    }
+
+    pub fn repl<R: BufRead, W: Write>(&mut self, cpu: &mut Cpu, input: R, mut output: W)
+                                      -> io::Result<()> {
+        writeln!(output, "{}", self.current_op(cpu))?;
+        write!(output, "(chip8) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let command = if line.trim().is_empty() {
+                self.last_command.clone()
+            } else {
+                match Command::parse(&line) {
+                    Ok(command) => Some(command),
+                    Err(e) => {
+                        write!(output, "{}\n(chip8) ", e)?;
+                        output.flush()?;
+                        continue;
+                    }
+                }
+            };
+            if let Some(command) = command {
+                if command == Command::Quit {
+                    return Ok(());
+                }
+                write!(output, "{}", self.execute(cpu, &command))?;
+                self.last_command = Some(command);
+            }
+            write!(output, "(chip8) ")?;
+            output.flush()?;
+        }
+        writeln!(output)
+    }
+
+    pub fn execute(&mut self, cpu: &mut Cpu, command: &Command) -> String {
+        let mut out = String::new();
+        match *command {
+            Command::Step(n) => {
+                for _ in 0..n {
+                    if let Err(e) = self.step(cpu) {
+                        writeln!(out, "CPU fault: {}", e).unwrap();
+                        break;
+                    }
+                }
+                writeln!(out, "{}", self.current_op(cpu)).unwrap();
+            }
+            Command::Continue => {
+                writeln!(out, "{}", self.continue_execution(cpu)).unwrap();
+                writeln!(out, "{}", self.current_op(cpu)).unwrap();
+            }
+            Command::Break(addr) => {
+                if !self.breakpoints.contains(&addr) {
+                    self.breakpoints.push(addr);
+                }
+                writeln!(out, "Breakpoint at {:#06X}", addr).unwrap();
+            }
+            Command::BreakOpcode(pattern) => {
+                if !self.patterns.contains(&pattern) {
+                    self.patterns.push(pattern);
+                }
+                writeln!(out, "Breakpoint on opcodes {:04X} (mask {:04X})",
+                         pattern.value, pattern.mask).unwrap();
+            }
+            Command::Delete(ref what) => {
+                let before = self.breakpoints.len() + self.patterns.len();
+                if let Ok(addr) = parse_hex(what) {
+                    self.breakpoints.retain(|&a| a != addr);
+                }
+                if let Some(pattern) = OpcodePattern::parse(what) {
+                    self.patterns.retain(|&p| p != pattern);
+                }
+                if before == self.breakpoints.len() + self.patterns.len() {
+                    writeln!(out, "No breakpoint {}", what).unwrap();
+                }
+            }
+            Command::Breakpoints => {
+                for addr in &self.breakpoints {
+                    writeln!(out, "{:#06X}", addr).unwrap();
+                }
+                for pattern in &self.patterns {
+                    writeln!(out, "op {:04X} (mask {:04X})", pattern.value, pattern.mask).unwrap();
+                }
+            }
+            Command::Registers => {
+                for (n, v) in cpu.registers().iter().enumerate() {
+                    write!(out, "V{:X}={:02X}{}", n, v, if n % 8 == 7 { "\n" } else { " " }).unwrap();
+                }
+                writeln!(out, "I={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}",
+                         cpu.i, cpu.pc(), cpu.stack().len(),
+                         cpu.delay_timer(), cpu.sound_timer()).unwrap();
+            }
+            Command::Stack => {
+                for (depth, addr) in cpu.stack().iter().enumerate().rev() {
+                    writeln!(out, "#{} {:#06X}", depth, addr).unwrap();
+                }
+            }
+            Command::Op => writeln!(out, "{}", self.current_op(cpu)).unwrap(),
+            Command::Dump(addr, len) => {
+                let memory = cpu.memory();
+                let end = addr.saturating_add(len).min(memory.len());
+                for start in (addr.min(end)..end).step_by(16) {
+                    let line = &memory[start..(start + 16).min(end)];
+                    let hex: Vec<String> = line.iter().map(|b| format!("{:02X}", b)).collect();
+                    let ascii: String = line.iter()
+                        .map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' })
+                        .collect();
+                    writeln!(out, "{:04X}: {:<47}  {}", start, hex.join(" "), ascii).unwrap();
+                }
+            }
+            Command::Set(addr, ref bytes) => if let Err(e) = cpu.write_memory(addr, bytes) {
+                writeln!(out, "{}", e).unwrap();
+            },
+            Command::Press(key) => self.keypad.key_down(key),
+            Command::Release(key) => self.keypad.key_up(key),
+            Command::Screen => {
+                let screen = &cpu.screen;
+                for y in 0..screen.height() {
+                    let row: String = (0..screen.width())
+                        .map(|x| if screen.is_on(x, y) { '#' } else { '.' })
+                        .collect();
+                    writeln!(out, "{}", row).unwrap();
+                }
+            }
+            Command::Help => writeln!(out, "{}", HELP).unwrap(),
+            Command::Quit => (),
+        }
+        out
+    }
+
+    fn step(&mut self, cpu: &mut Cpu) -> Result<Op, CpuError> {
+        self.clock.step(cpu, &self.keypad)
+    }
+
+    fn continue_execution(&mut self, cpu: &mut Cpu) -> String {
+        for _ in 0..CONTINUE_LIMIT {
+            let pc = cpu.pc();
+            match self.step(cpu) {
+                Err(e) => return format!("CPU fault: {}", e),
+                Ok(Op::Jp(addr)) if addr as usize == pc => {
+                    return format!("Endless jump at {:#06X}", pc);
+                }
+                Ok(Op::LdKb(_)) if cpu.pc() == pc => {
+                    return format!("Waiting for a key at {:#06X}, use press KEY", pc);
+                }
+                Ok(_) => (),
+            }
+            if cpu.has_exited() {
+                return "Program exited".to_string();
+            }
+            if self.breakpoints.contains(&cpu.pc()) {
+                return format!("Breakpoint at {:#06X}", cpu.pc());
+            }
+            if let Ok(opcode) = cpu.opcode() {
+                if self.patterns.iter().any(|p| p.matches(opcode)) {
+                    return format!("Breakpoint on opcode {:04X} at {:#06X}", opcode, cpu.pc());
+                }
+            }
+        }
+        format!("Stopped after {} instructions", CONTINUE_LIMIT)
+    }
+
+    fn current_op(&self, cpu: &Cpu) -> String {
+        match (cpu.opcode(), cpu.fetch_opcode()) {
+            (Ok(opcode), Ok(op)) => format!("{:04X}: {:04X}  {:?}", cpu.pc(), opcode, op),
+            (_, Err(e)) => format!("{:04X}: {}", cpu.pc(), e),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cpu(program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_program(program).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn opcode_pattern() {
+        let pattern = OpcodePattern::parse("8?y6").unwrap();
+        assert!(pattern.matches(0x8126));
+        assert!(!pattern.matches(0x8127));
+        assert_eq!(None, OpcodePattern::parse("8?6"));
+        assert_eq!(None, OpcodePattern::parse("8?-6"));
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Ok(Command::Step(1)), Command::parse("s"));
+        assert_eq!(Ok(Command::Step(5)), Command::parse("step 5"));
+        assert_eq!(Ok(Command::Break(0x204)), Command::parse("break 0x204"));
+        assert_eq!(Ok(Command::Dump(0x200, 64)), Command::parse("x 200"));
+        assert_eq!(Ok(Command::Set(0x300, vec![0xAB, 0x01])), Command::parse("set 300 AB 01"));
+        assert_eq!(Ok(Command::Press(0xF)), Command::parse("press f"));
+        assert!(Command::parse("press 10").is_err());
+        assert!(Command::parse("jump").is_err());
+    }
+
+    #[test]
+    fn step() {
+        let mut c = cpu(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03]);
+        let mut debugger = Debugger::new(Speed::default());
+        let out = debugger.execute(&mut c, &Command::Step(2));
+        assert_eq!([1, 2, 0], c.registers()[0..3]);
+        assert_eq!("0204: 6203  Ld(2, 3)\n", out);
+    }
+
+    #[test]
+    fn continue_to_breakpoint() {
+        let mut c = cpu(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x06]);
+        let mut debugger = Debugger::new(Speed::default());
+        debugger.execute(&mut c, &Command::Break(0x204));
+        let out = debugger.execute(&mut c, &Command::Continue);
+        assert_eq!(0x204, c.pc());
+        assert!(out.starts_with("Breakpoint at 0x0204"));
+        let out = debugger.execute(&mut c, &Command::Continue);
+        assert!(out.starts_with("Endless jump at 0x0206"));
+    }
+
+    #[test]
+    fn continue_to_opcode_breakpoint() {
+        let mut c = cpu(&[0x60, 0x01, 0x61, 0x02, 0xD0, 0x15, 0x12, 0x06]);
+        let mut debugger = Debugger::new(Speed::default());
+        debugger.execute(&mut c, &Command::parse("break op D???").unwrap());
+        debugger.execute(&mut c, &Command::Continue);
+        assert_eq!(0x204, c.pc());
+    }
+
+    #[test]
+    fn continue_until_fault() {
+        let mut c = cpu(&[0x00, 0xEE]);
+        let mut debugger = Debugger::new(Speed::default());
+        let out = debugger.execute(&mut c, &Command::Continue);
+        assert!(out.starts_with("CPU fault: "));
+    }
+
+    #[test]
+    fn continue_stops_in_loops() {
+        let mut c = cpu(&[0x70, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new(Speed::default());
+        let out = debugger.execute(&mut c, &Command::Continue);
+        assert!(out.starts_with("Stopped after 1000000 instructions"));
+
+        let mut c = cpu(&[0xF0, 0x0A]);
+        let out = debugger.execute(&mut c, &Command::Continue);
+        assert!(out.starts_with("Waiting for a key at 0x0200"));
+        debugger.execute(&mut c, &Command::Press(5));
+        debugger.execute(&mut c, &Command::Step(1));
+        assert_eq!(5, c.registers()[0]);
+    }
+
+    #[test]
+    fn dump_and_set() {
+        let mut c = cpu(&[]);
+        let mut debugger = Debugger::new(Speed::default());
+        debugger.execute(&mut c, &Command::Set(0x300, vec![0x41, 0x42]));
+        let out = debugger.execute(&mut c, &Command::Dump(0x300, 4));
+        assert_eq!("0300: 41 42 00 00                                      AB..\n", out);
+        let out = debugger.execute(&mut c, &Command::Dump(0xFF8, usize::MAX));
+        assert_eq!(1, out.lines().count());
+    }
+
+    #[test]
+    fn repl_repeats_last_command() {
+        let mut c = cpu(&[0x70, 0x01, 0x70, 0x01, 0x70, 0x01]);
+        let mut debugger = Debugger::new(Speed::default());
+        let mut output = Vec::new();
+        debugger.repl(&mut c, "step\n\n\nquit\nstep\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(3, c.registers()[0]);
+    }
+}
@@ -0,0 +1,360 @@
+use opcodes::{decode, Op};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write
+};
+
+pub const LOAD_ADDRESS: usize = 0x200;
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Syntax {
+    Chipper,
+    Octo,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LabelKind {
+    Subroutine,
+    Jump,
+    Data,
+}
+
+pub fn instruction_at(rom: &[u8], addr: usize) -> Option<(Op, usize)> {
+    let offset = addr.checked_sub(LOAD_ADDRESS)?;
+    let word = |offset: usize| {
+        rom.get(offset..offset + 2).map(|bytes| (bytes[0] as u16) << 8 | bytes[1] as u16)
+    };
+    let op = decode(word(offset)?)?;
+    match op {
+        Op::LdILong => word(offset + 2).map(|_| (op, 4)),
+        _ => Some((op, 2)),
+    }
+}
+
+pub fn successors(rom: &[u8], op: Op, addr: usize, len: usize) -> Vec<usize> {
+    let next = addr + len;
+    match op {
+        Op::Jp(target) => vec![target as usize],
+        Op::Call(target) => vec![target as usize, next],
+        Op::Ret | Op::Exit | Op::JpRegI(_) => vec![],
+        Op::Se(..) | Op::Sne(..) | Op::SeReg(..) | Op::SneReg(..) | Op::Skp(_) | Op::Sknp(_) => {
+            let skipped = match instruction_at(rom, next) {
+                Some((Op::LdILong, _)) => 4,
+                _ => 2,
+            };
+            vec![next, next + skipped]
+        }
+        _ => vec![next],
+    }
+}
+
+pub fn reachable(rom: &[u8]) -> BTreeMap<usize, (Op, usize)> {
+    let mut code = BTreeMap::new();
+    let mut pending = vec![LOAD_ADDRESS];
+    while let Some(addr) = pending.pop() {
+        if code.contains_key(&addr) {
+            continue;
+        }
+        if let Some((op, len)) = instruction_at(rom, addr) {
+            code.insert(addr, (op, len));
+            pending.extend(successors(rom, op, addr, len));
+        }
+    }
+    code
+}
+
+pub fn labels(code: &BTreeMap<usize, (Op, usize)>) -> BTreeMap<usize, LabelKind> {
+    let mut labels = BTreeMap::new();
+    for &(op, _) in code.values() {
+        let (target, kind) = match op {
+            Op::Call(target) => (target, LabelKind::Subroutine),
+            Op::Jp(target) => (target, LabelKind::Jump),
+            Op::JpRegI(target) => (target, LabelKind::Jump),
+            Op::LdI(target) => (target, LabelKind::Data),
+            _ => continue,
+        };
+        let kind = labels.get(&(target as usize)).map_or(kind, |&k: &LabelKind| k.min(kind));
+        labels.insert(target as usize, kind);
+    }
+    labels
+}
+
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    let code = reachable(rom);
+    let mut labels = labels(&code);
+    let end = LOAD_ADDRESS + rom.len();
+    labels.retain(|&addr, _| addr >= LOAD_ADDRESS && addr < end);
+    for (&addr, &(op, _)) in &code {
+        if let Op::LdILong = op {
+            let offset = addr - LOAD_ADDRESS + 2;
+            let target = (rom[offset] as usize) << 8 | rom[offset + 1] as usize;
+            if target >= LOAD_ADDRESS && target < end {
+                labels.entry(target).or_insert(LabelKind::Data);
+            }
+        }
+    }
+    let code_starts: BTreeSet<usize> = code.keys().cloned().collect();
+    let names: BTreeMap<usize, String> = labels.iter()
+        .map(|(&addr, &kind)| (addr, label_name(addr, kind)))
+        .collect();
+
+    let mut out = String::new();
+    if syntax == Syntax::Chipper {
+        writeln!(out, "option binary\nalign off").unwrap();
+    }
+    let mut addr = LOAD_ADDRESS;
+    while addr < end {
+        if let Some(name) = names.get(&addr) {
+            match syntax {
+                Syntax::Chipper => writeln!(out, "{}:", name).unwrap(),
+                Syntax::Octo => writeln!(out, ": {}", name).unwrap(),
+            }
+        }
+        let instruction = code.get(&addr).filter(|&&(_, len)| {
+            (addr + 1..addr + len).all(|a| !code_starts.contains(&a) && !names.contains_key(&a))
+        });
+        if let Some(&(op, len)) = instruction {
+            let bytes = &rom[addr - LOAD_ADDRESS..addr - LOAD_ADDRESS + len];
+            let text = mnemonic(op, bytes, syntax, &names);
+            write_line(&mut out, syntax, &text, addr, bytes);
+            addr += len;
+        } else {
+            let mut len = 1;
+            while len < DATA_BYTES_PER_LINE && addr + len < end
+                && !code_starts.contains(&(addr + len)) && !names.contains_key(&(addr + len)) {
+                len += 1;
+            }
+            let bytes = &rom[addr - LOAD_ADDRESS..addr - LOAD_ADDRESS + len];
+            let text = data(bytes, syntax);
+            write_line(&mut out, syntax, &text, addr, bytes);
+            addr += len;
+        }
+    }
+    out
+}
+
+fn label_name(addr: usize, kind: LabelKind) -> String {
+    let prefix = match kind {
+        LabelKind::Subroutine => "sub",
+        LabelKind::Jump => "label",
+        LabelKind::Data => "data",
+    };
+    format!("{}_{:03X}", prefix, addr)
+}
+
+fn write_line(out: &mut String, syntax: Syntax, text: &str, addr: usize, bytes: &[u8]) {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let comment = match syntax {
+        Syntax::Chipper => ';',
+        Syntax::Octo => '#',
+    };
+    writeln!(out, "    {:<29} {} {:04X}  {}", text, comment, addr, hex.join(" ")).unwrap();
+}
+
+fn data(bytes: &[u8], syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Chipper => {
+            let values: Vec<String> = bytes.iter().map(|b| format!("#{:02X}", b)).collect();
+            format!("DB {}", values.join(", "))
+        }
+        Syntax::Octo => {
+            let values: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+            values.join(" ")
+        }
+    }
+}
+
+fn target(addr: u16, syntax: Syntax, names: &BTreeMap<usize, String>) -> String {
+    match names.get(&(addr as usize)) {
+        Some(name) => name.clone(),
+        None if syntax == Syntax::Chipper => format!("#{:03X}", addr),
+        None => format!("0x{:03X}", addr),
+    }
+}
+
+pub(crate) fn mnemonic(op: Op, bytes: &[u8], syntax: Syntax, names: &BTreeMap<usize, String>) -> String {
+    match syntax {
+        Syntax::Chipper => chipper(op, bytes, names),
+        Syntax::Octo => octo(op, bytes, names),
+    }
+}
+
+fn chipper(op: Op, bytes: &[u8], names: &BTreeMap<usize, String>) -> String {
+    let t = |addr| target(addr, Syntax::Chipper, names);
+    match op {
+        Op::Cls => "CLS".to_string(),
+        Op::Ret => "RET".to_string(),
+        Op::Jp(addr) => format!("JP {}", t(addr)),
+        Op::Call(addr) => format!("CALL {}", t(addr)),
+        Op::Se(x, kk) => format!("SE V{:X}, #{:02X}", x, kk),
+        Op::Sne(x, kk) => format!("SNE V{:X}, #{:02X}", x, kk),
+        Op::SeReg(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        Op::Ld(x, kk) => format!("LD V{:X}, #{:02X}", x, kk),
+        Op::Add(x, kk) => format!("ADD V{:X}, #{:02X}", x, kk),
+        Op::LdReg(x, y) => format!("LD V{:X}, V{:X}", x, y),
+        Op::Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+        Op::And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+        Op::Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+        Op::AddReg(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+        Op::Sub(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        Op::Shr(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+        Op::Subn(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        Op::Shl(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+        Op::SneReg(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+        Op::LdI(addr) => format!("LD I, {}", t(addr)),
+        Op::JpRegI(addr) => format!("JP V0, {}", t(addr)),
+        Op::Rnd(x, kk) => format!("RND V{:X}, #{:02X}", x, kk),
+        Op::Drw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Op::Skp(x) => format!("SKP V{:X}", x),
+        Op::Sknp(x) => format!("SKNP V{:X}", x),
+        Op::LdDT(x) => format!("LD V{:X}, DT", x),
+        Op::LdKb(x) => format!("LD V{:X}, K", x),
+        Op::SetDT(x) => format!("LD DT, V{:X}", x),
+        Op::SetST(x) => format!("LD ST, V{:X}", x),
+        Op::AddToI(x) => format!("ADD I, V{:X}", x),
+        Op::LdChr(x) => format!("LD F, V{:X}", x),
+        Op::LdHf(x) => format!("LD HF, V{:X}", x),
+        Op::LdBCD(x) => format!("LD B, V{:X}", x),
+        Op::LdRegs(x) => format!("LD [I], V{:X}", x),
+        Op::RdMem(x) => format!("LD V{:X}, [I]", x),
+        Op::Scd(n) => format!("SCD {}", n),
+        Op::Scr => "SCR".to_string(),
+        Op::Scl => "SCL".to_string(),
+        Op::Exit => "EXIT".to_string(),
+        Op::Low => "LOW".to_string(),
+        Op::High => "HIGH".to_string(),
+        Op::SaveFlags(x) => format!("LD R, V{:X}", x),
+        Op::LoadFlags(x) => format!("LD V{:X}, R", x),
+        Op::Scu(_) | Op::SaveRange(..) | Op::LoadRange(..) | Op::LdILong | Op::Plane(_)
+            | Op::Audio | Op::Pitch(_) => {
+            let words: Vec<String> = bytes.chunks(2)
+                .map(|w| format!("#{:02X}{:02X}", w[0], w[1]))
+                .collect();
+            format!("DW {}", words.join(", "))
+        }
+    }
+}
+
+fn octo(op: Op, bytes: &[u8], names: &BTreeMap<usize, String>) -> String {
+    let t = |addr| target(addr, Syntax::Octo, names);
+    match op {
+        Op::Cls => "clear".to_string(),
+        Op::Ret => "return".to_string(),
+        Op::Jp(addr) => format!("jump {}", t(addr)),
+        Op::Call(addr) if names.contains_key(&(addr as usize)) => t(addr),
+        Op::Call(addr) => format!(":call {}", t(addr)),
+        Op::Se(x, kk) => format!("if v{:x} != 0x{:02X} then", x, kk),
+        Op::Sne(x, kk) => format!("if v{:x} == 0x{:02X} then", x, kk),
+        Op::SeReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Op::Ld(x, kk) => format!("v{:x} := 0x{:02X}", x, kk),
+        Op::Add(x, kk) => format!("v{:x} += 0x{:02X}", x, kk),
+        Op::LdReg(x, y) => format!("v{:x} := v{:x}", x, y),
+        Op::Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+        Op::And(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Op::Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Op::AddReg(x, y) => format!("v{:x} += v{:x}", x, y),
+        Op::Sub(x, y) => format!("v{:x} -= v{:x}", x, y),
+        Op::Shr(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        Op::Subn(x, y) => format!("v{:x} =- v{:x}", x, y),
+        Op::Shl(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        Op::SneReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Op::LdI(addr) => format!("i := {}", t(addr)),
+        Op::JpRegI(addr) => format!("jump0 {}", t(addr)),
+        Op::Rnd(x, kk) => format!("v{:x} := random 0x{:02X}", x, kk),
+        Op::Drw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Op::Skp(x) => format!("if v{:x} -key then", x),
+        Op::Sknp(x) => format!("if v{:x} key then", x),
+        Op::LdDT(x) => format!("v{:x} := delay", x),
+        Op::LdKb(x) => format!("v{:x} := key", x),
+        Op::SetDT(x) => format!("delay := v{:x}", x),
+        Op::SetST(x) => format!("buzzer := v{:x}", x),
+        Op::AddToI(x) => format!("i += v{:x}", x),
+        Op::LdChr(x) => format!("i := hex v{:x}", x),
+        Op::LdHf(x) => format!("i := bighex v{:x}", x),
+        Op::LdBCD(x) => format!("bcd v{:x}", x),
+        Op::LdRegs(x) => format!("save v{:x}", x),
+        Op::RdMem(x) => format!("load v{:x}", x),
+        Op::Scd(n) => format!("scroll-down {}", n),
+        Op::Scu(n) => format!("scroll-up {}", n),
+        Op::Scr => "scroll-right".to_string(),
+        Op::Scl => "scroll-left".to_string(),
+        Op::Exit => "exit".to_string(),
+        Op::Low => "lores".to_string(),
+        Op::High => "hires".to_string(),
+        Op::SaveFlags(x) => format!("saveflags v{:x}", x),
+        Op::LoadFlags(x) => format!("loadflags v{:x}", x),
+        Op::SaveRange(x, y) => format!("save v{:x} - v{:x}", x, y),
+        Op::LoadRange(x, y) => format!("load v{:x} - v{:x}", x, y),
+        Op::LdILong => format!("i := long {}", t((bytes[2] as u16) << 8 | bytes[3] as u16)),
+        Op::Plane(n) => format!("plane {}", n),
+        Op::Audio => "audio".to_string(),
+        Op::Pitch(x) => format!("pitch := v{:x}", x),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reachable_follows_control_flow() {
+        let rom = [0x22, 0x08, 0x12, 0x06, 0xFF, 0xFF, 0x12, 0x06, 0x30, 0x01, 0x00, 0xE0, 0x00, 0xEE];
+        let code = reachable(&rom);
+        let addrs: Vec<usize> = code.keys().cloned().collect();
+        assert_eq!(vec![0x200, 0x202, 0x206, 0x208, 0x20A, 0x20C], addrs);
+    }
+
+    #[test]
+    fn skip_over_long_load() {
+        let rom = [0x30, 0x01, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD];
+        let code = reachable(&rom);
+        assert!(code.contains_key(&0x206));
+        assert!(!code.contains_key(&0x204));
+    }
+
+    #[test]
+    fn labels_prefer_subroutines() {
+        let rom = [0x22, 0x04, 0x12, 0x04, 0xA2, 0x04, 0x00, 0xEE];
+        let labels = labels(&reachable(&rom));
+        assert_eq!(Some(&LabelKind::Subroutine), labels.get(&0x204));
+    }
+
+    #[test]
+    fn chipper_listing() {
+        let rom = [0xA2, 0x06, 0x12, 0x04, 0x12, 0x04, 0xF0, 0x90];
+        let expected = "\
+option binary
+align off
+    LD I, data_206                ; 0200  A2 06
+    JP label_204                  ; 0202  12 04
+label_204:
+    JP label_204                  ; 0204  12 04
+data_206:
+    DB #F0, #90                   ; 0206  F0 90
+";
+        assert_eq!(expected, disassemble(&rom, Syntax::Chipper));
+    }
+
+    #[test]
+    fn octo_listing() {
+        let rom = [0x22, 0x04, 0x00, 0xFD, 0x30, 0x01, 0x00, 0xEE];
+        let expected = "    sub_204                       # 0200  22 04
+    exit                          # 0202  00 FD
+: sub_204
+    if v0 != 0x01 then            # 0204  30 01
+    return                        # 0206  00 EE
+";
+        assert_eq!(expected, disassemble(&rom, Syntax::Octo));
+    }
+
+    #[test]
+    fn xo_chip_ops_are_words_in_chipper() {
+        let rom = [0xF0, 0x00, 0x02, 0x06, 0x00, 0xFD, 0x00, 0x00];
+        let listing = disassemble(&rom, Syntax::Chipper);
+        assert!(listing.contains("DW #F000, #0206"));
+        assert!(listing.contains("data_206:"));
+    }
+}
@@ -0,0 +1,54 @@
+use chip8::keymap::Bindings;
+
+use sdl2::keyboard::Keycode;
+
+use std::{
+    collections::{HashMap}
+};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct KeyMap {
+    maping: HashMap<Keycode, u8>,
+}
+
+impl KeyMap {
+    /// Resolves the SDL key names of `bindings`.
+    pub fn new(bindings: &Bindings) -> Result<KeyMap, String> {
+        let mut maping = HashMap::new();
+        for (name, key) in bindings.iter() {
+            let keycode = Keycode::from_name(name)
+                .ok_or_else(|| format!("unknown key name {:?} for CHIP-8 key {:X}", name, key))?;
+            if let Some(other) = maping.insert(keycode, key) {
+                return Err(format!("{} is bound to both {:X} and {:X}", name, other, key));
+            }
+        }
+        Ok(KeyMap { maping })
+    }
+
+    pub fn key(&self, keycode: Keycode) -> Option<u8> {
+        self.maping.get(&keycode).cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unknown_key_name() {
+        let mut bindings = Bindings::default();
+        bindings.bind(1, &["Nope".to_string()]);
+        assert_eq!(Err("unknown key name \"Nope\" for CHIP-8 key 1".to_string()), KeyMap::new(&bindings));
+    }
+
+    #[test]
+    fn mapped_key() {
+        assert_eq!(Some(1), KeyMap::new(&Bindings::default()).unwrap().key(Keycode::NUM_1));
+        assert_eq!(Some(5), KeyMap::new(&Bindings::default()).unwrap().key(Keycode::Z));
+    }
+
+    #[test]
+    fn unmapped_key() {
+        assert_eq!(None, KeyMap::new(&Bindings::default()).unwrap().key(Keycode::NUM_0));
+    }
+}
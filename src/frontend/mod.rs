@@ -0,0 +1,210 @@
+mod keymap;
+mod recorder;
+mod slots;
+mod sound;
+
+use self::{keymap::KeyMap, recorder::Recorder};
+
+use {open_trace, Options};
+
+use chip8::{image::Palette, Cpu, FramePacer, KeyPad, Movie, RewindBuffer, Screen};
+
+use std::{
+    fs,
+    time::Instant,
+    thread::sleep
+};
+
+use sdl2::{
+    event::Event,
+    keyboard::{Mod, Scancode},
+    pixels::Color,
+    rect,
+    render::Canvas,
+    video::Window
+};
+
+pub fn run(mut c: Cpu, options: &Options, playback: Option<Movie>, mut recording: Option<Movie>)
+           -> Result<(), String> {
+    let rom_name = options.rom_name.as_str();
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let buzzer = sdl_context.audio().and_then(|audio| sound::open(&audio, options.tone))
+        .map_err(|e| eprintln!("Audio disabled: {}", e))
+        .ok();
+    let mut muted = false;
+
+    let window = video_subsystem
+        .window("Chip-8", 640, 320)
+        .position_centered()
+        .opengl()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let palette = colors(&options.palette);
+
+    let keymap = KeyMap::new(&options.keys)?;
+    let mut k = KeyPad::new();
+
+    let mut halted = false;
+    let mut rewind = RewindBuffer::new(options.rewind_frames);
+    let mut rewinding = false;
+    let mut frame = 0;
+
+    let mut tracer = open_trace(options)?;
+    let mut capture: Option<Recorder> = None;
+    let mut pacer = FramePacer::new(Instant::now());
+
+    let mut event_pump = sdl_context.event_pump()?;
+    'running: loop {
+        let frames_due = pacer.frames_due(Instant::now());
+        for _ in 0..frames_due {
+            if rewinding && (recording.is_some() || playback.is_some()) {
+                eprintln!("Rewind is disabled while recording or playing a movie");
+                rewinding = false;
+            } else if rewinding {
+                match rewind.rewind(&mut c, options.rewind_rate) {
+                    Ok(true) => halted = false,
+                    Ok(false) => (),
+                    Err(e) => eprintln!("Rewind failed: {}", e),
+                }
+            } else if !halted {
+                let (keypad, speed) = match playback {
+                    Some(ref movie) if frame < movie.frames.len() as u64 =>
+                        (&movie.frames[frame as usize], movie.speed),
+                    _ => (&k, options.speed),
+                };
+                if let Some(ref mut movie) = recording {
+                    movie.record(keypad);
+                }
+                let cycles = speed.cycles_in_frame(frame);
+                let result = match tracer {
+                    Some(ref mut tracer) => tracer.run_frame(&mut c, keypad, cycles),
+                    None => c.run_frame(keypad, cycles),
+                };
+                frame += 1;
+                if let Err(e) = result {
+                    eprintln!("CPU fault: {}", e);
+                    canvas.window_mut().set_title(&format!("Chip-8 - halted: {}", e))
+                        .map_err(|e| e.to_string())?;
+                    halted = true;
+                }
+                rewind.push(&c);
+            }
+
+            if let Some(ref mut recorder) = capture {
+                let sound = c.is_sound_active() && !halted;
+                if let Err(e) = recorder.add_frame(&c.screen, sound) {
+                    eprintln!("Recording stopped: {}", e);
+                    capture = None;
+                }
+            }
+
+            if c.has_exited() {
+                break 'running;
+            }
+        }
+
+        if let Some(ref buzzer) = buzzer {
+            if c.is_sound_active() && !muted && !halted {
+                buzzer.resume();
+            } else {
+                buzzer.pause();
+            }
+        }
+
+        if frames_due > 0 {
+            draw_screen(&mut canvas, &c.screen, &palette);
+            canvas.present();
+        }
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'running,
+                Event::KeyDown { scancode: Some(Scancode::Backspace), .. } => rewinding = true,
+                Event::KeyUp { scancode: Some(Scancode::Backspace), .. } => rewinding = false,
+                Event::KeyDown { scancode: Some(Scancode::F10), repeat: false, .. } => muted = !muted,
+                Event::KeyDown { scancode: Some(Scancode::F11), repeat: false, .. } => {
+                    let result = match capture.take() {
+                        Some(recorder) => recorder.finish().map(|path| format!("Saved {}", path)),
+                        None => Recorder::start(options.capture_format, rom_name, options.scale,
+                                                &options.palette, options.tone)
+                            .map(|recorder| {
+                                let message = format!("Recording to {}", recorder.path());
+                                capture = Some(recorder);
+                                message
+                            }),
+                    };
+                    eprintln!("{}", result.unwrap_or_else(|e| e));
+                }
+                Event::KeyDown { scancode: Some(Scancode::F12), repeat: false, .. } => {
+                    let result = recorder::screenshot(&c.screen, rom_name, options.scale, &options.palette);
+                    eprintln!("{}", result.map(|path| format!("Saved {}", path)).unwrap_or_else(|e| e));
+                }
+                Event::KeyDown { scancode: Some(s), keycode, keymod, .. } => if let Some(slot) = slots::slot(s) {
+                    let result = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        if recording.is_some() || playback.is_some() {
+                            eprintln!("Loading a state is disabled while recording or playing a movie");
+                            continue;
+                        }
+                        rewind.clear();
+                        slots::load(&mut c, rom_name, slot).map(|_| halted = false)
+                    } else {
+                        slots::save(&c, rom_name, slot)
+                    };
+                    if let Err(e) = result {
+                        eprintln!("Save state slot {}: {}", slot, e);
+                    }
+                } else if let Some(key) = keycode.and_then(|c| keymap.key(c)) {
+                    k.key_down(key)
+                },
+                Event::KeyUp { keycode: Some(c), .. } => if let Some(key) = keymap.key(c) { k.key_up(key) },
+                _ => (),
+            }
+        }
+
+        sleep(pacer.time_until_next_frame(Instant::now()))
+    }
+
+    if let Some(recorder) = capture {
+        eprintln!("Saved {}", recorder.finish()?);
+    }
+
+    if let (Some(tracer), Some(path)) = (tracer, options.trace.as_ref()) {
+        tracer.finish().map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    if let (Some(movie), Some(path)) = (recording, options.record.as_ref()) {
+        fs::write(path, movie.to_string()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    Ok(())
+}
+
+fn colors(palette: &Palette) -> [Color; 4] {
+    let mut colors = [Color::RGB(0, 0, 0); 4];
+    for (color, &[r, g, b]) in colors.iter_mut().zip(palette) {
+        *color = Color::RGB(r, g, b);
+    }
+    colors
+}
+
+fn draw_screen(canvas: &mut Canvas<Window>, screen: &Screen, palette: &[Color; 4]) {
+    canvas.set_draw_color(palette[0]);
+    canvas.clear();
+
+    let (width, height) = canvas.output_size().unwrap();
+    let (pixel_width, pixel_height) =
+        (width / screen.width() as u32, height / screen.height() as u32);
+
+    screen.pixels().filter(|p| p.on()).for_each(|p| {
+        let x = p.x() * pixel_width as usize;
+        let y = p.y() * pixel_height as usize;
+        let rectangle = rect::Rect::new(x as i32, y as i32, pixel_width, pixel_height);
+        canvas.set_draw_color(palette[p.color() as usize]);
+        canvas
+            .fill_rect(rectangle)
+            .unwrap_or_else(|_| panic!("Unable to draw: {:#?}", rectangle));
+    })
+}
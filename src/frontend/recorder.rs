@@ -0,0 +1,103 @@
+use chip8::{
+    capture::{CaptureFormat, GifEncoder, WavRecorder},
+    image::{self, Palette},
+    Screen, Tone
+};
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path
+};
+
+/// The first `<rom>.NNN.<extension>` that does not exist yet.
+fn next_path(rom_name: &str, extension: &str) -> String {
+    (0..)
+        .map(|n| format!("{}.{:03}.{}", rom_name, n, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+pub fn screenshot(screen: &Screen, rom_name: &str, scale: usize, palette: &Palette) -> Result<String, String> {
+    let path = next_path(rom_name, "png");
+    let (width, height) = image::image_size(screen, scale);
+    fs::write(&path, image::png(screen, width, height, palette)).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(path)
+}
+
+/// Captures one image per emulated frame, so recordings play at 60 fps whatever the host does.
+pub enum Recorder {
+    Gif {
+        path: String,
+        scale: usize,
+        encoder: GifEncoder<BufWriter<File>>,
+    },
+    Frames {
+        path: String,
+        scale: usize,
+        palette: Palette,
+        frame: u64,
+        audio: WavRecorder,
+    },
+}
+
+impl Recorder {
+    pub fn start(format: CaptureFormat, rom_name: &str, scale: usize, palette: &Palette, tone: Tone)
+                 -> Result<Recorder, String> {
+        match format {
+            CaptureFormat::Gif => {
+                let path = next_path(rom_name, "gif");
+                let (width, height) = image::recording_size(scale);
+                let encoder = File::create(&path)
+                    .and_then(|f| GifEncoder::new(BufWriter::new(f), width, height, palette))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                Ok(Recorder::Gif { path, scale, encoder })
+            }
+            CaptureFormat::Frames => {
+                let path = next_path(rom_name, "frames");
+                fs::create_dir(&path).map_err(|e| format!("{}: {}", path, e))?;
+                Ok(Recorder::Frames { path, scale, palette: *palette, frame: 0, audio: WavRecorder::new(tone) })
+            }
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match *self {
+            Recorder::Gif { ref path, .. } | Recorder::Frames { ref path, .. } => path,
+        }
+    }
+
+    pub fn add_frame(&mut self, screen: &Screen, sound: bool) -> Result<(), String> {
+        match *self {
+            Recorder::Gif { ref path, scale, ref mut encoder } => {
+                let (width, height) = image::recording_size(scale);
+                encoder.add_frame(image::indexed(screen, width, height))
+                    .map_err(|e| format!("{}: {}", path, e))
+            }
+            Recorder::Frames { ref path, scale, ref palette, ref mut frame, ref mut audio } => {
+                let file = format!("{}/frame_{:06}.png", path, frame);
+                *frame += 1;
+                audio.add_frame(sound);
+                let (width, height) = image::recording_size(scale);
+                fs::write(&file, image::png(screen, width, height, palette))
+                    .map_err(|e| format!("{}: {}", file, e))
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<String, String> {
+        match self {
+            Recorder::Gif { path, encoder, .. } => {
+                encoder.finish().map_err(|e| format!("{}: {}", path, e))?;
+                Ok(path)
+            }
+            Recorder::Frames { path, audio, .. } => {
+                let file = format!("{}/audio.wav", path);
+                File::create(&file)
+                    .and_then(|f| audio.finish(BufWriter::new(f)))
+                    .map_err(|e| format!("{}: {}", file, e))?;
+                Ok(path)
+            }
+        }
+    }
+}
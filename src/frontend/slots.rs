@@ -0,0 +1,41 @@
+use chip8::Cpu;
+
+use std::{
+    io::prelude::*,
+    fs::File
+};
+
+use sdl2::keyboard::Scancode;
+
+pub fn slot(scancode: Scancode) -> Option<u8> {
+    match scancode {
+        Scancode::F1 => Some(1),
+        Scancode::F2 => Some(2),
+        Scancode::F3 => Some(3),
+        Scancode::F4 => Some(4),
+        Scancode::F5 => Some(5),
+        Scancode::F6 => Some(6),
+        Scancode::F7 => Some(7),
+        Scancode::F8 => Some(8),
+        Scancode::F9 => Some(9),
+        _ => None,
+    }
+}
+
+fn path(rom_name: &str, slot: u8) -> String {
+    format!("{}.st{}", rom_name, slot)
+}
+
+pub fn save(cpu: &Cpu, rom_name: &str, slot: u8) -> Result<(), String> {
+    File::create(path(rom_name, slot))
+        .and_then(|mut f| f.write_all(&cpu.save_state()))
+        .map_err(|e| e.to_string())
+}
+
+pub fn load(cpu: &mut Cpu, rom_name: &str, slot: u8) -> Result<(), String> {
+    let mut data = Vec::new();
+    File::open(path(rom_name, slot))
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| e.to_string())?;
+    cpu.load_state(&data).map_err(|e| e.to_string())
+}
@@ -0,0 +1,24 @@
+use chip8::Tone;
+
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    AudioSubsystem
+};
+
+pub struct Buzzer {
+    tone: Tone,
+    sample_rate: u32,
+}
+
+impl AudioCallback for Buzzer {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.tone.fill(out, self.sample_rate);
+    }
+}
+
+pub fn open(audio: &AudioSubsystem, tone: Tone) -> Result<AudioDevice<Buzzer>, String> {
+    let desired = AudioSpecDesired { freq: Some(44100), channels: Some(1), samples: None };
+    audio.open_playback(None, &desired, |spec| Buzzer { tone, sample_rate: spec.freq as u32 })
+}
@@ -0,0 +1,394 @@
+use cpu::{Cpu, CpuError};
+use keypad::KeyPad;
+use scheduler::{InstructionClock, Speed};
+
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    str
+};
+
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\"><feature name=\"org.chip8.core\">\
+<reg name=\"v0\" bitsize=\"8\" regnum=\"0\"/><reg name=\"v1\" bitsize=\"8\"/>\
+<reg name=\"v2\" bitsize=\"8\"/><reg name=\"v3\" bitsize=\"8\"/>\
+<reg name=\"v4\" bitsize=\"8\"/><reg name=\"v5\" bitsize=\"8\"/>\
+<reg name=\"v6\" bitsize=\"8\"/><reg name=\"v7\" bitsize=\"8\"/>\
+<reg name=\"v8\" bitsize=\"8\"/><reg name=\"v9\" bitsize=\"8\"/>\
+<reg name=\"va\" bitsize=\"8\"/><reg name=\"vb\" bitsize=\"8\"/>\
+<reg name=\"vc\" bitsize=\"8\"/><reg name=\"vd\" bitsize=\"8\"/>\
+<reg name=\"ve\" bitsize=\"8\"/><reg name=\"vf\" bitsize=\"8\"/>\
+<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\
+<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+<reg name=\"sp\" bitsize=\"8\"/><reg name=\"dt\" bitsize=\"8\"/><reg name=\"st\" bitsize=\"8\"/>\
+</feature></target>";
+
+const REGISTER_COUNT: usize = 21;
+const INTERRUPT_POLL_CYCLES: usize = 1024;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Action {
+    Reply(String),
+    Step,
+    Continue,
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub {
+    clock: InstructionClock,
+    keypad: KeyPad,
+    breakpoints: Vec<usize>,
+}
+
+impl GdbStub {
+    pub fn new(speed: Speed) -> GdbStub {
+        GdbStub { clock: InstructionClock::new(speed), keypad: KeyPad::new(), breakpoints: Vec::new() }
+    }
+
+    pub fn serve(&mut self, cpu: &mut Cpu, mut stream: TcpStream) -> io::Result<()> {
+        while let Some(packet) = read_packet(&mut stream)? {
+            let command = match packet {
+                Packet::Command(command) => command,
+                Packet::Interrupt => {
+                    write_packet(&mut stream, &format!("S{:02x}", SIGINT))?;
+                    continue;
+                }
+            };
+            let reply = match self.handle(cpu, &command) {
+                Action::Reply(reply) => reply,
+                Action::Step => {
+                    let result = self.clock.step(cpu, &self.keypad).map(|_| ());
+                    stop_reply(cpu, result)
+                }
+                Action::Continue => {
+                    let result = self.resume(cpu, &mut stream)?;
+                    stop_reply(cpu, result)
+                }
+                Action::Detach => {
+                    write_packet(&mut stream, "OK")?;
+                    return Ok(());
+                }
+                Action::Kill => return Ok(()),
+            };
+            write_packet(&mut stream, &reply)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, cpu: &mut Cpu, command: &str) -> Action {
+        let reply = match command.split_at(command.len().min(1)) {
+            ("?", _) => format!("S{:02x}", SIGTRAP),
+            ("g", _) => (0..REGISTER_COUNT).map(|n| register(cpu, n)).collect(),
+            ("p", n) => match usize::from_str_radix(n, 16) {
+                Ok(n) if n < REGISTER_COUNT => register(cpu, n),
+                _ => "E01".to_string(),
+            },
+            ("m", args) => match parse_pair(args, ',') {
+                Some((addr, len)) => match cpu.memory().get(addr..addr.saturating_add(len)) {
+                    Some(bytes) => hex(bytes),
+                    None => "E01".to_string(),
+                },
+                None => "E01".to_string(),
+            },
+            ("M", args) => {
+                let written = args.find(':')
+                    .and_then(|colon| parse_pair(&args[..colon], ',').map(|p| (p, &args[colon + 1..])))
+                    .and_then(|((addr, len), data)| {
+                        let bytes = unhex(data).filter(|bytes| bytes.len() == len)?;
+                        cpu.write_memory(addr, &bytes).ok()
+                    });
+                if written.is_some() { "OK".to_string() } else { "E01".to_string() }
+            }
+            ("Z", args) | ("z", args) => match parse_breakpoint(args) {
+                Some(addr) => {
+                    if command.starts_with('Z') {
+                        if !self.breakpoints.contains(&addr) {
+                            self.breakpoints.push(addr);
+                        }
+                    } else {
+                        self.breakpoints.retain(|&a| a != addr);
+                    }
+                    "OK".to_string()
+                }
+                None => String::new(),
+            },
+            ("s", "") => return Action::Step,
+            ("c", "") => return Action::Continue,
+            ("D", _) => return Action::Detach,
+            ("k", _) => return Action::Kill,
+            ("H", _) => "OK".to_string(),
+            _ if command.starts_with("qSupported") => "PacketSize=4000;qXfer:features:read+".to_string(),
+            _ if command == "qAttached" => "1".to_string(),
+            _ if command.starts_with("qXfer:features:read:target.xml:") => {
+                let args = &command["qXfer:features:read:target.xml:".len()..];
+                match parse_pair(args, ',') {
+                    Some((offset, len)) => {
+                        let data = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+                        if data.len() > len {
+                            format!("m{}", &data[..len])
+                        } else {
+                            format!("l{}", data)
+                        }
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    fn resume(&mut self, cpu: &mut Cpu, stream: &mut TcpStream) -> io::Result<Result<(), CpuError>> {
+        let mut cycles = 0;
+        loop {
+            if let Err(e) = self.clock.step(cpu, &self.keypad) {
+                return Ok(Err(e));
+            }
+            if cpu.has_exited() || self.breakpoints.contains(&cpu.pc()) {
+                return Ok(Ok(()));
+            }
+            cycles += 1;
+            if cycles % INTERRUPT_POLL_CYCLES == 0 && interrupted(stream)? {
+                return Ok(Ok(()));
+            }
+        }
+    }
+}
+
+fn stop_reply(cpu: &Cpu, result: Result<(), CpuError>) -> String {
+    match result {
+        Ok(()) if cpu.has_exited() => "W00".to_string(),
+        Ok(()) => format!("S{:02x}", SIGTRAP),
+        Err(CpuError::InvalidOpcode { .. }) | Err(CpuError::InvalidDigit { .. }) =>
+            format!("S{:02x}", SIGILL),
+        Err(_) => format!("S{:02x}", SIGSEGV),
+    }
+}
+
+fn register(cpu: &Cpu, n: usize) -> String {
+    match n {
+        0..=15 => format!("{:02x}", cpu.registers()[n]),
+        16 => hex(&cpu.i.to_le_bytes()),
+        17 => hex(&(cpu.pc() as u16).to_le_bytes()),
+        18 => format!("{:02x}", cpu.stack().len()),
+        19 => format!("{:02x}", cpu.delay_timer()),
+        _ => format!("{:02x}", cpu.sound_timer()),
+    }
+}
+
+fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = stream.read(&mut byte);
+    stream.set_nonblocking(false)?;
+    match result {
+        Ok(1) => Ok(byte[0] == 0x03),
+        Ok(_) => Err(io::ErrorKind::UnexpectedEof.into()),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn parse_pair(args: &str, separator: char) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, separator);
+    let first = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let second = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((first, second))
+}
+
+fn parse_breakpoint(args: &str) -> Option<usize> {
+    let mut parts = args.split(',');
+    match parts.next()? {
+        "0" | "1" => usize::from_str_radix(parts.next()?, 16).ok(),
+        _ => None,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(data: &str) -> Option<Vec<u8>> {
+    if data.len() % 2 != 0 {
+        return None;
+    }
+    (0..data.len()).step_by(2).map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn read_byte<R: Read>(stream: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+pub fn read_packet<S: Read + Write>(stream: &mut S) -> io::Result<Option<Packet>> {
+    loop {
+        match read_byte(stream)? {
+            None => return Ok(None),
+            Some(0x03) => return Ok(Some(Packet::Interrupt)),
+            Some(b'$') => (),
+            Some(_) => continue,
+        }
+        let mut data = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum)?;
+        let valid = str::from_utf8(&sum).ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok()) == Some(checksum(&data));
+        if valid {
+            stream.write_all(b"+")?;
+            return Ok(Some(Packet::Command(String::from_utf8_lossy(&data).into_owned())));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+pub fn write_packet<W: Write>(stream: &mut W, data: &str) -> io::Result<()> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &b in data.as_bytes() {
+        if let b'#' | b'$' | b'}' | b'*' = b {
+            escaped.extend_from_slice(&[b'}', b ^ 0x20]);
+        } else {
+            escaped.push(b);
+        }
+    }
+    write!(stream, "$")?;
+    stream.write_all(&escaped)?;
+    write!(stream, "#{:02x}", checksum(&escaped))?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{
+        io::Cursor,
+        net::TcpListener,
+        thread
+    };
+
+    fn cpu(program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_program(program).unwrap();
+        cpu
+    }
+
+    fn reply(stub: &mut GdbStub, cpu: &mut Cpu, command: &str) -> String {
+        match stub.handle(cpu, command) {
+            Action::Reply(reply) => reply,
+            action => panic!("unexpected action {:?}", action),
+        }
+    }
+
+    #[test]
+    fn packets() {
+        let mut stream = Cursor::new(b"+$g#67".to_vec());
+        assert_eq!(Some(Packet::Command("g".to_string())), read_packet(&mut stream).unwrap());
+        assert_eq!(b"+", &stream.get_ref()[6..]);
+
+        let mut out = Vec::new();
+        write_packet(&mut out, "OK").unwrap();
+        assert_eq!(b"$OK#9a", out.as_slice());
+    }
+
+    #[test]
+    fn bad_checksum_is_nacked() {
+        let mut stream = Cursor::new(b"$g#00".to_vec());
+        assert_eq!(None, read_packet(&mut stream).unwrap());
+        assert_eq!(b"-", &stream.get_ref()[5..]);
+    }
+
+    #[test]
+    fn registers() {
+        let mut c = cpu(&[0x60, 0xAB, 0xA3, 0x45]);
+        let mut stub = GdbStub::new(Speed::default());
+        c.cycle(&KeyPad::new()).unwrap();
+        c.cycle(&KeyPad::new()).unwrap();
+        let all = reply(&mut stub, &mut c, "g");
+        assert_eq!(REGISTER_COUNT * 2 + 4, all.len());
+        assert!(all.starts_with("ab00"));
+        assert_eq!("4503", reply(&mut stub, &mut c, "p10"));
+        assert_eq!("0402", reply(&mut stub, &mut c, "p11"));
+        assert_eq!("E01", reply(&mut stub, &mut c, "p15"));
+    }
+
+    #[test]
+    fn memory() {
+        let mut c = cpu(&[0x60, 0xAB]);
+        let mut stub = GdbStub::new(Speed::default());
+        assert_eq!("60ab", reply(&mut stub, &mut c, "m200,2"));
+        assert_eq!("OK", reply(&mut stub, &mut c, "M300,2:1234"));
+        assert_eq!(&[0x12, 0x34], &c.memory()[0x300..0x302]);
+        assert_eq!("E01", reply(&mut stub, &mut c, "M300,2:12"));
+        assert_eq!("E01", reply(&mut stub, &mut c, "mffff,2"));
+    }
+
+    #[test]
+    fn target_xml() {
+        let mut c = Cpu::new();
+        let mut stub = GdbStub::new(Speed::default());
+        let first = reply(&mut stub, &mut c, "qXfer:features:read:target.xml:0,10");
+        assert_eq!(format!("m{}", &TARGET_XML[..0x10]), first);
+        let rest = reply(&mut stub, &mut c, "qXfer:features:read:target.xml:10,fff");
+        assert_eq!(format!("l{}", &TARGET_XML[0x10..]), rest);
+    }
+
+    #[test]
+    fn session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut c = cpu(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x06]);
+            let (stream, _) = listener.accept().unwrap();
+            GdbStub::new(Speed::default()).serve(&mut c, stream).unwrap();
+            c
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut exchange = |command: &str| {
+            write_packet(&mut client, command).unwrap();
+            let mut ack = [0];
+            client.read_exact(&mut ack).unwrap();
+            assert_eq!(b"+", &ack);
+            match read_packet(&mut client).unwrap() {
+                Some(Packet::Command(reply)) => reply,
+                packet => panic!("unexpected packet {:?}", packet),
+            }
+        };
+        assert_eq!("OK", exchange("Z0,204,2"));
+        assert_eq!("S05", exchange("s"));
+        assert_eq!("0202", exchange("p11"));
+        assert_eq!("S05", exchange("c"));
+        assert_eq!("0402", exchange("p11"));
+        assert_eq!("OK", exchange("D"));
+
+        let c = server.join().unwrap();
+        assert_eq!([1, 2, 0], c.registers()[0..3]);
+    }
+}
@@ -0,0 +1,173 @@
+use cpu::{Cpu, CpuError};
+use keypad::KeyPad;
+
+use std::{
+    error::Error,
+    fmt
+};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InputError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for InputError {}
+
+/// Key presses for headless runs, one `FRAME press|release KEY...` line per event:
+///
+/// ```text
+/// # start the game, then hold 4 for a second
+/// 30 press 5
+/// 32 release 5
+/// 60 press 4
+/// 120 release 4
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct InputScript {
+    events: Vec<(u64, u8, bool)>,
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<InputScript, InputError> {
+        let mut events = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| InputError { line: index + 1, message };
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let frame = match words.next() {
+                Some(frame) => frame.parse()
+                    .map_err(|_| error(format!("expected a frame number, got {}", frame)))?,
+                None => continue,
+            };
+            let pressed = match words.next() {
+                Some("press") => true,
+                Some("release") => false,
+                Some(action) => return Err(error(format!("expected press or release, got {}", action))),
+                None => return Err(error("missing press or release".to_string())),
+            };
+            let mut keys = 0;
+            for key in words {
+                let key = u8::from_str_radix(key, 16).ok().filter(|&k| k <= 0xF)
+                    .ok_or_else(|| error(format!("expected a key from 0 to F, got {}", key)))?;
+                events.push((frame, key, pressed));
+                keys += 1;
+            }
+            if keys == 0 {
+                return Err(error("missing keys".to_string()));
+            }
+        }
+        events.sort_by_key(|&(frame, _, _)| frame);
+        Ok(InputScript { events })
+    }
+
+    /// Applies the events of `frame` to the keypad.
+    pub fn apply(&self, frame: u64, keypad: &mut KeyPad) {
+        let start = self.events.partition_point(|&(f, _, _)| f < frame);
+        for &(_, key, pressed) in self.events[start..].iter().take_while(|&&(f, _, _)| f == frame) {
+            if pressed {
+                keypad.key_down(key);
+            } else {
+                keypad.key_up(key);
+            }
+        }
+    }
+}
+
+/// The machine state after a headless run, as a JSON object.
+pub fn state_json(cpu: &Cpu, frames: u64, fault: Option<&CpuError>) -> String {
+    let list = |values: Vec<String>| values.join(", ");
+    let fault = fault.map_or_else(|| "null".to_string(), |e| format!("\"{}\"", json_escape(&e.to_string())));
+    format!("{{
+  \"frames\": {},
+  \"exited\": {},
+  \"fault\": {},
+  \"pc\": {},
+  \"i\": {},
+  \"v\": [{}],
+  \"stack\": [{}],
+  \"delay_timer\": {},
+  \"sound_timer\": {},
+  \"hires\": {},
+  \"rom_hash\": \"{:016X}\"
+}}
+",
+        frames,
+        cpu.has_exited(),
+        fault,
+        cpu.pc(),
+        cpu.i,
+        list(cpu.registers().iter().map(|v| v.to_string()).collect()),
+        list(cpu.stack().iter().map(|a| a.to_string()).collect()),
+        cpu.delay_timer(),
+        cpu.sound_timer(),
+        cpu.screen.is_hires(),
+        cpu.rom_hash(),
+    )
+}
+
+fn json_escape(text: &str) -> String {
+    text.chars().fold(String::new(), |mut out, c| {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+        out
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn input_script() {
+        let script = InputScript::parse("# comment\n\n10 press 5 a\n12 release 5 # late\n2 press 1\n").unwrap();
+        let mut keypad = KeyPad::new();
+        script.apply(2, &mut keypad);
+        assert_eq!(0b10, keypad.bits());
+        script.apply(10, &mut keypad);
+        assert_eq!(0b100_0010_0010, keypad.bits());
+        script.apply(11, &mut keypad);
+        script.apply(12, &mut keypad);
+        assert_eq!(0b100_0000_0010, keypad.bits());
+    }
+
+    #[test]
+    fn input_script_errors() {
+        assert_eq!(Err(InputError { line: 2, message: "expected press or release, got hold".into() }),
+                   InputScript::parse("1 press 2\n3 hold 4"));
+        assert_eq!(Err(InputError { line: 1, message: "expected a key from 0 to F, got 10".into() }),
+                   InputScript::parse("1 press 10"));
+        assert_eq!(Err(InputError { line: 1, message: "expected a frame number, got x".into() }),
+                   InputScript::parse("x press 1"));
+        assert_eq!(Err(InputError { line: 1, message: "missing keys".into() }),
+                   InputScript::parse("1 release"));
+    }
+
+    #[test]
+    fn json_dump() {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&[0x22, 0x04, 0x00, 0x00, 0x6A, 0x2A, 0x00, 0x00]).unwrap();
+        cpu.cycle(&KeyPad::new()).unwrap();
+        cpu.cycle(&KeyPad::new()).unwrap();
+        let fault = cpu.cycle(&KeyPad::new()).unwrap_err();
+        let json = state_json(&cpu, 1, Some(&fault));
+        assert!(json.contains("\"frames\": 1,\n  \"exited\": false,\n"));
+        assert!(json.contains(&format!("\"fault\": \"{}\",", fault)));
+        assert!(json.contains("\"pc\": 518,"));
+        assert!(json.contains("\"v\": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0],"));
+        assert!(json.contains("\"stack\": [514],"));
+        assert_eq!("a \\\"b\\\" \\\\ \\u000a", json_escape("a \"b\" \\ \n"));
+    }
+}
@@ -0,0 +1,269 @@
+use screen::{Screen, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH};
+
+use std::fmt::Write;
+
+/// RGB colors indexed by the planes a pixel is lit in.
+pub type Palette = [[u8; 3]; 4];
+
+pub const DEFAULT_PALETTE: Palette = [[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]];
+
+const ASCII_COLORS: [char; 4] = ['.', '#', '+', '*'];
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Pbm,
+    Ascii,
+}
+
+impl ImageFormat {
+    /// Picks the format from the file extension, ASCII art for anything but `.png` and `.pbm`.
+    pub fn from_path(path: &str) -> ImageFormat {
+        match path.rsplit('.').next().map(|e| e.to_lowercase()).as_deref() {
+            Some("png") => ImageFormat::Png,
+            Some("pbm") => ImageFormat::Pbm,
+            _ => ImageFormat::Ascii,
+        }
+    }
+}
+
+/// Parses four comma separated `RRGGBB` colors.
+pub fn parse_palette(text: &str) -> Option<Palette> {
+    let mut palette = DEFAULT_PALETTE;
+    let mut colors = text.split(',');
+    for color in palette.iter_mut() {
+        let hex = colors.next()?.trim().trim_start_matches('#');
+        if hex.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        *color = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+    }
+    match colors.next() {
+        Some(_) => None,
+        None => Some(palette),
+    }
+}
+
+/// The size of an image of `screen` at `scale`: low resolution pixels are `scale` by `scale`
+/// squares and high resolution ones half that, like the SDL window, but at least one pixel.
+pub fn image_size(screen: &Screen, scale: usize) -> (usize, usize) {
+    let pixel = (scale.max(1) * LORES_WIDTH / screen.width()).max(1);
+    (screen.width() * pixel, screen.height() * pixel)
+}
+
+/// The size of every frame of a recording at `scale`, which fits either resolution without
+/// dropping pixels.
+pub fn recording_size(scale: usize) -> (usize, usize) {
+    ((LORES_WIDTH * scale.max(1)).max(HIRES_WIDTH), (LORES_HEIGHT * scale.max(1)).max(HIRES_HEIGHT))
+}
+
+/// The palette index of every pixel of a `width` by `height` image of the screen.
+pub fn indexed(screen: &Screen, width: usize, height: usize) -> Vec<u8> {
+    let colors: Vec<u8> = screen.pixels().map(|p| p.color() & 3).collect();
+    let mut out = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = y * screen.height() / height * screen.width();
+        out.extend((0..width).map(|x| colors[row + x * screen.width() / width]));
+    }
+    out
+}
+
+pub fn encode(screen: &Screen, format: ImageFormat, scale: usize, palette: &Palette) -> Vec<u8> {
+    match format {
+        ImageFormat::Png => {
+            let (width, height) = image_size(screen, scale);
+            png(screen, width, height, palette)
+        }
+        ImageFormat::Pbm => pbm(screen),
+        ImageFormat::Ascii => ascii(screen).into_bytes(),
+    }
+}
+
+pub fn ascii(screen: &Screen) -> String {
+    let mut out = String::with_capacity((screen.width() + 1) * screen.height());
+    for (n, pixel) in screen.pixels().enumerate() {
+        out.push(ASCII_COLORS[pixel.color() as usize & 3]);
+        if (n + 1) % screen.width() == 0 {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// A plain (P1) PBM, where any lit plane is black.
+pub fn pbm(screen: &Screen) -> Vec<u8> {
+    let mut out = String::new();
+    writeln!(out, "P1\n{} {}", screen.width(), screen.height()).unwrap();
+    let rows: Vec<u8> = screen.pixels().map(|p| if p.on() { b'1' } else { b'0' }).collect();
+    for row in rows.chunks(screen.width()) {
+        let row: Vec<String> = row.iter().map(|&b| (b as char).to_string()).collect();
+        writeln!(out, "{}", row.join(" ")).unwrap();
+    }
+    out.into_bytes()
+}
+
+/// An indexed PNG of the screen stretched to `width` by `height`.
+pub fn png(screen: &Screen, width: usize, height: usize, palette: &Palette) -> Vec<u8> {
+    let pixels = indexed(screen, width, height);
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width) {
+        raw.push(0);
+        raw.extend(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend(&(width as u32).to_be_bytes());
+    header.extend(&(height as u32).to_be_bytes());
+    header.extend(&[8, 3, 0, 0, 0]);
+    let palette: Vec<u8> = palette.iter().flat_map(|rgb| rgb.iter().cloned()).collect();
+
+    let mut out = PNG_SIGNATURE.to_vec();
+    png_chunk(&mut out, b"IHDR", &header);
+    png_chunk(&mut out, b"PLTE", &palette);
+    png_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(&crc.to_be_bytes());
+}
+
+/// A zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend(&len.to_le_bytes());
+        out.extend(&(!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn screen() -> Screen {
+        let mut screen = Screen::new();
+        screen.set_pixel_value(0, 0, true);
+        screen.set_plane_pixel_value(2, 2, 0, true);
+        screen
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(ImageFormat::Png, ImageFormat::from_path("shots/frame.PNG"));
+        assert_eq!(ImageFormat::Pbm, ImageFormat::from_path("frame.pbm"));
+        assert_eq!(ImageFormat::Ascii, ImageFormat::from_path("frame.txt"));
+    }
+
+    #[test]
+    fn palettes() {
+        let palette = parse_palette("000000,#FFFFFF, 102030,aabbcc").unwrap();
+        assert_eq!([[0, 0, 0], [255, 255, 255], [0x10, 0x20, 0x30], [0xAA, 0xBB, 0xCC]], palette);
+        assert_eq!(None, parse_palette("000000,FFFFFF,102030"));
+        assert_eq!(None, parse_palette("000000,FFFFFF,102030,AABBCC,000000"));
+        assert_eq!(None, parse_palette("000000,FFFFFF,102030,AABBCG"));
+    }
+
+    #[test]
+    fn hires_is_drawn_at_half_scale() {
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+        screen.set_pixel_value(1, 0, true);
+        let (width, height) = image_size(&screen, 2);
+        let pixels = indexed(&screen, width, height);
+        assert_eq!((128, 64), (width, height));
+        assert_eq!((128, 64), image_size(&screen, 1));
+        assert_eq!((64, 32), image_size(&Screen::new(), 1));
+        assert_eq!((128, 64), recording_size(1));
+        assert_eq!((192, 96), recording_size(3));
+        assert_eq!([0, 1, 0], pixels[..3]);
+        assert_eq!(0, pixels[width + 1]);
+        assert_eq!(indexed(&Screen::new(), width, height), vec![0; width * height]);
+    }
+
+    #[test]
+    fn ascii_art() {
+        let text = ascii(&screen());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(32, lines.len());
+        assert_eq!(format!("#.+{}", ".".repeat(61)), lines[0]);
+        assert_eq!(".".repeat(64), lines[1]);
+    }
+
+    #[test]
+    fn plain_pbm() {
+        let text = String::from_utf8(pbm(&screen())).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(["P1", "64 32"], lines[..2]);
+        assert!(lines[2].starts_with("1 0 1 0 0"));
+        assert_eq!(34, lines.len());
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        assert_eq!(0x091E_01DE, adler32(b"123456789"));
+    }
+
+    #[test]
+    fn png_layout() {
+        let png = png(&screen(), 128, 64, &DEFAULT_PALETTE);
+        assert_eq!(PNG_SIGNATURE, png[..8]);
+        assert_eq!(b"IHDR", &png[12..16]);
+        assert_eq!([0, 0, 0, 128, 0, 0, 0, 64, 8, 3], png[16..26]);
+        assert_eq!(b"IEND", &png[png.len() - 8..png.len() - 4]);
+
+        // The image data is stored uncompressed: a filter byte then one index per pixel.
+        let idat = png.windows(4).position(|w| w == b"IDAT").unwrap() + 4;
+        let length = u32::from_be_bytes([png[idat - 8], png[idat - 7], png[idat - 6], png[idat - 5]]);
+        assert_eq!(2 + 5 + 129 * 64 + 4, length as usize);
+        assert_eq!([0, 1, 1, 0, 0, 2, 2], png[idat + 7..idat + 14]);
+    }
+
+    #[test]
+    fn stored_blocks() {
+        let data = vec![7; MAX_STORED_BLOCK + 10];
+        let stream = zlib_stored(&data);
+        assert_eq!(0, stream[2]);
+        assert_eq!(1, stream[2 + 5 + MAX_STORED_BLOCK]);
+        assert_eq!(2 + 5 * 2 + data.len() + 4, stream.len());
+    }
+}
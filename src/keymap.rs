@@ -0,0 +1,334 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt
+};
+
+pub const LAYOUTS: [&str; 5] = ["qwerty", "azerty", "qwertz", "dvorak", "numpad"];
+
+const DEFAULT_LAYOUT: &str = "azerty";
+
+/// The CHIP-8 keys in keypad order, matching the rows of the layouts below.
+const KEYPAD: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+const QWERTY: [&str; 16] = ["1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V"];
+const AZERTY: [&str; 16] = ["1", "2", "3", "4", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V"];
+const QWERTZ: [&str; 16] = ["1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Y", "X", "C", "V"];
+const DVORAK: [&str; 16] = ["1", "2", "3", "4", "'", ",", ".", "P", "A", "O", "E", "U", ";", "Q", "J", "K"];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KeyMapError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl KeyMapError {
+    fn new(line: Option<usize>, message: String) -> KeyMapError {
+        KeyMapError { line, message }
+    }
+}
+
+impl fmt::Display for KeyMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for KeyMapError {}
+
+/// Host keys, by SDL key name, bound to CHIP-8 keys.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Bindings {
+    keys: BTreeMap<String, u8>,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings::layout(DEFAULT_LAYOUT).unwrap()
+    }
+}
+
+impl Bindings {
+    /// One of `LAYOUTS`: the 4x4 block under 1-4 for keyboard layouts, or the numpad digits
+    /// and the A-F letters for `numpad`.
+    pub fn layout(name: &str) -> Result<Bindings, KeyMapError> {
+        let names = match name.to_lowercase().as_str() {
+            "qwerty" => QWERTY,
+            "azerty" => AZERTY,
+            "qwertz" => QWERTZ,
+            "dvorak" => DVORAK,
+            "numpad" => {
+                let mut keys: BTreeMap<String, u8> =
+                    (0..10).map(|n| (format!("Keypad {}", n), n)).collect();
+                keys.extend((0xA..=0xF).map(|n| (format!("{:X}", n), n)));
+                return Ok(Bindings { keys });
+            }
+            _ => return Err(KeyMapError::new(None, format!(
+                "unknown layout {}, expected one of {}", name, LAYOUTS.join(", ")))),
+        };
+        let keys = names.iter().map(|name| name.to_string()).zip(KEYPAD.iter().cloned()).collect();
+        Ok(Bindings { keys })
+    }
+
+    /// Binds `names` to `key` instead of its current host keys. Names bound to another key
+    /// move to this one.
+    pub fn bind(&mut self, key: u8, names: &[String]) {
+        self.keys.retain(|_, bound| *bound != key);
+        for name in names {
+            self.keys.insert(name.clone(), key);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.keys.iter().map(|(name, key)| (name.as_str(), *key))
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+struct Section {
+    layout: Option<String>,
+    keys: Vec<(u8, Vec<String>)>,
+}
+
+/// A keymap file, in a small subset of TOML:
+///
+/// ```toml
+/// layout = "qwerty"           # one of LAYOUTS, azerty by default
+/// 5 = "Space"                 # CHIP-8 key = SDL key name, or a list of them
+///
+/// [rom."pong.rom"]            # overrides for one ROM, by file name
+/// 1 = ["W", "Up"]
+/// 4 = ["S", "Down"]
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct KeyMapConfig {
+    global: Section,
+    roms: Vec<(String, Section)>,
+}
+
+impl KeyMapConfig {
+    pub fn parse(text: &str) -> Result<KeyMapConfig, KeyMapError> {
+        let mut config = KeyMapConfig::default();
+        let mut rom: Option<usize> = None;
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| KeyMapError::new(Some(index + 1), message);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                let end = line.find(']').ok_or_else(|| error("unclosed table header".to_string()))?;
+                expect_end(&line[end + 1..]).map_err(error)?;
+                let name = parse_rom_table(&line[1..end]).map_err(error)?;
+                if config.roms.iter().any(|(rom, _)| *rom == name) {
+                    return Err(error(format!("duplicate table for {}", name)));
+                }
+                config.roms.push((name, Section::default()));
+                rom = Some(config.roms.len() - 1);
+                continue;
+            }
+
+            let equals = line.find('=').ok_or_else(|| error("expected key = value".to_string()))?;
+            let (setting, value) = (unquote(line[..equals].trim()), &line[equals + 1..]);
+            let section = match rom {
+                Some(n) => &mut config.roms[n].1,
+                None => &mut config.global,
+            };
+            if setting == "layout" {
+                let name = parse_string(value).map_err(error)?;
+                Bindings::layout(&name).map_err(|e| error(e.message))?;
+                if section.layout.replace(name).is_some() {
+                    return Err(error("layout is set twice".to_string()));
+                }
+                continue;
+            }
+
+            let key = u8::from_str_radix(&setting, 16).ok().filter(|_| setting.len() == 1)
+                .ok_or_else(|| error(format!("expected layout or a CHIP-8 key from 0 to F, got {}", setting)))?;
+            let names = parse_names(value).map_err(error)?;
+            if section.keys.iter().any(|&(bound, _)| bound == key) {
+                return Err(error(format!("key {:X} is bound twice", key)));
+            }
+            for name in &names {
+                if let Some(&(other, _)) = section.keys.iter().find(|(_, others)| others.contains(name)) {
+                    return Err(error(format!("{} is bound to both {:X} and {:X}", name, other, key)));
+                }
+            }
+            section.keys.push((key, names));
+        }
+        Ok(config)
+    }
+
+    /// The bindings for a ROM: the layout from `layout`, the ROM's table, the top of the file
+    /// or the default, in that order, then the file's key bindings and the ROM's on top.
+    pub fn bindings(&self, rom_name: &str, layout: Option<&str>) -> Result<Bindings, KeyMapError> {
+        let file_name = rom_name.rsplit(['/', '\\']).next().unwrap_or(rom_name);
+        let rom = self.roms.iter()
+            .find(|(name, _)| name == file_name || name == rom_name)
+            .map(|(_, section)| section);
+        let layout = layout
+            .or_else(|| rom.and_then(|s| s.layout.as_deref()))
+            .or(self.global.layout.as_deref())
+            .unwrap_or(DEFAULT_LAYOUT);
+        let mut bindings = Bindings::layout(layout)?;
+        for section in Some(&self.global).into_iter().chain(rom) {
+            for (key, names) in &section.keys {
+                bindings.bind(*key, names);
+            }
+        }
+        Ok(bindings)
+    }
+}
+
+fn expect_end(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected {}", rest))
+    }
+}
+
+fn unquote(text: &str) -> String {
+    text.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(text).to_string()
+}
+
+/// `rom."name"` or `rom.name`.
+fn parse_rom_table(header: &str) -> Result<String, String> {
+    match header.trim().strip_prefix("rom.") {
+        Some(name) if !name.trim().is_empty() => Ok(unquote(name.trim())),
+        _ => Err(format!("unknown table [{}], expected [rom.\"file name\"]", header.trim())),
+    }
+}
+
+/// Reads a basic string at the start of `text` and returns it with the rest of the text.
+fn read_string(text: &str) -> Result<(String, &str), String> {
+    let text = text.trim_start();
+    let mut chars = text.strip_prefix('"').ok_or("expected a quoted string")?.char_indices();
+    let mut value = String::new();
+    while let Some((n, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[n + 2..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                _ => return Err("only \\\" and \\\\ escapes are supported".to_string()),
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unclosed string".to_string())
+}
+
+fn parse_string(text: &str) -> Result<String, String> {
+    let (value, rest) = read_string(text)?;
+    expect_end(rest)?;
+    Ok(value)
+}
+
+/// A key name or an array of them.
+fn parse_names(text: &str) -> Result<Vec<String>, String> {
+    let text = text.trim_start();
+    let mut rest = match text.strip_prefix('[') {
+        Some(rest) => rest,
+        None => return parse_string(text).map(|name| vec![name]),
+    };
+    let mut names = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(end) = rest.strip_prefix(']') {
+            expect_end(end)?;
+            break;
+        }
+        let (name, after) = read_string(rest)?;
+        names.push(name);
+        rest = after.trim_start();
+        match rest.strip_prefix(',') {
+            Some(after) => rest = after,
+            None if rest.starts_with(']') => (),
+            None => return Err("expected a comma separated list of strings".to_string()),
+        }
+    }
+    if names.is_empty() {
+        return Err("expected at least one key name".to_string());
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bound(bindings: &Bindings, key: u8) -> Vec<&str> {
+        bindings.iter().filter(|&(_, k)| k == key).map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn layouts() {
+        for name in LAYOUTS.iter() {
+            let bindings = Bindings::layout(name).unwrap();
+            for key in 0..16 {
+                assert_eq!(1, bound(&bindings, key).len(), "{} {:X}", name, key);
+            }
+        }
+        assert_eq!(vec!["Z"], bound(&Bindings::default(), 5));
+        assert_eq!(vec!["W"], bound(&Bindings::layout("QWERTY").unwrap(), 5));
+        assert_eq!(vec!["Y"], bound(&Bindings::layout("qwertz").unwrap(), 0xA));
+        assert_eq!(vec![","], bound(&Bindings::layout("dvorak").unwrap(), 5));
+        assert_eq!(vec!["Keypad 5"], bound(&Bindings::layout("numpad").unwrap(), 5));
+        assert_eq!(vec!["B"], bound(&Bindings::layout("numpad").unwrap(), 0xB));
+        assert_eq!("unknown layout colemak, expected one of qwerty, azerty, qwertz, dvorak, numpad",
+                   Bindings::layout("colemak").unwrap_err().to_string());
+    }
+
+    #[test]
+    fn overrides() {
+        let config = KeyMapConfig::parse(r#"
+# everyone
+layout = "qwerty"
+5 = "Space"
+
+[rom."pong.rom"]
+1 = ["W", "Up"]   # steals W from 5
+4 = "Down"
+
+[rom.tetris]
+layout = "numpad"
+"#).unwrap();
+        let pong = config.bindings("roms/pong.rom", None).unwrap();
+        assert_eq!(vec!["Up", "W"], bound(&pong, 1));
+        assert_eq!(vec!["Space"], bound(&pong, 5));
+        assert_eq!(vec!["Down"], bound(&pong, 4));
+        assert_eq!(vec!["Space"], bound(&config.bindings("brix.rom", None).unwrap(), 5));
+        assert_eq!(vec!["Q"], bound(&config.bindings("brix.rom", None).unwrap(), 4));
+        assert_eq!(vec!["Keypad 7"], bound(&config.bindings("tetris", None).unwrap(), 7));
+        assert_eq!(vec!["A"], bound(&config.bindings("tetris", Some("qwerty")).unwrap(), 7));
+        assert_eq!(KeyMapConfig::default().bindings("pong.rom", None).unwrap(), Bindings::default());
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text: &str| KeyMapConfig::parse(text).unwrap_err().to_string();
+        assert_eq!("line 1: unknown layout bepo, expected one of qwerty, azerty, qwertz, dvorak, numpad",
+                   error("layout = \"bepo\""));
+        assert_eq!("line 2: expected layout or a CHIP-8 key from 0 to F, got 10", error("\n10 = \"X\""));
+        assert_eq!("line 1: expected a quoted string", error("1 = X"));
+        assert_eq!("line 1: unclosed string", error("1 = \"X"));
+        assert_eq!("line 1: unexpected junk", error("1 = \"X\" junk"));
+        assert_eq!("line 2: key 1 is bound twice", error("1 = \"X\"\n1 = \"Y\""));
+        assert_eq!("line 2: X is bound to both 1 and 2", error("1 = \"X\"\n2 = [\"Y\", \"X\"]"));
+        assert_eq!("line 1: expected at least one key name", error("1 = []"));
+        assert_eq!("line 1: expected a comma separated list of strings", error("1 = [\"X\" \"Y\"]"));
+        assert_eq!("line 1: unknown table [keys], expected [rom.\"file name\"]", error("[keys]"));
+        assert_eq!("line 2: duplicate table for a.ch8", error("[rom.a.ch8]\n[rom.\"a.ch8\"]"));
+        assert_eq!("line 1: expected key = value", error("layout"));
+    }
+}
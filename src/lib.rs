@@ -0,0 +1,40 @@
+extern crate rand;
+
+pub mod analysis;
+pub mod asm;
+pub mod audio;
+pub mod capture;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod gdb;
+pub mod headless;
+pub mod image;
+pub mod keymap;
+pub mod keypad;
+pub mod lockstep;
+pub mod movie;
+pub mod octo;
+pub mod opcodes;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod scheduler;
+pub mod screen;
+pub mod state;
+pub mod trace;
+
+pub use audio::{Tone, Waveform};
+pub use cpu::{Cpu, CpuError};
+pub use debugger::Debugger;
+pub use gdb::GdbStub;
+pub use keypad::KeyPad;
+pub use movie::{Movie, MovieError};
+pub use opcodes::{decode, Op};
+pub use quirks::Quirks;
+pub use random::{RandomMode, RandomSource};
+pub use rewind::RewindBuffer;
+pub use scheduler::{FramePacer, InstructionClock, Speed};
+pub use screen::{Pixel, Screen};
+pub use state::StateError;
+pub use trace::{TraceFormat, Tracer};
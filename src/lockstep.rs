@@ -0,0 +1,300 @@
+use cpu::{Cpu, CpuError};
+use headless::InputScript;
+use image;
+use keypad::KeyPad;
+use trace::TraceEntry;
+
+use std::fmt;
+
+const MAX_MEMORY_DIFFERENCES: usize = 8;
+
+/// One machine's view of the cycle where two runs disagree. Traces only record registers,
+/// so the other fields are `None` for them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Side {
+    pub name: String,
+    pub result: Result<TraceEntry, CpuError>,
+    pub stack: Option<Vec<u16>>,
+    pub exited: Option<bool>,
+    /// The differing bytes, by address.
+    pub memory: Vec<(usize, u8)>,
+    pub screen: Option<String>,
+}
+
+impl Side {
+    fn from_cpu(name: &str, result: Result<TraceEntry, CpuError>, cpu: &Cpu) -> Side {
+        Side {
+            name: name.to_string(),
+            result,
+            stack: Some(cpu.stack().to_vec()),
+            exited: Some(cpu.has_exited()),
+            memory: Vec::new(),
+            screen: Some(image::ascii(&cpu.screen)),
+        }
+    }
+}
+
+/// The first cycle where two runs disagree, shown side by side with `!` on the differences.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub cycle: u64,
+    pub frame: u64,
+    pub left: Side,
+    pub right: Side,
+}
+
+fn row(f: &mut fmt::Formatter, label: &str, left: Option<String>, right: Option<String>) -> fmt::Result {
+    let mark = match (&left, &right) {
+        (Some(l), Some(r)) if l != r => '!',
+        _ => ' ',
+    };
+    let show = |side: Option<String>| side.unwrap_or_else(|| "-".to_string());
+    writeln!(f, "{} {:<8} {:<32} {}", mark, label, show(left), show(right))
+}
+
+fn entry_row<F: Fn(&TraceEntry) -> String>(f: &mut fmt::Formatter, label: &str, left: Option<&TraceEntry>,
+                                            right: Option<&TraceEntry>, show: F) -> fmt::Result {
+    row(f, label, left.map(&show), right.map(&show))
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "diverged at cycle {}, frame {}", self.cycle, self.frame)?;
+        writeln!(f, "  {:<8} {:<32} {}", "", self.left.name, self.right.name)?;
+        let (left, right) = (self.left.result.as_ref().ok(), self.right.result.as_ref().ok());
+        entry_row(f, "pc", left, right, |e| format!("{:04X}", e.pc))?;
+        entry_row(f, "opcode", left, right, |e| {
+            let op = e.op().map_or_else(|| "?".to_string(), |op| format!("{:?}", op));
+            format!("{:04X} {}", e.opcode, op)
+        })?;
+        entry_row(f, "I", left, right, |e| format!("{:04X}", e.i))?;
+        entry_row(f, "DT", left, right, |e| format!("{:02X}", e.delay_timer))?;
+        entry_row(f, "ST", left, right, |e| format!("{:02X}", e.sound_timer))?;
+        for n in 0..16 {
+            entry_row(f, &format!("V{:X}", n), left, right, |e| format!("{:02X}", e.v[n]))?;
+        }
+        let stack = |side: &Side| side.stack.as_ref().map(|s| format!("{:04X?}", s));
+        row(f, "stack", stack(&self.left), stack(&self.right))?;
+        row(f, "exited", self.left.exited.map(|e| e.to_string()), self.right.exited.map(|e| e.to_string()))?;
+        let fault = |side: &Side| {
+            Some(side.result.as_ref().err().map_or_else(|| "none".to_string(), |e| e.to_string()))
+        };
+        row(f, "fault", fault(&self.left), fault(&self.right))?;
+        for (&(addr, l), &(_, r)) in self.left.memory.iter().zip(&self.right.memory) {
+            row(f, &format!("[{:04X}]", addr), Some(format!("{:02X}", l)), Some(format!("{:02X}", r)))?;
+        }
+        if let (Some(l), Some(r)) = (&self.left.screen, &self.right.screen) {
+            if l != r {
+                for (l, r) in l.lines().zip(r.lines()) {
+                    writeln!(f, "{} {}  {}", if l == r { ' ' } else { '!' }, l, r)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Running,
+    /// Both machines exited, or faulted the same way.
+    Stopped(Option<CpuError>),
+    Diverged(Box<Divergence>),
+}
+
+fn step(cpu: &mut Cpu, keypad: &KeyPad, cycle: u64, frame: u64) -> Result<TraceEntry, CpuError> {
+    let pc = cpu.pc();
+    let opcode = cpu.opcode()?;
+    cpu.cycle(keypad)?;
+    Ok(TraceEntry::after(cycle, frame, pc, opcode, cpu))
+}
+
+/// Runs two machines, e.g. with different quirks, on the same input and compares their
+/// registers, memory and screen after every instruction.
+pub struct Lockstep {
+    names: (String, String),
+    left: Cpu,
+    right: Cpu,
+    cycle: u64,
+    frame: u64,
+}
+
+impl Lockstep {
+    pub fn new(left: (&str, Cpu), right: (&str, Cpu)) -> Lockstep {
+        Lockstep {
+            names: (left.0.to_string(), right.0.to_string()),
+            left: left.1,
+            right: right.1,
+            cycle: 0,
+            frame: 0,
+        }
+    }
+
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    pub fn run_frame(&mut self, keypad: &KeyPad, cycles: usize) -> Outcome {
+        for _ in 0..cycles {
+            if self.left.has_exited() && self.right.has_exited() {
+                return Outcome::Stopped(None);
+            }
+            let left = step(&mut self.left, keypad, self.cycle, self.frame);
+            let right = step(&mut self.right, keypad, self.cycle, self.frame);
+            if let Some(divergence) = self.compare(left, right) {
+                return Outcome::Diverged(Box::new(divergence));
+            }
+            if let Err(e) = left {
+                return Outcome::Stopped(Some(e));
+            }
+            self.cycle += 1;
+        }
+        self.left.update_timers();
+        self.right.update_timers();
+        self.frame += 1;
+        Outcome::Running
+    }
+
+    fn compare(&self, left: Result<TraceEntry, CpuError>, right: Result<TraceEntry, CpuError>)
+               -> Option<Divergence> {
+        let mut left = Side::from_cpu(&self.names.0, left, &self.left);
+        let mut right = Side::from_cpu(&self.names.1, right, &self.right);
+        let (left_memory, right_memory) = (self.left.memory(), self.right.memory());
+        if left_memory != right_memory {
+            let differences = left_memory.iter().zip(right_memory).enumerate()
+                .filter(|&(_, (l, r))| l != r)
+                .take(MAX_MEMORY_DIFFERENCES);
+            for (addr, (&l, &r)) in differences {
+                left.memory.push((addr, l));
+                right.memory.push((addr, r));
+            }
+        }
+        let same = left.result == right.result && left.stack == right.stack && left.exited == right.exited
+            && left.memory.is_empty() && left.screen == right.screen;
+        if same {
+            return None;
+        }
+        Some(Divergence { cycle: self.cycle, frame: self.frame, left, right })
+    }
+}
+
+/// Replays `trace`, recorded by another emulator or an older build in the binary trace
+/// format, and returns the first instruction where `cpu` disagrees with it. The trace must
+/// be unfiltered: every instruction, with frames counted from 0.
+pub fn compare_trace(cpu: &mut Cpu, name: &str, trace: &[TraceEntry], input: &InputScript)
+                     -> Result<u64, Box<Divergence>> {
+    let mut keypad = KeyPad::new();
+    let mut frame = 0;
+    input.apply(frame, &mut keypad);
+    for (cycle, expected) in trace.iter().enumerate() {
+        while frame < expected.frame {
+            cpu.update_timers();
+            frame += 1;
+            input.apply(frame, &mut keypad);
+        }
+        let actual = step(cpu, &keypad, cycle as u64, frame);
+        if actual.as_ref() != Ok(expected) {
+            let trace_side = Side {
+                name: "trace".to_string(),
+                result: Ok(*expected),
+                stack: None,
+                exited: None,
+                memory: Vec::new(),
+                screen: None,
+            };
+            return Err(Box::new(Divergence {
+                cycle: cycle as u64,
+                frame,
+                left: Side::from_cpu(name, actual, cpu),
+                right: trace_side,
+            }));
+        }
+    }
+    Ok(trace.len() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use quirks::Quirks;
+    use trace::{TraceFormat, Tracer};
+
+    // 200: LD V1, 3    202: SHR V0, V1    204: LD I, 300    206: LD [I], V0    208: JP 208
+    const PROGRAM: [u8; 10] = [0x61, 0x03, 0x80, 0x16, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x08];
+
+    fn cpu(quirks: Quirks) -> Cpu {
+        let mut cpu = Cpu::with_quirks(quirks);
+        cpu.load_program(&PROGRAM).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn same_machines_agree() {
+        let mut lockstep = Lockstep::new(("a", cpu(Quirks::COSMAC_VIP)), ("b", cpu(Quirks::COSMAC_VIP)));
+        for _ in 0..3 {
+            assert_eq!(Outcome::Running, lockstep.run_frame(&KeyPad::new(), 10));
+        }
+        assert_eq!(30, lockstep.cycle());
+    }
+
+    #[test]
+    fn quirks_diverge() {
+        let mut lockstep = Lockstep::new(("vip", cpu(Quirks::COSMAC_VIP)), ("schip", cpu(Quirks::SUPER_CHIP)));
+        let divergence = match lockstep.run_frame(&KeyPad::new(), 10) {
+            Outcome::Diverged(divergence) => divergence,
+            outcome => panic!("{:?}", outcome),
+        };
+        assert_eq!((1, 0), (divergence.cycle, divergence.frame));
+        assert_eq!(0x202, divergence.left.result.as_ref().unwrap().pc);
+        assert_eq!(1, divergence.left.result.as_ref().unwrap().v[0]);
+        assert_eq!(0, divergence.right.result.as_ref().unwrap().v[0]);
+        let text = divergence.to_string();
+        assert!(text.starts_with("diverged at cycle 1, frame 0\n"));
+        assert!(text.contains("\n! V0       01                               00\n"));
+        assert!(text.contains("\n  V1       03                               03\n"));
+    }
+
+    #[test]
+    fn memory_differences() {
+        let mut left = cpu(Quirks::default());
+        let mut right = cpu(Quirks::default());
+        left.write_memory(0x400, &[1]).unwrap();
+        right.write_memory(0x400, &[2]).unwrap();
+        let mut lockstep = Lockstep::new(("left", left), ("right", right));
+        match lockstep.run_frame(&KeyPad::new(), 1) {
+            Outcome::Diverged(divergence) => {
+                assert_eq!(vec![(0x400, 1)], divergence.left.memory);
+                assert!(divergence.to_string().contains("\n! [0400]   01"));
+            }
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn faults() {
+        let mut left = Cpu::new();
+        left.load_program(&[0x00, 0xEE]).unwrap();
+        let mut right = Cpu::new();
+        right.load_program(&[0x00, 0xEE]).unwrap();
+        let mut lockstep = Lockstep::new(("left", left), ("right", right));
+        assert_eq!(Outcome::Stopped(Some(CpuError::StackUnderflow)), lockstep.run_frame(&KeyPad::new(), 1));
+    }
+
+    #[test]
+    fn against_a_trace() {
+        let mut traced = cpu(Quirks::COSMAC_VIP);
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Binary);
+        for _ in 0..2 {
+            tracer.run_frame(&mut traced, &KeyPad::new(), 3).unwrap();
+        }
+        let trace = ::trace::read_binary(&tracer.finish().unwrap()[..]).unwrap();
+
+        let input = InputScript::default();
+        assert_eq!(Ok(6), compare_trace(&mut cpu(Quirks::COSMAC_VIP), "vip", &trace, &input));
+        let divergence = compare_trace(&mut cpu(Quirks::SUPER_CHIP), "schip", &trace, &input).unwrap_err();
+        assert_eq!(1, divergence.cycle);
+        assert_eq!(None, divergence.right.screen);
+        assert!(divergence.to_string().contains("\n  stack    []                               -\n"));
+    }
+}
@@ -1,91 +1,403 @@
+extern crate chip8;
 extern crate rand;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 
-mod cpu;
-mod keypad;
-mod opcodes;
-mod screen;
+mod commands;
+#[cfg(feature = "sdl")]
+mod frontend;
 
-use screen::Screen;
+use chip8::{
+    headless::{self, InputScript},
+    capture::CaptureFormat,
+    keymap::{Bindings, KeyMapConfig},
+    image::{self, ImageFormat, Palette},
+    octo, Cpu, Debugger, GdbStub, KeyPad, Movie, Quirks, RandomMode, RandomSource, Speed, Tone,
+    TraceFormat, Tracer, Waveform
+};
 
 use std::{
-    io::prelude::*,
-    fs::File,
+    convert::TryFrom,
+    io::{self, prelude::*, BufWriter},
+    fs::{self, File},
+    net::TcpListener,
     env,
-    time::Duration,
-    thread::sleep
+    ops::RangeInclusive,
+    process,
+    str::FromStr
 };
 
+const USAGE: &str = "usage: ./chip8_emulator [--quirks vip|chip48|schip|octo|xochip] \
+[--rewind-frames N] [--rewind-rate N] [--seed N] [--vip-random] \
+[--record movie] [--play movie] [--ipf N | --clock HZ] \
+[--waveform square|sine|triangle|sawtooth] [--tone HZ] [--volume 0-100] [--debug | --gdb PORT] \
+[--trace file] [--trace-format text|binary] [--trace-range ADDR-ADDR] [--trace-frames N-N] \
+[--scale N] [--palette RRGGBB,RRGGBB,RRGGBB,RRGGBB] [--capture gif|frames] \
+[--keymap file.toml] [--layout qwerty|azerty|qwertz|dvorak|numpad] \
+[--headless [--frames N] [--input script] [--screenshot file.png|pbm|txt]] <rom_name>
+       ./chip8_emulator run [options] <rom_name>
+       ./chip8_emulator disasm [--octo] <rom_name>
+       ./chip8_emulator analyze [--dot | --json] <rom_name>
+       ./chip8_emulator asm [--dialect chipper|robson (detected by default)] [-D SYMBOL] [-o rom] <source>
+       ./chip8_emulator octo [-o rom] <source.8o>
+       ./chip8_emulator diff [--left quirks] (--right quirks | --trace file) [--frames N] [--ipf N] \
+[--seed N] [--input script] <rom_name>";
+
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+struct Options {
+    rom_name: String,
+    quirks: Quirks,
+    rewind_frames: usize,
+    rewind_rate: usize,
+    seed: Option<u64>,
+    vip_random: bool,
+    record: Option<String>,
+    play: Option<String>,
+    speed: Speed,
+    tone: Tone,
+    debug: bool,
+    gdb_port: Option<u16>,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    trace_addresses: Option<RangeInclusive<usize>>,
+    trace_frames: Option<RangeInclusive<u64>>,
+    headless: bool,
+    frames: u64,
+    input: Option<String>,
+    screenshot: Option<String>,
+    scale: usize,
+    palette: Palette,
+    capture_format: CaptureFormat,
+    keys: Bindings,
+}
 
-use sdl2::{event::Event, rect, render::Canvas, video::Window};
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    if let Some(command) = args.peek().and_then(|name| commands::find(name)) {
+        args.next();
+        if let Err(e) = command(&mut args) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
 
+    let options = parse_args(args).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
 
-fn main() -> std::io::Result<()> {
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
+    if let Err(e) = start(&options) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
 
-    let window = video_subsystem
-        .window("Chip-8", 640, 320)
-        .position_centered()
-        .opengl()
-        .build()
-        .unwrap();
+fn read_rom(path: &str) -> Result<Vec<u8>, String> {
+    let mut rom = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut rom_data = Vec::new();
+    rom.read_to_end(&mut rom_data)
+        .map_err(|e| format!("Error while reading the ROM file: {}", e))?;
+    if path.ends_with(".8o") {
+        return octo::compile(&String::from_utf8_lossy(&rom_data))
+            .map_err(|e| format!("{}: {}", path, e));
+    }
+    Ok(rom_data)
+}
 
-    let mut canvas = window.into_canvas().build().unwrap();
+fn start(options: &Options) -> Result<(), String> {
+    let rom_data = read_rom(&options.rom_name)?;
 
-    let rom_name = env::args().nth(1)
-        .expect("usage: ./chip8_emulator <rom_name>");
-    let mut rom = File::open(&rom_name)?;
-    let mut rom_data = [0u8; 3584];
-    rom.read(&mut rom_data)
-        .expect("Error while reading the ROM file !");
+    let playback = match options.play {
+        Some(ref path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            Some(Movie::parse(&text).map_err(|e| format!("{}: {}", path, e))?)
+        }
+        None => None,
+    };
 
-    let mut c = cpu::Cpu::new();
-    c.load_program(&rom_data)
-        .expect("Error while loading the ROM !");
+    let (c, recording) = match playback {
+        Some(ref movie) => {
+            let c = movie.cpu(&rom_data)?;
+            movie.check_rom(&c).map_err(|e| e.to_string())?;
+            (c, None)
+        }
+        None => {
+            let seed = options.seed.unwrap_or_else(rand::random);
+            let (mode, rng) = if options.vip_random {
+                (RandomMode::CosmacVip, RandomSource::cosmac_vip(seed))
+            } else {
+                (RandomMode::SplitMix, RandomSource::new(seed))
+            };
+            let mut c = Cpu::with_quirks(options.quirks);
+            c.set_random_source(rng);
+            c.load_program(&rom_data)?;
+            let recording = options.record.as_ref()
+                .map(|_| Movie::new(c.rom_hash(), seed, mode, options.quirks, options.speed));
+            (c, recording)
+        }
+    };
 
-    let mut k = keypad::KeyPad::new();
+    if options.debug {
+        let mut c = c;
+        let stdin = io::stdin();
+        return Debugger::new(options.speed).repl(&mut c, stdin.lock(), io::stdout())
+            .map_err(|e| e.to_string());
+    }
 
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    'running: loop {
-        c.cycle(&k);
-        c.cycle(&k);
-        c.cycle(&k);
-        c.cycle(&k);
-        c.update_timers();
+    if let Some(port) = options.gdb_port {
+        let mut c = c;
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+        eprintln!("Waiting for a GDB connection on 127.0.0.1:{}", port);
+        let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+        return GdbStub::new(options.speed).serve(&mut c, stream).map_err(|e| e.to_string());
+    }
 
-        draw_screen(&mut canvas, &c.screen);
-        canvas.present();
+    if options.headless {
+        return run_headless(c, options, playback, recording);
+    }
 
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } => break 'running,
-                Event::KeyDown {scancode: s, ..} => if let Some(key) = s { k.key_down(key) },
-                Event::KeyUp {scancode: s, ..} => if let Some(key) = s { k .key_up(key) },
-                _ => (),
+    run(c, options, playback, recording)
+}
+
+fn run_headless(mut c: Cpu, options: &Options, playback: Option<Movie>, mut recording: Option<Movie>)
+                -> Result<(), String> {
+    let script = match options.input {
+        Some(ref path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            InputScript::parse(&text).map_err(|e| format!("{}: {}", path, e))?
+        }
+        None => InputScript::default(),
+    };
+    let mut tracer = open_trace(options)?;
+    let mut keypad = KeyPad::new();
+    let mut fault = None;
+    let mut frame = 0;
+    while frame < options.frames && !c.has_exited() {
+        script.apply(frame, &mut keypad);
+        let (keypad, speed) = match playback {
+            Some(ref movie) if frame < movie.frames.len() as u64 =>
+                (&movie.frames[frame as usize], movie.speed),
+            _ => (&keypad, options.speed),
+        };
+        if let Some(ref mut movie) = recording {
+            movie.record(keypad);
+        }
+        let cycles = speed.cycles_in_frame(frame);
+        let result = match tracer {
+            Some(ref mut tracer) => tracer.run_frame(&mut c, keypad, cycles),
+            None => c.run_frame(keypad, cycles),
+        };
+        frame += 1;
+        if let Err(e) = result {
+            fault = Some(e);
+            break;
+        }
+    }
+
+    if let (Some(tracer), Some(path)) = (tracer, options.trace.as_ref()) {
+        tracer.finish().map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if let (Some(movie), Some(path)) = (recording, options.record.as_ref()) {
+        fs::write(path, movie.to_string()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if let Some(ref path) = options.screenshot {
+        let data = image::encode(&c.screen, ImageFormat::from_path(path), options.scale, &options.palette);
+        fs::write(path, data).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    print!("{}", headless::state_json(&c, frame, fault.as_ref()));
+    match fault {
+        Some(e) => Err(format!("CPU fault: {}", e)),
+        None => Ok(()),
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut rom_name = None;
+    let mut quirks = Quirks::default();
+    let mut rewind_frames = 600;
+    let mut rewind_rate = 1;
+    let mut seed = None;
+    let mut vip_random = false;
+    let mut record = None;
+    let mut play = None;
+    let mut speed = Speed::default();
+    let mut waveform = Waveform::Square;
+    let mut frequency = 440.0;
+    let mut volume: u8 = 25;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_addresses = None;
+    let mut trace_frames = None;
+    let mut headless = false;
+    let mut frames = None;
+    let mut input = None;
+    let mut screenshot = None;
+    let mut scale = 4;
+    let mut palette = image::DEFAULT_PALETTE;
+    let mut capture_format = CaptureFormat::Gif;
+    let mut keymap = None;
+    let mut layout = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks expects a preset name")?;
+                quirks = Quirks::from_name(&name)
+                    .ok_or_else(|| format!("unknown quirks preset: {}", name))?;
+            }
+            "--rewind-frames" => rewind_frames = parse_number(&arg, args.next())?,
+            "--rewind-rate" => rewind_rate = parse_number(&arg, args.next())?,
+            "--seed" => seed = Some(parse_number(&arg, args.next())?),
+            "--vip-random" => vip_random = true,
+            "--record" => record = Some(args.next().ok_or("--record expects a file name")?),
+            "--play" => play = Some(args.next().ok_or("--play expects a file name")?),
+            "--ipf" => speed = Speed::InstructionsPerFrame(parse_nonzero(&arg, args.next())?),
+            "--clock" => speed = Speed::ClockHz(parse_nonzero(&arg, args.next())?),
+            "--waveform" => {
+                let name = args.next().ok_or("--waveform expects a waveform name")?;
+                waveform = Waveform::from_name(&name)
+                    .ok_or_else(|| format!("unknown waveform: {}", name))?;
+            }
+            "--tone" => frequency = parse_number(&arg, args.next())?,
+            "--volume" => volume = parse_number(&arg, args.next())?,
+            "--debug" => debug = true,
+            "--gdb" => gdb_port = Some(parse_number(&arg, args.next())?),
+            "--trace" => trace = Some(args.next().ok_or("--trace expects a file name")?),
+            "--trace-format" => {
+                let name = args.next().ok_or("--trace-format expects text or binary")?;
+                trace_format = TraceFormat::from_name(&name)
+                    .ok_or_else(|| format!("unknown trace format: {}", name))?;
             }
+            "--trace-range" => trace_addresses = Some(parse_range(&arg, args.next(), 16)?),
+            "--trace-frames" => trace_frames = Some(parse_range(&arg, args.next(), 10)?),
+            "--headless" => headless = true,
+            "--frames" => frames = Some(parse_number(&arg, args.next())?),
+            "--input" => input = Some(args.next().ok_or("--input expects a file name")?),
+            "--screenshot" => screenshot = Some(args.next().ok_or("--screenshot expects a file name")?),
+            "--scale" => scale = parse_number(&arg, args.next())?,
+            "--palette" => {
+                let colors = args.next().ok_or("--palette expects four RRGGBB colors")?;
+                palette = image::parse_palette(&colors)
+                    .ok_or_else(|| format!("--palette expects four RRGGBB colors, got {}", colors))?;
+            }
+            "--capture" => {
+                let name = args.next().ok_or("--capture expects gif or frames")?;
+                capture_format = CaptureFormat::from_name(&name)
+                    .ok_or_else(|| format!("unknown capture format: {}", name))?;
+            }
+            "--keymap" => keymap = Some(args.next().ok_or("--keymap expects a file name")?),
+            "--layout" => {
+                let name = args.next().ok_or("--layout expects a layout name")?;
+                Bindings::layout(&name).map_err(|e| e.to_string())?;
+                layout = Some(name);
+            }
+            _ if rom_name.is_none() => rom_name = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
         }
+    }
 
-        sleep(Duration::from_millis(10))
+    if record.is_some() && play.is_some() {
+        return Err("--record and --play cannot be used together".to_string());
+    }
+    if (debug || gdb_port.is_some()) && (record.is_some() || play.is_some()) {
+        return Err("--debug and --gdb cannot be used with --record or --play".to_string());
+    }
+    if debug && gdb_port.is_some() {
+        return Err("--debug and --gdb cannot be used together".to_string());
+    }
+    if (debug || gdb_port.is_some()) && trace.is_some() {
+        return Err("--trace cannot be used with --debug or --gdb".to_string());
+    }
+    if headless && (debug || gdb_port.is_some()) {
+        return Err("--headless cannot be used with --debug or --gdb".to_string());
+    }
+    if !headless && (frames.is_some() || input.is_some() || screenshot.is_some()) {
+        return Err("--frames, --input and --screenshot need --headless".to_string());
+    }
+    if input.is_some() && play.is_some() {
+        return Err("--input and --play cannot be used together".to_string());
+    }
+    if scale == 0 {
+        return Err("--scale expects a positive number".to_string());
     }
 
-    Ok(())
-}
+    if volume > 100 {
+        return Err(format!("--volume expects a value between 0 and 100, got {}", volume));
+    }
+    if frequency <= 0.0 {
+        return Err(format!("--tone expects a positive frequency, got {}", frequency));
+    }
+    let tone = Tone::new(waveform, frequency, f32::from(volume) / 100.0);
 
-fn draw_screen(canvas: &mut Canvas<Window>, screen: &Screen) {
-    canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255));
-
-    let (width, height) = canvas.output_size().unwrap();
-    let (pixel_width, pixel_height) = (width / 64, height / 32);
-
-    screen.pixels().filter(|p| p.on()).for_each(|p| {
-        let x = p.x() * pixel_width as usize;
-        let y = p.y() * pixel_height as usize;
-        let rectangle = rect::Rect::new(x as i32, y as i32, pixel_width, pixel_height);
-        canvas
-            .fill_rect(rectangle)
-            .expect(&format!("Unable to draw: {:#?}", rectangle));
+    let rom_name = rom_name.ok_or("missing ROM file")?;
+    let config = match keymap {
+        Some(ref path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            KeyMapConfig::parse(&text).map_err(|e| format!("{}: {}", path, e))?
+        }
+        None => KeyMapConfig::default(),
+    };
+    let keys = config.bindings(&rom_name, layout.as_deref()).map_err(|e| e.to_string())?;
+    Ok(Options {
+        rom_name, quirks, rewind_frames, rewind_rate, seed, vip_random, record, play, speed, tone,
+        debug, gdb_port, trace, trace_format, trace_addresses, trace_frames, headless,
+        frames: frames.unwrap_or(600), input, screenshot, scale, palette, capture_format,
+        keys
     })
 }
+
+fn parse_range<T>(flag: &str, value: Option<String>, radix: u32) -> Result<RangeInclusive<T>, String>
+    where T: TryFrom<u64> + PartialOrd {
+    let value = value.ok_or_else(|| format!("{} expects a range START-END", flag))?;
+    let error = || format!("{} expects a range START-END, got {}", flag, value);
+    let mut bounds = value.splitn(2, '-')
+        .map(|bound| u64::from_str_radix(bound, radix).ok().and_then(|n| T::try_from(n).ok()));
+    match (bounds.next(), bounds.next()) {
+        (Some(Some(start)), Some(Some(end))) if start <= end => Ok(start..=end),
+        _ => Err(error()),
+    }
+}
+
+fn open_trace(options: &Options) -> Result<Option<Tracer<BufWriter<File>>>, String> {
+    let path = match options.trace {
+        Some(ref path) => path,
+        None => return Ok(None),
+    };
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut tracer = Tracer::new(BufWriter::new(file), options.trace_format);
+    if let Some(ref range) = options.trace_addresses {
+        tracer = tracer.addresses(range.clone());
+    }
+    if let Some(ref range) = options.trace_frames {
+        tracer = tracer.frames(range.clone());
+    }
+    Ok(Some(tracer))
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} expects a number", flag))?;
+    value.parse().map_err(|_| format!("{} expects a number, got {}", flag, value))
+}
+
+fn parse_nonzero<T: FromStr + Default + PartialEq>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let n = parse_number(flag, value)?;
+    if n == T::default() {
+        return Err(format!("{} must be greater than 0", flag));
+    }
+    Ok(n)
+}
+
+#[cfg(feature = "sdl")]
+fn run(c: Cpu, options: &Options, playback: Option<Movie>, recording: Option<Movie>)
+       -> Result<(), String> {
+    frontend::run(c, options, playback, recording)
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run(_c: Cpu, _options: &Options, _playback: Option<Movie>, _recording: Option<Movie>)
+       -> Result<(), String> {
+    Err("This binary was built without a frontend, rebuild it with the `sdl` feature.".to_string())
+}
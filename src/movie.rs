@@ -0,0 +1,238 @@
+use super::{
+    cpu::{Cpu, CpuError},
+    keypad::KeyPad,
+    quirks::Quirks,
+    random::{RandomMode, RandomSource},
+    scheduler::Speed
+};
+
+use std::{
+    error::Error,
+    fmt
+};
+
+const HEADER: &str = "chip8-movie 1";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MovieError {
+    Parse { line: usize, message: String },
+    RomMismatch { expected: u64, found: u64 },
+    Cpu { frame: usize, error: CpuError },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::Parse { line, ref message } => write!(f, "line {}: {}", line, message),
+            MovieError::RomMismatch { expected, found } =>
+                write!(f, "movie was recorded with ROM {:016X}, loaded ROM is {:016X}", found, expected),
+            MovieError::Cpu { frame, error } => write!(f, "frame {}: {}", frame, error),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub random_mode: RandomMode,
+    pub quirks: Quirks,
+    pub speed: Speed,
+    pub frames: Vec<KeyPad>,
+}
+
+impl Movie {
+    pub fn new(rom_hash: u64, seed: u64, random_mode: RandomMode, quirks: Quirks,
+               speed: Speed) -> Movie {
+        Movie { rom_hash, seed, random_mode, quirks, speed, frames: Vec::new() }
+    }
+
+    pub fn record(&mut self, keypad: &KeyPad) {
+        self.frames.push(keypad.clone());
+    }
+
+    pub fn random_source(&self) -> RandomSource {
+        match self.random_mode {
+            RandomMode::SplitMix => RandomSource::new(self.seed),
+            RandomMode::CosmacVip => RandomSource::cosmac_vip(self.seed),
+        }
+    }
+
+    pub fn cpu(&self, rom: &[u8]) -> Result<Cpu, String> {
+        let mut cpu = Cpu::with_quirks(self.quirks);
+        cpu.set_random_source(self.random_source());
+        cpu.load_program(rom)?;
+        Ok(cpu)
+    }
+
+    pub fn check_rom(&self, cpu: &Cpu) -> Result<(), MovieError> {
+        if cpu.rom_hash() != self.rom_hash {
+            return Err(MovieError::RomMismatch { expected: cpu.rom_hash(), found: self.rom_hash });
+        }
+        Ok(())
+    }
+
+    pub fn play(&self, cpu: &mut Cpu) -> Result<(), MovieError> {
+        self.check_rom(cpu)?;
+        for (frame, keypad) in self.frames.iter().enumerate() {
+            cpu.run_frame(keypad, self.speed.cycles_in_frame(frame as u64))
+                .map_err(|error| MovieError::Cpu { frame, error })?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text.lines().enumerate()
+            .map(|(n, l)| (n + 1, l.trim()))
+            .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'));
+
+        match lines.next() {
+            Some((_, HEADER)) => (),
+            Some((line, _)) => return Err(parse_error(line, "not a chip8 movie")),
+            None => return Err(parse_error(1, "empty movie")),
+        }
+
+        let mut movie = Movie::new(0, 0, RandomMode::SplitMix, Quirks::default(), Speed::default());
+        let mut in_frames = false;
+        for (line, content) in lines {
+            if in_frames {
+                movie.frames.push(parse_frame(line, content)?);
+                continue;
+            }
+            let mut words = content.split_whitespace();
+            let key = words.next().unwrap_or("");
+            let value = words.next().unwrap_or("");
+            match key {
+                "rom" => movie.rom_hash = u64::from_str_radix(value, 16)
+                    .map_err(|_| parse_error(line, "invalid ROM hash"))?,
+                "seed" => movie.seed = value.parse()
+                    .map_err(|_| parse_error(line, "invalid seed"))?,
+                "random" => movie.random_mode = match value {
+                    "splitmix" => RandomMode::SplitMix,
+                    "vip" => RandomMode::CosmacVip,
+                    _ => return Err(parse_error(line, "unknown random mode")),
+                },
+                "quirks" => movie.quirks = u8::from_str_radix(value, 16).ok()
+                    .and_then(Quirks::from_bits)
+                    .ok_or_else(|| parse_error(line, "invalid quirks"))?,
+                "cycles-per-frame" => movie.speed = Speed::InstructionsPerFrame(value.parse().ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| parse_error(line, "invalid cycles per frame"))?),
+                "clock-hz" => movie.speed = Speed::ClockHz(value.parse().ok()
+                    .filter(|&hz| hz > 0)
+                    .ok_or_else(|| parse_error(line, "invalid clock frequency"))?),
+                "frames" => in_frames = true,
+                _ => return Err(parse_error(line, &format!("unknown field: {}", key))),
+            }
+        }
+        Ok(movie)
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "rom {:016X}", self.rom_hash)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "random {}", match self.random_mode {
+            RandomMode::SplitMix => "splitmix",
+            RandomMode::CosmacVip => "vip",
+        })?;
+        writeln!(f, "quirks {:02X}", self.quirks.to_bits())?;
+        match self.speed {
+            Speed::InstructionsPerFrame(n) => writeln!(f, "cycles-per-frame {}", n)?,
+            Speed::ClockHz(hz) => writeln!(f, "clock-hz {}", hz)?,
+        }
+        writeln!(f, "frames")?;
+        for keypad in self.frames.iter() {
+            let line: String = (0..16)
+                .map(|key| if keypad.is_pressed(key) { format!("{:X}", key) } else { ".".to_string() })
+                .collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_error(line: usize, message: &str) -> MovieError {
+    MovieError::Parse { line, message: message.to_string() }
+}
+
+fn parse_frame(line: usize, content: &str) -> Result<KeyPad, MovieError> {
+    if content.chars().count() != 16 {
+        return Err(parse_error(line, "a frame must have 16 key columns"));
+    }
+    let mut keypad = KeyPad::new();
+    for (key, c) in content.chars().enumerate() {
+        match c {
+            '.' => (),
+            _ if c.to_digit(16) == Some(key as u32) => keypad.key_down(key as u8),
+            _ => return Err(parse_error(line, &format!("unexpected '{}' in column {:X}", c, key))),
+        }
+    }
+    Ok(keypad)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ROM: [u8; 10] = [0xC0, 0xFF, 0xE1, 0x9E, 0x12, 0x00, 0x71, 0x01, 0x12, 0x00];
+
+    fn movie() -> Movie {
+        let mut movie = Movie::new(::state::rom_hash(&ROM), 1234, RandomMode::SplitMix,
+                                   Quirks::COSMAC_VIP, Speed::InstructionsPerFrame(4));
+        let mut pad = KeyPad::new();
+        movie.record(&pad);
+        pad.key_down(1);
+        pad.key_down(0xF);
+        movie.record(&pad);
+        movie
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let movie = movie();
+        let text = movie.to_string();
+        assert!(text.contains("\n................\n.1.............F\n"));
+        assert_eq!(Ok(movie), Movie::parse(&text));
+    }
+
+    #[test]
+    fn clock_speed_round_trip() {
+        let mut movie = movie();
+        movie.speed = Speed::ClockHz(700);
+        assert_eq!(Ok(movie.clone()), Movie::parse(&movie.to_string()));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Err(parse_error(1, "not a chip8 movie")), Movie::parse("hello"));
+        let text = format!("{}\nframes\n.2..............\n", HEADER);
+        assert_eq!(Err(parse_error(3, "unexpected '2' in column 1")), Movie::parse(&text));
+        let text = format!("{}\ncycles-per-frame 0\n", HEADER);
+        assert_eq!(Err(parse_error(2, "invalid cycles per frame")), Movie::parse(&text));
+    }
+
+    #[test]
+    fn play_is_deterministic() {
+        let movie = movie();
+        let mut a = movie.cpu(&ROM).unwrap();
+        let mut b = movie.cpu(&ROM).unwrap();
+        movie.play(&mut a).unwrap();
+        movie.play(&mut b).unwrap();
+        assert_eq!(a.save_state(), b.save_state());
+    }
+
+    #[test]
+    fn play_other_rom() {
+        let movie = movie();
+        let mut cpu = movie.cpu(&[0x12, 0x00]).unwrap();
+        match movie.play(&mut cpu) {
+            Err(MovieError::RomMismatch { .. }) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
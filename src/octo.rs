@@ -0,0 +1,1043 @@
+use disasm::LOAD_ADDRESS;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    f64::consts,
+    fmt
+};
+
+const MEMORY_END: usize = 0x10000;
+const MAX_EXPANSIONS: usize = 0x10000;
+
+const UNARY_OPERATORS: &[&str] = &[
+    "-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor", "@",
+];
+
+const BINARY_OPERATORS: &[&str] = &[
+    "-", "+", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", "<=", "==", "!=",
+    ">=", ">",
+];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OctoError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for OctoError {}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> OctoError {
+        OctoError { line: self.line, message }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Fixup {
+    /// The low 12 bits of the instruction at the address.
+    Address,
+    /// The 16-bit word at the address, after `i := long`.
+    Long,
+    /// The immediates of the `v0 :=` and `v1 :=` pair of `:unpack`.
+    Unpack,
+    /// The same pair for `:unpack long`.
+    UnpackLong,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Compiler {
+    tokens: Vec<Token>,
+    pos: usize,
+    rom: Vec<u8>,
+    here: usize,
+    values: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, Fixup, Token)>,
+    branches: Vec<(usize, Token)>,
+    loops: Vec<(usize, usize, Token)>,
+    whiles: Vec<usize>,
+    expansions: usize,
+}
+
+/// Compiles an Octo program to the bytes loaded at `0x200`.
+///
+/// As in Octo, a program with a `main` label starts with a jump to it.
+pub fn compile(source: &str) -> Result<Vec<u8>, OctoError> {
+    let mut compiler = Compiler::new(tokenize(source));
+    compiler.run()?;
+    Ok(compiler.rom)
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        for word in text.split_whitespace() {
+            if word.starts_with('#') {
+                break;
+            }
+            tokens.push(Token { text: word.to_string(), line: index + 1 });
+        }
+    }
+    tokens
+}
+
+fn number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value as f64 } else { value as f64 })
+}
+
+fn op(a: u8, x: u8, y: u8, n: u8) -> u16 {
+    (a as u16) << 12 | (x as u16) << 8 | (y as u16) << 4 | n as u16
+}
+
+impl Compiler {
+    fn new(tokens: Vec<Token>) -> Compiler {
+        let mut compiler = Compiler {
+            tokens,
+            pos: 0,
+            rom: Vec::new(),
+            here: LOAD_ADDRESS,
+            values: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+            whiles: Vec::new(),
+            expansions: 0,
+        };
+        let has_main = compiler.tokens.windows(2).any(|w| w[0].text == ":" && w[1].text == "main");
+        if has_main {
+            let token = Token { text: "main".to_string(), line: 1 };
+            compiler.fixups.push((LOAD_ADDRESS, Fixup::Address, token));
+            compiler.rom = vec![0x10, 0x00];
+            compiler.here += 2;
+        }
+        compiler
+    }
+
+    fn run(&mut self) -> Result<(), OctoError> {
+        while self.pos < self.tokens.len() {
+            let token = self.next()?;
+            self.statement(&token)?;
+        }
+        if let Some((_, token)) = self.branches.pop() {
+            return Err(token.error("this 'begin' is missing its 'end'".to_string()));
+        }
+        if let Some((_, _, token)) = self.loops.pop() {
+            return Err(token.error("this 'loop' is missing its 'again'".to_string()));
+        }
+        for (addr, fixup, token) in std::mem::take(&mut self.fixups) {
+            let value = self.values.get(&token.text).cloned()
+                .ok_or_else(|| token.error(format!("undefined name: {}", token.text)))?;
+            match fixup {
+                Fixup::Address => {
+                    let value = self.check(&token, value, 0xFFF)?;
+                    self.patch(addr, value as u16);
+                }
+                Fixup::Long => {
+                    let value = self.check(&token, value, 0xFFFF)?;
+                    self.write(addr, (value >> 8) as u8);
+                    self.write(addr + 1, value as u8);
+                }
+                Fixup::Unpack => {
+                    let value = self.check(&token, value, 0xFFF)?;
+                    let high = self.read(addr + 1) | (value >> 8) as u8;
+                    self.write(addr + 1, high);
+                    self.write(addr + 3, value as u8);
+                }
+                Fixup::UnpackLong => {
+                    let value = self.check(&token, value, 0xFFFF)?;
+                    self.write(addr + 1, (value >> 8) as u8);
+                    self.write(addr + 3, value as u8);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, token: &Token) -> Result<(), OctoError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                let here = self.here as f64;
+                self.define(&name, here)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.constant()?;
+                self.define(&name, value)?;
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = if self.peek_is("{") {
+                    let value = self.calc()?;
+                    self.check(&name, value, 0xF)? as u8
+                } else {
+                    self.register()?
+                };
+                self.aliases.insert(name.text, register);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.values.insert(name.text, value);
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    let value = self.calc()?;
+                    self.check_byte(token, value)?
+                } else {
+                    self.byte()?
+                };
+                self.emit(value)?;
+            }
+            ":org" => {
+                let value = self.constant()?;
+                let addr = self.check(token, value, MEMORY_END as i64 - 1)? as usize;
+                if addr < LOAD_ADDRESS {
+                    return Err(token.error(format!(":org below 0x{:X}", LOAD_ADDRESS)));
+                }
+                self.here = addr;
+            }
+            ":next" => {
+                let name = self.name()?;
+                let next = self.here as f64 + 1.0;
+                self.define(&name, next)?;
+            }
+            ":unpack" => {
+                let high = if self.peek_is("long") {
+                    self.next()?;
+                    None
+                } else {
+                    let value = self.constant()?;
+                    Some(self.check(token, value, 0xF)? as u8)
+                };
+                let target = self.next()?;
+                self.unpack(high, &target)?;
+            }
+            ":call" => self.jump(0x2000)?,
+            ":macro" => self.define_macro()?,
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ";" | "return" => self.word(0x00EE)?,
+            "clear" => self.word(0x00E0)?,
+            "exit" => self.word(0x00FD)?,
+            "lores" => self.word(0x00FE)?,
+            "hires" => self.word(0x00FF)?,
+            "scroll-right" => self.word(0x00FB)?,
+            "scroll-left" => self.word(0x00FC)?,
+            "audio" => self.word(0xF002)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.word(0x00C0 | n as u16)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.word(0x00D0 | n as u16)?;
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.word(op(0xF, n, 0x0, 0x1))?;
+            }
+            "bcd" => self.register_op(0x33)?,
+            "saveflags" => self.register_op(0x75)?,
+            "loadflags" => self.register_op(0x85)?,
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let n = if token.text == "save" { 0x2 } else { 0x3 };
+                    self.word(op(0x5, x, y, n))?;
+                } else {
+                    let low = if token.text == "save" { 0x55 } else { 0x65 };
+                    self.word(op(0xF, x, low >> 4, low & 0xF))?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.word(op(0xD, x, y, n))?;
+            }
+            "jump" => self.jump(0x1000)?,
+            "jump0" => self.jump(0xB000)?,
+            "native" => {
+                return Err(token.error("native machine code calls are not supported".to_string()));
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let low = match token.text.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.register_op(low)?;
+            }
+            "i" => self.index()?,
+            "loop" => self.loops.push((self.here, self.whiles.len(), token.clone())),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(token.error("'while' outside of a loop".to_string()));
+                }
+                self.condition(true)?;
+                self.whiles.push(self.here);
+                self.word(0x1000)?;
+            }
+            "again" => {
+                let (start, whiles, _) = self.loops.pop()
+                    .ok_or_else(|| token.error("'again' without 'loop'".to_string()))?;
+                let start = self.jump_target(token, start)?;
+                self.word(0x1000 | start)?;
+                for addr in self.whiles.split_off(whiles) {
+                    let here = self.jump_target(token, self.here)?;
+                    self.patch(addr, here);
+                }
+            }
+            "if" => self.branch()?,
+            "else" => {
+                let (addr, _) = self.branches.pop()
+                    .ok_or_else(|| token.error("'else' without 'begin'".to_string()))?;
+                self.branches.push((self.here, token.clone()));
+                self.word(0x1000)?;
+                let here = self.jump_target(token, self.here)?;
+                self.patch(addr, here);
+            }
+            "end" => {
+                let (addr, _) = self.branches.pop()
+                    .ok_or_else(|| token.error("'end' without 'begin'".to_string()))?;
+                let here = self.jump_target(token, self.here)?;
+                self.patch(addr, here);
+            }
+            _ => {
+                if let Some(x) = self.register_name(&token.text) {
+                    return self.assignment(x);
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand(token);
+                }
+                if let Some(value) = number(&token.text) {
+                    let value = self.check_byte(token, value)?;
+                    return self.emit(value);
+                }
+                if token.text.starts_with(':') {
+                    return Err(token.error(format!("unexpected {}", token.text)));
+                }
+                self.address_op(0x2000, token)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn assignment(&mut self, x: u8) -> Result<(), OctoError> {
+        let operator = self.next()?;
+        if let Some(y) = self.peek().and_then(|t| self.register_name(&t.text)) {
+            let n = match operator.text.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return Err(operator.error(format!("unknown register operation: {}", operator.text))),
+            };
+            self.next()?;
+            return self.word(op(0x8, x, y, n));
+        }
+        match operator.text.as_str() {
+            ":=" => match self.peek().map(|t| t.text.as_str()) {
+                Some("random") => {
+                    self.next()?;
+                    let kk = self.byte()?;
+                    self.word(op(0xC, x, kk >> 4, kk & 0xF))
+                }
+                Some("key") => {
+                    self.next()?;
+                    self.word(op(0xF, x, 0x0, 0xA))
+                }
+                Some("delay") => {
+                    self.next()?;
+                    self.word(op(0xF, x, 0x0, 0x7))
+                }
+                _ => {
+                    let kk = self.byte()?;
+                    self.word(op(0x6, x, kk >> 4, kk & 0xF))
+                }
+            },
+            "+=" => {
+                let kk = self.byte()?;
+                self.word(op(0x7, x, kk >> 4, kk & 0xF))
+            }
+            "-=" => {
+                let kk = self.byte()?.wrapping_neg();
+                self.word(op(0x7, x, kk >> 4, kk & 0xF))
+            }
+            "|=" | "&=" | "^=" | ">>=" | "=-" | "<<=" =>
+                Err(operator.error(format!("{} expects a register", operator.text))),
+            _ => Err(operator.error(format!("unknown register operation: {}", operator.text))),
+        }
+    }
+
+    fn index(&mut self) -> Result<(), OctoError> {
+        let operator = self.next()?;
+        match operator.text.as_str() {
+            ":=" => match self.peek().map(|t| t.text.as_str()) {
+                Some("hex") => {
+                    self.next()?;
+                    self.register_op(0x29)
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    self.register_op(0x30)
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.word(0xF000)?;
+                    let target = self.next()?;
+                    match self.value(&target) {
+                        Some(value) => {
+                            let value = self.check(&target, value, 0xFFFF)? as u16;
+                            self.word(value)
+                        }
+                        None => {
+                            self.fixups.push((self.here, Fixup::Long, target));
+                            self.word(0x0000)
+                        }
+                    }
+                }
+                _ => self.jump(0xA000),
+            },
+            "+=" => self.register_op(0x1E),
+            _ => Err(operator.error(format!("unknown i operation: {}", operator.text))),
+        }
+    }
+
+    /// Parses an `if` and emits a skip over the next instruction or, for `begin`, over a
+    /// jump to the matching `else` or `end`.
+    fn branch(&mut self) -> Result<(), OctoError> {
+        let operator = self.tokens.get(self.pos + 1).map(|t| t.text.as_str());
+        let length = if let Some("key") | Some("-key") = operator { 2 } else { 3 };
+        let keyword = self.tokens.get(self.pos + length).map(|t| t.text.clone());
+        match keyword.as_deref() {
+            Some("then") => {
+                self.condition(false)?;
+                self.next()?;
+            }
+            Some("begin") => {
+                self.condition(true)?;
+                let begin = self.next()?;
+                self.branches.push((self.here, begin));
+                self.word(0x1000)?;
+            }
+            _ => {
+                let token = self.tokens.get(self.pos).unwrap_or(&self.tokens[self.pos - 1]);
+                return Err(token.error("'if' expects a condition followed by 'then' or 'begin'".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits instructions that skip the next one unless the condition holds, or when it
+    /// holds if `negated` is set.
+    fn condition(&mut self, negated: bool) -> Result<(), OctoError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        let mut text = operator.text.as_str();
+        if negated {
+            text = match text {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">" => "<=",
+                "<=" => ">",
+                ">=" => "<",
+                other => other,
+            };
+        }
+        match text {
+            "key" => self.word(op(0xE, x, 0xA, 0x1)),
+            "-key" => self.word(op(0xE, x, 0x9, 0xE)),
+            "==" | "!=" => {
+                let equal = text == "==";
+                match self.peek().and_then(|t| self.register_name(&t.text)) {
+                    Some(y) => {
+                        self.next()?;
+                        self.word(op(if equal { 0x9 } else { 0x5 }, x, y, 0x0))
+                    }
+                    None => {
+                        let kk = self.byte()?;
+                        self.word(op(if equal { 0x4 } else { 0x3 }, x, kk >> 4, kk & 0xF))
+                    }
+                }
+            }
+            "<" | ">" | "<=" | ">=" => {
+                let temp = self.aliases.get("compare-temp").cloned().unwrap_or(0xF);
+                match self.peek().and_then(|t| self.register_name(&t.text)) {
+                    Some(y) => {
+                        self.next()?;
+                        self.word(op(0x8, temp, y, 0x0))?;
+                    }
+                    None => {
+                        let kk = self.byte()?;
+                        self.word(op(0x6, temp, kk >> 4, kk & 0xF))?;
+                    }
+                }
+                let subtract = if text == ">" || text == "<=" { 0x5 } else { 0x7 };
+                self.word(op(0x8, temp, x, subtract))?;
+                self.word(if text == ">" || text == "<" { 0x3F01 } else { 0x4F01 })
+            }
+            _ => Err(operator.error(format!("unknown comparison: {}", operator.text))),
+        }
+    }
+
+    fn unpack(&mut self, high: Option<u8>, target: &Token) -> Result<(), OctoError> {
+        let start = self.here;
+        match self.value(target) {
+            Some(value) => {
+                let value = self.check(target, value, if high.is_some() { 0xFFF } else { 0xFFFF })?;
+                let top = high.map_or((value >> 8) as u8, |high| high << 4 | (value >> 8) as u8);
+                self.word(op(0x6, 0x0, top >> 4, top & 0xF))?;
+                self.word(0x6100 | (value & 0xFF) as u16)
+            }
+            None => {
+                let fixup = if high.is_some() { Fixup::Unpack } else { Fixup::UnpackLong };
+                self.fixups.push((start, fixup, target.clone()));
+                self.word(0x6000 | (high.unwrap_or(0) as u16) << 4)?;
+                self.word(0x6100)
+            }
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), OctoError> {
+        let name = self.name()?;
+        let mut args = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, token: &Token) -> Result<(), OctoError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(token.error(format!("too many expansions of macro {}", token.text)));
+        }
+        let count = self.macros[&token.text].args.len();
+        let mut bindings = HashMap::new();
+        for i in 0..count {
+            let value = self.next()?;
+            bindings.insert(self.macros[&token.text].args[i].clone(), value.text);
+        }
+        let body: Vec<Token> = self.macros[&token.text].body.iter()
+            .map(|t| Token {
+                text: bindings.get(&t.text).cloned().unwrap_or_else(|| t.text.clone()),
+                line: t.line,
+            })
+            .collect();
+        self.tokens.splice(self.pos..self.pos, body);
+        Ok(())
+    }
+
+    fn calc(&mut self) -> Result<f64, OctoError> {
+        let open = self.expect("{")?;
+        let mut terms = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "}" {
+                break;
+            }
+            terms.push(token);
+        }
+        let mut pos = 0;
+        let value = self.expression(&terms, &mut pos).map_err(|m| open.error(m))?;
+        if pos != terms.len() {
+            return Err(terms[pos].error(format!("unexpected {} in expression", terms[pos].text)));
+        }
+        Ok(value)
+    }
+
+    /// Octo expressions have no precedence: binary operators group to the right.
+    fn expression(&self, terms: &[Token], pos: &mut usize) -> Result<f64, String> {
+        let left = self.term(terms, pos)?;
+        let operator = match terms.get(*pos) {
+            Some(t) if BINARY_OPERATORS.contains(&t.text.as_str()) => t.text.as_str(),
+            _ => return Ok(left),
+        };
+        *pos += 1;
+        let right = self.expression(terms, pos)?;
+        let (a, b) = (left as i64, right as i64);
+        Ok(match operator {
+            "-" => left - right,
+            "+" => left + right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << (b & 63)) as f64,
+            ">>" => (a >> (b & 63)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            _ => (left > right) as i64 as f64,
+        })
+    }
+
+    fn term(&self, terms: &[Token], pos: &mut usize) -> Result<f64, String> {
+        let token = terms.get(*pos).ok_or("incomplete expression")?;
+        *pos += 1;
+        let text = token.text.as_str();
+        if text == "(" {
+            let value = self.expression(terms, pos)?;
+            return match terms.get(*pos) {
+                Some(t) if t.text == ")" => {
+                    *pos += 1;
+                    Ok(value)
+                }
+                _ => Err("missing )".to_string()),
+            };
+        }
+        if UNARY_OPERATORS.contains(&text) {
+            let value = self.term(terms, pos)?;
+            return Ok(match text {
+                "-" => -value,
+                "~" => !(value as i64) as f64,
+                "!" => (value == 0.0) as i64 as f64,
+                "sin" => value.sin(),
+                "cos" => value.cos(),
+                "tan" => value.tan(),
+                "exp" => value.exp(),
+                "log" => value.ln(),
+                "abs" => value.abs(),
+                "sqrt" => value.sqrt(),
+                "sign" => if value == 0.0 { 0.0 } else { value.signum() },
+                "ceil" => value.ceil(),
+                "floor" => value.floor(),
+                _ => self.read(value as usize) as f64,
+            });
+        }
+        match text {
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(consts::PI),
+            "E" => Ok(consts::E),
+            _ => self.value(token).ok_or_else(|| format!("undefined name: {}", text)),
+        }
+    }
+
+    fn value(&self, token: &Token) -> Option<f64> {
+        number(&token.text).or_else(|| self.values.get(&token.text).cloned())
+    }
+
+    fn constant(&mut self) -> Result<f64, OctoError> {
+        let token = self.next()?;
+        self.value(&token).ok_or_else(|| token.error(format!("undefined name: {}", token.text)))
+    }
+
+    fn check(&self, token: &Token, value: f64, max: i64) -> Result<i64, OctoError> {
+        let value = value as i64;
+        if value < 0 || value > max {
+            return Err(token.error(format!("{} does not fit in 0x{:X}: {}", token.text, max, value)));
+        }
+        Ok(value)
+    }
+
+    fn check_byte(&self, token: &Token, value: f64) -> Result<u8, OctoError> {
+        let value = value as i64;
+        if !(-128..=255).contains(&value) {
+            return Err(token.error(format!("{} does not fit in a byte: {}", token.text, value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn byte(&mut self) -> Result<u8, OctoError> {
+        let token = self.next()?;
+        let value = self.value(&token)
+            .ok_or_else(|| token.error(format!("undefined name: {}", token.text)))?;
+        self.check_byte(&token, value)
+    }
+
+    fn nibble(&mut self) -> Result<u8, OctoError> {
+        let token = self.next()?;
+        let value = self.value(&token)
+            .ok_or_else(|| token.error(format!("undefined name: {}", token.text)))?;
+        Ok(self.check(&token, value, 0xF)? as u8)
+    }
+
+    fn register_name(&self, text: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        let mut chars = text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) =>
+                digit.to_digit(16).map(|d| d as u8),
+            _ => None,
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, OctoError> {
+        let token = self.next()?;
+        self.register_name(&token.text)
+            .ok_or_else(|| token.error(format!("expected a register, got {}", token.text)))
+    }
+
+    fn register_op(&mut self, low: u8) -> Result<(), OctoError> {
+        let x = self.register()?;
+        self.word(op(0xF, x, low >> 4, low & 0xF))
+    }
+
+    fn jump(&mut self, prefix: u16) -> Result<(), OctoError> {
+        let target = self.next()?;
+        self.address_op(prefix, &target)
+    }
+
+    fn address_op(&mut self, prefix: u16, target: &Token) -> Result<(), OctoError> {
+        match self.value(target) {
+            Some(value) => {
+                let addr = self.check(target, value, 0xFFF)? as u16;
+                self.word(prefix | addr)
+            }
+            None => {
+                self.fixups.push((self.here, Fixup::Address, target.clone()));
+                self.word(prefix)
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<Token, OctoError> {
+        let token = self.next()?;
+        if number(&token.text).is_some() || self.register_name(&token.text).is_some() {
+            return Err(token.error(format!("{} is not a valid name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn define(&mut self, name: &Token, value: f64) -> Result<(), OctoError> {
+        if self.values.insert(name.text.clone(), value).is_some() {
+            return Err(name.error(format!("{} is already defined", name.text)));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.peek().is_some_and(|t| t.text == text)
+    }
+
+    fn next(&mut self) -> Result<Token, OctoError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => {
+                let line = self.tokens.last().map_or(1, |t| t.line);
+                Err(OctoError { line, message: "unexpected end of program".to_string() })
+            }
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, OctoError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token.error(format!("expected {}, got {}", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn read(&self, addr: usize) -> u8 {
+        addr.checked_sub(LOAD_ADDRESS).and_then(|i| self.rom.get(i)).cloned().unwrap_or(0)
+    }
+
+    fn write(&mut self, addr: usize, value: u8) {
+        let index = addr - LOAD_ADDRESS;
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = value;
+    }
+
+    fn emit(&mut self, value: u8) -> Result<(), OctoError> {
+        if self.here >= MEMORY_END {
+            let line = self.tokens[self.pos.saturating_sub(1)].line;
+            return Err(OctoError { line, message: "program does not fit in memory".to_string() });
+        }
+        let here = self.here;
+        self.write(here, value);
+        self.here += 1;
+        Ok(())
+    }
+
+    fn word(&mut self, value: u16) -> Result<(), OctoError> {
+        self.emit((value >> 8) as u8)?;
+        self.emit(value as u8)
+    }
+
+    /// Jumps only reach the first 4 KiB, unlike `i := long` and `:org`.
+    fn jump_target(&self, token: &Token, addr: usize) -> Result<u16, OctoError> {
+        if addr > 0xFFF {
+            return Err(token.error(format!("'{}' needs a jump to 0x{:X}, past 0xFFF", token.text, addr)));
+        }
+        Ok(addr as u16)
+    }
+
+    fn patch(&mut self, addr: usize, target: u16) {
+        let high = self.read(addr) & 0xF0 | (target >> 8) as u8 & 0xF;
+        self.write(addr, high);
+        self.write(addr + 1, target as u8);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cpu::Cpu;
+    use disasm::{self, Syntax};
+    use keypad::KeyPad;
+
+    #[test]
+    fn statements() {
+        let source = "\
+clear
+v1 := 0x2A  v2 := v1  v3 := random 7  v4 := key  v5 := delay
+v1 += 2  v1 -= 1  v1 += v2  v1 -= v2  v1 =- v2
+v1 |= v2  v1 &= v2  v1 ^= v2  v1 >>= v2  v1 <<= v2
+i := 0x123  i += v3  i := hex v4  i := bighex v5
+delay := v1  buzzer := v2  bcd v3  save v4  load v5
+sprite v1 v2 5  jump0 0x300  return
+";
+        assert_eq!(Ok(vec![
+            0x00, 0xE0, 0x61, 0x2A, 0x82, 0x10, 0xC3, 0x07, 0xF4, 0x0A, 0xF5, 0x07,
+            0x71, 0x02, 0x71, 0xFF, 0x81, 0x24, 0x81, 0x25, 0x81, 0x27,
+            0x81, 0x21, 0x81, 0x22, 0x81, 0x23, 0x81, 0x26, 0x81, 0x2E,
+            0xA1, 0x23, 0xF3, 0x1E, 0xF4, 0x29, 0xF5, 0x30,
+            0xF1, 0x15, 0xF2, 0x18, 0xF3, 0x33, 0xF4, 0x55, 0xF5, 0x65,
+            0xD1, 0x25, 0xB3, 0x00, 0x00, 0xEE,
+        ]), compile(source));
+    }
+
+    #[test]
+    fn schip_and_xochip() {
+        let source = "\
+hires lores scroll-down 4 scroll-up 2 scroll-left scroll-right exit
+saveflags v7 loadflags v7 save v1 - v3 load v2 - v4
+i := long 0x1234 plane 3 audio pitch := v5
+";
+        assert_eq!(Ok(vec![
+            0x00, 0xFF, 0x00, 0xFE, 0x00, 0xC4, 0x00, 0xD2, 0x00, 0xFC, 0x00, 0xFB, 0x00, 0xFD,
+            0xF7, 0x75, 0xF7, 0x85, 0x51, 0x32, 0x52, 0x43,
+            0xF0, 0x00, 0x12, 0x34, 0xF3, 0x01, 0xF0, 0x02, 0xF5, 0x3A,
+        ]), compile(source));
+    }
+
+    #[test]
+    fn labels_and_main() {
+        let source = "\
+: sprite-data 0xF0 0x90
+: main  # comment
+    i := sprite-data
+    draw
+    jump later
+: draw
+    sprite v0 v0 2 ;
+: later
+    i := long later
+";
+        assert_eq!(Ok(vec![
+            0x12, 0x04, 0xF0, 0x90, 0xA2, 0x02, 0x22, 0x0A, 0x12, 0x0E, 0xD0, 0x02,
+            0x00, 0xEE, 0xF0, 0x00, 0x02, 0x0E,
+        ]), compile(source));
+    }
+
+    #[test]
+    fn constants_aliases_and_calc() {
+        let source = "\
+:const SPEED 3
+:alias x v4
+:alias compare-temp ve
+:calc double { SPEED * 2 }
+:calc mixed { 1 + 2 * 3 }
+:calc grouped { ( 1 + 2 ) * 3 }
+x := SPEED
+x += double
+:byte mixed
+:byte grouped
+:byte { HERE - 0x200 }
+:org 0x210
+:next target
+v0 := 0
+if x > 5 then v0 := 1
+";
+        assert_eq!(Ok(vec![
+            0x64, 0x03, 0x74, 0x06, 0x07, 0x09, 0x06, 0x00,
+            0, 0, 0, 0, 0, 0, 0, 0,
+            0x60, 0x00, 0x6E, 0x05, 0x8E, 0x45, 0x3F, 0x01, 0x60, 0x01,
+        ]), compile(source));
+    }
+
+    #[test]
+    fn macros() {
+        let source = "\
+:macro move reg amount { reg += amount }
+:macro twice body { body body }
+move v1 3
+twice clear
+";
+        assert_eq!(Ok(vec![0x71, 0x03, 0x00, 0xE0, 0x00, 0xE0]), compile(source));
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "\
+loop
+    v0 += 1
+    while v0 != 10
+    if v1 key then v2 := 1
+    if v1 == v2 begin
+        v3 := 1
+    else
+        v3 := 2
+    end
+again
+";
+        assert_eq!(Ok(vec![
+            0x70, 0x01, 0x40, 0x0A, 0x12, 0x16, 0xE1, 0xA1, 0x62, 0x01,
+            0x51, 0x20, 0x12, 0x12, 0x63, 0x01, 0x12, 0x14, 0x63, 0x02,
+            0x12, 0x00,
+        ]), compile(source));
+    }
+
+    #[test]
+    fn unpack() {
+        let source = "\
+:unpack 0xA data
+:unpack long data
+: data
+";
+        assert_eq!(Ok(vec![0x60, 0xA2, 0x61, 0x08, 0x60, 0x02, 0x61, 0x08]), compile(source));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err(OctoError { line: 2, message: "undefined name: nowhere".into() }),
+                   compile("clear\njump nowhere"));
+        assert_eq!(Err(OctoError { line: 1, message: "256 does not fit in a byte: 256".into() }),
+                   compile("v0 := 256"));
+        assert_eq!(Err(OctoError { line: 2, message: "x is already defined".into() }),
+                   compile(": x\n: x"));
+        assert_eq!(Err(OctoError { line: 1, message: "this 'loop' is missing its 'again'".into() }),
+                   compile("loop clear"));
+        assert_eq!(Err(OctoError { line: 1, message: "'end' without 'begin'".into() }),
+                   compile("end"));
+        assert_eq!(Err(OctoError { line: 1, message: "unexpected end of program".into() }),
+                   compile("v0 :="));
+        assert_eq!(Err(OctoError { line: 2, message: "native machine code calls are not supported".into() }),
+                   compile("clear\nnative 0x123"));
+        assert_eq!(Err(OctoError { line: 3, message: "'again' needs a jump to 0x1000, past 0xFFF".into() }),
+                   compile(":org 0x1000\nloop clear\nagain"));
+        assert_eq!(Err(OctoError { line: 3, message: "'end' needs a jump to 0x1004, past 0xFFF".into() }),
+                   compile(":org 0xFFE\nif v0 == 1 begin clear\nend"));
+    }
+
+    #[test]
+    fn runs_on_the_cpu() {
+        let source = "\
+: main
+    v0 := 0
+    loop
+        v0 += 3
+        if v0 != 12 then
+    again
+    exit
+";
+        let mut cpu = Cpu::new();
+        cpu.load_program(&compile(source).unwrap()).unwrap();
+        let keypad = KeyPad::new();
+        while !cpu.has_exited() {
+            cpu.cycle(&keypad).unwrap();
+        }
+        assert_eq!(12, cpu.registers()[0]);
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        let roms: &[&[u8]] = &[
+            include_bytes!("../roms/BC_test.ch8"),
+            include_bytes!("../roms/pong.rom"),
+            include_bytes!("../roms/blinky.rom"),
+        ];
+        for rom in roms {
+            assert_eq!(Ok(rom.to_vec()), compile(&disasm::disassemble(rom, Syntax::Octo)));
+        }
+    }
+}
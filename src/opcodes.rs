@@ -15,9 +15,9 @@ pub enum Op {
     Xor(u8, u8),
     AddReg(u8, u8),
     Sub(u8, u8),
-    Shr(u8),
+    Shr(u8, u8),
     Subn(u8, u8),
-    Shl(u8),
+    Shl(u8, u8),
     SneReg(u8, u8),
     LdI(u16),
     JpRegI(u16),
@@ -34,27 +34,46 @@ pub enum Op {
     LdBCD(u8),
     LdRegs(u8),
     RdMem(u8),
+    Scd(u8),
+    Scu(u8),
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    LdHf(u8),
+    SaveFlags(u8),
+    LoadFlags(u8),
+    SaveRange(u8, u8),
+    LoadRange(u8, u8),
+    LdILong,
+    Plane(u8),
+    Audio,
+    Pitch(u8),
 }
 
-impl From<u16> for Op {
-    fn from(op: u16) -> Self {
-        decode(op)
-    }
-}
-
-pub fn decode(opcode: u16) -> Op {
+pub fn decode(opcode: u16) -> Option<Op> {
     let components = opcode_components(opcode);
     let addr = opcode & 0x0FFF;
     let byte = (opcode & 0x00FF) as u8;
 
-    match components {
+    let op = match components {
         (0x0, 0x0, 0xE, 0x0) => Op::Cls,
         (0x0, 0x0, 0xE, 0xE) => Op::Ret,
+        (0x0, 0x0, 0xC, n) => Op::Scd(n),
+        (0x0, 0x0, 0xD, n) => Op::Scu(n),
+        (0x0, 0x0, 0xF, 0xB) => Op::Scr,
+        (0x0, 0x0, 0xF, 0xC) => Op::Scl,
+        (0x0, 0x0, 0xF, 0xD) => Op::Exit,
+        (0x0, 0x0, 0xF, 0xE) => Op::Low,
+        (0x0, 0x0, 0xF, 0xF) => Op::High,
         (0x1, _, _, _) => Op::Jp(addr),
         (0x2, _, _, _) => Op::Call(addr),
         (0x3, x, _, _) => Op::Se(x, byte),
         (0x4, x, _, _) => Op::Sne(x, byte),
         (0x5, x, y, 0x0) => Op::SeReg(x, y),
+        (0x5, x, y, 0x2) => Op::SaveRange(x, y),
+        (0x5, x, y, 0x3) => Op::LoadRange(x, y),
         (0x6, x, _, _) => Op::Ld(x, byte),
         (0x7, x, _, _) => Op::Add(x, byte),
         (0x8, x, y, 0x0) => Op::LdReg(x, y),
@@ -63,9 +82,9 @@ pub fn decode(opcode: u16) -> Op {
         (0x8, x, y, 0x3) => Op::Xor(x, y),
         (0x8, x, y, 0x4) => Op::AddReg(x, y),
         (0x8, x, y, 0x5) => Op::Sub(x, y),
-        (0x8, x, _, 0x6) => Op::Shr(x),
+        (0x8, x, y, 0x6) => Op::Shr(x, y),
         (0x8, x, y, 0x7) => Op::Subn(x, y),
-        (0x8, x, _, 0xE) => Op::Shl(x),
+        (0x8, x, y, 0xE) => Op::Shl(x, y),
         (0x9, x, y, 0x0) => Op::SneReg(x, y),
         (0xA, _, _, _) => Op::LdI(addr),
         (0xB, _, _, _) => Op::JpRegI(addr),
@@ -73,17 +92,25 @@ pub fn decode(opcode: u16) -> Op {
         (0xD, x, y, n) => Op::Drw(x, y, n),
         (0xE, x, 0x9, 0xE) => Op::Skp(x),
         (0xE, x, 0xA, 0x1) => Op::Sknp(x),
+        (0xF, 0x0, 0x0, 0x0) => Op::LdILong,
+        (0xF, n, 0x0, 0x1) => Op::Plane(n),
+        (0xF, 0x0, 0x0, 0x2) => Op::Audio,
         (0xF, x, 0x0, 0x7) => Op::LdDT(x),
         (0xF, x, 0x0, 0xA) => Op::LdKb(x),
         (0xF, x, 0x1, 0x5) => Op::SetDT(x),
         (0xF, x, 0x1, 0x8) => Op::SetST(x),
         (0xF, x, 0x1, 0xE) => Op::AddToI(x),
         (0xF, x, 0x2, 0x9) => Op::LdChr(x),
+        (0xF, x, 0x3, 0x0) => Op::LdHf(x),
         (0xF, x, 0x3, 0x3) => Op::LdBCD(x),
+        (0xF, x, 0x3, 0xA) => Op::Pitch(x),
         (0xF, x, 0x5, 0x5) => Op::LdRegs(x),
         (0xF, x, 0x6, 0x5) => Op::RdMem(x),
-        _ => panic!("Invalid opcode: {:X?}", opcode),
-    }
+        (0xF, x, 0x7, 0x5) => Op::SaveFlags(x),
+        (0xF, x, 0x8, 0x5) => Op::LoadFlags(x),
+        _ => return None,
+    };
+    Some(op)
 }
 
 fn opcode_components(opcode: u16) -> (u8, u8, u8, u8) {
@@ -101,171 +128,258 @@ mod test {
 
     #[test]
     fn cls() {
-        assert_eq!(Op::Cls, decode(0x00E0))
+        assert_eq!(Some(Op::Cls), decode(0x00E0))
     }
 
     #[test]
     fn ret() {
-        assert_eq!(Op::Ret, decode(0x00EE))
+        assert_eq!(Some(Op::Ret), decode(0x00EE))
     }
 
     #[test]
     fn jp() {
-        assert_eq!(Op::Jp(0x0234), decode(0x1234))
+        assert_eq!(Some(Op::Jp(0x0234)), decode(0x1234))
     }
 
     #[test]
     fn call() {
-        assert_eq!(Op::Call(0x0345), decode(0x2345))
+        assert_eq!(Some(Op::Call(0x0345)), decode(0x2345))
     }
 
     #[test]
     fn se() {
-        assert_eq!(Op::Se(0x04, 0x56), decode(0x3456))
+        assert_eq!(Some(Op::Se(0x04, 0x56)), decode(0x3456))
     }
 
     #[test]
     fn sne() {
-        assert_eq!(Op::Sne(0x05, 0x67), decode(0x4567))
+        assert_eq!(Some(Op::Sne(0x05, 0x67)), decode(0x4567))
     }
 
     #[test]
     fn se_reg() {
-        assert_eq!(Op::SeReg(0x06, 0x07), decode(0x5670))
+        assert_eq!(Some(Op::SeReg(0x06, 0x07)), decode(0x5670))
     }
 
     #[test]
     fn ld() {
-        assert_eq!(Op::Ld(0x07, 0x89), decode(0x6789))
+        assert_eq!(Some(Op::Ld(0x07, 0x89)), decode(0x6789))
     }
 
     #[test]
     fn add() {
-        assert_eq!(Op::Add(0x08, 0x90), decode(0x7890))
+        assert_eq!(Some(Op::Add(0x08, 0x90)), decode(0x7890))
     }
 
     #[test]
     fn ld_reg() {
-        assert_eq!(Op::LdReg(0x09, 0x01), decode(0x8910))
+        assert_eq!(Some(Op::LdReg(0x09, 0x01)), decode(0x8910))
     }
 
     #[test]
     fn or() {
-        assert_eq!(Op::Or(0x09, 0x01), decode(0x8911))
+        assert_eq!(Some(Op::Or(0x09, 0x01)), decode(0x8911))
     }
 
     #[test]
     fn and() {
-        assert_eq!(Op::And(0x09, 0x07), decode(0x8972))
+        assert_eq!(Some(Op::And(0x09, 0x07)), decode(0x8972))
     }
 
     #[test]
     fn xor() {
-        assert_eq!(Op::Xor(0x09, 0x07), decode(0x8973))
+        assert_eq!(Some(Op::Xor(0x09, 0x07)), decode(0x8973))
     }
 
     #[test]
     fn add_reg() {
-        assert_eq!(Op::AddReg(0x09, 0x07), decode(0x8974))
+        assert_eq!(Some(Op::AddReg(0x09, 0x07)), decode(0x8974))
     }
 
     #[test]
     fn sub() {
-        assert_eq!(Op::Sub(0x09, 0x07), decode(0x8975))
+        assert_eq!(Some(Op::Sub(0x09, 0x07)), decode(0x8975))
     }
 
     #[test]
     fn shr() {
-        assert_eq!(Op::Shr(0x09), decode(0x8906))
+        assert_eq!(Some(Op::Shr(0x09, 0x00)), decode(0x8906))
     }
 
     #[test]
     fn subn() {
-        assert_eq!(Op::Subn(0x09, 0x07), decode(0x8977))
+        assert_eq!(Some(Op::Subn(0x09, 0x07)), decode(0x8977))
     }
 
     #[test]
     fn shl() {
-        assert_eq!(Op::Shl(0x09), decode(0x897E))
+        assert_eq!(Some(Op::Shl(0x09, 0x07)), decode(0x897E))
     }
 
     #[test]
     fn sne_reg() {
-        assert_eq!(Op::SneReg(0x01, 0x02), decode(0x9120))
+        assert_eq!(Some(Op::SneReg(0x01, 0x02)), decode(0x9120))
     }
 
     #[test]
     fn ldi() {
-        assert_eq!(Op::LdI(0x0123), decode(0xA123))
+        assert_eq!(Some(Op::LdI(0x0123)), decode(0xA123))
     }
 
     #[test]
     fn jp_reg_i() {
-        assert_eq!(Op::JpRegI(0x0123), decode(0xB123))
+        assert_eq!(Some(Op::JpRegI(0x0123)), decode(0xB123))
     }
 
     #[test]
     fn rand() {
-        assert_eq!(Op::Rnd(0x0F, 0x12), decode(0xCF12))
+        assert_eq!(Some(Op::Rnd(0x0F, 0x12)), decode(0xCF12))
     }
 
     #[test]
     fn drw() {
-        assert_eq!(Op::Drw(0x05, 0x06, 0x07), decode(0xD567))
+        assert_eq!(Some(Op::Drw(0x05, 0x06, 0x07)), decode(0xD567))
     }
 
     #[test]
     fn skp() {
-        assert_eq!(Op::Skp(0x3), decode(0xE39E))
+        assert_eq!(Some(Op::Skp(0x3)), decode(0xE39E))
     }
 
     #[test]
     fn sknp() {
-        assert_eq!(Op::Sknp(0x03), decode(0xE3A1))
+        assert_eq!(Some(Op::Sknp(0x03)), decode(0xE3A1))
     }
 
     #[test]
     fn ld_dt() {
-        assert_eq!(Op::LdDT(0x09), decode(0xF907))
+        assert_eq!(Some(Op::LdDT(0x09)), decode(0xF907))
     }
 
     #[test]
     fn ld_kb() {
-        assert_eq!(Op::LdKb(0x09), decode(0xF90A))
+        assert_eq!(Some(Op::LdKb(0x09)), decode(0xF90A))
     }
 
     #[test]
     fn set_dt() {
-        assert_eq!(Op::SetDT(0x09), decode(0xF915))
+        assert_eq!(Some(Op::SetDT(0x09)), decode(0xF915))
     }
 
     #[test]
     fn set_st() {
-        assert_eq!(Op::SetST(0x09), decode(0xF918))
+        assert_eq!(Some(Op::SetST(0x09)), decode(0xF918))
     }
 
     #[test]
     fn add_to_i() {
-        assert_eq!(Op::AddToI(0x09), decode(0xF91E))
+        assert_eq!(Some(Op::AddToI(0x09)), decode(0xF91E))
     }
 
     #[test]
     fn ld_chr() {
-        assert_eq!(Op::LdChr(0x09), decode(0xF929))
+        assert_eq!(Some(Op::LdChr(0x09)), decode(0xF929))
     }
 
     #[test]
     fn ld_bcd() {
-        assert_eq!(Op::LdBCD(0x09), decode(0xF933))
+        assert_eq!(Some(Op::LdBCD(0x09)), decode(0xF933))
     }
 
     #[test]
     fn ld_regs() {
-        assert_eq!(Op::LdRegs(0x09), decode(0xF955))
+        assert_eq!(Some(Op::LdRegs(0x09)), decode(0xF955))
     }
 
     #[test]
     fn rd_mem() {
-        assert_eq!(Op::RdMem(0x09), decode(0xF965))
+        assert_eq!(Some(Op::RdMem(0x09)), decode(0xF965))
+    }
+
+    #[test]
+    fn scd() {
+        assert_eq!(Some(Op::Scd(0x04)), decode(0x00C4))
+    }
+
+    #[test]
+    fn scu() {
+        assert_eq!(Some(Op::Scu(0x02)), decode(0x00D2))
+    }
+
+    #[test]
+    fn scr() {
+        assert_eq!(Some(Op::Scr), decode(0x00FB))
+    }
+
+    #[test]
+    fn scl() {
+        assert_eq!(Some(Op::Scl), decode(0x00FC))
+    }
+
+    #[test]
+    fn exit() {
+        assert_eq!(Some(Op::Exit), decode(0x00FD))
+    }
+
+    #[test]
+    fn low() {
+        assert_eq!(Some(Op::Low), decode(0x00FE))
+    }
+
+    #[test]
+    fn high() {
+        assert_eq!(Some(Op::High), decode(0x00FF))
+    }
+
+    #[test]
+    fn ld_hf() {
+        assert_eq!(Some(Op::LdHf(0x09)), decode(0xF930))
+    }
+
+    #[test]
+    fn save_flags() {
+        assert_eq!(Some(Op::SaveFlags(0x07)), decode(0xF775))
+    }
+
+    #[test]
+    fn load_flags() {
+        assert_eq!(Some(Op::LoadFlags(0x07)), decode(0xF785))
+    }
+
+    #[test]
+    fn save_range() {
+        assert_eq!(Some(Op::SaveRange(0x01, 0x04)), decode(0x5142))
+    }
+
+    #[test]
+    fn load_range() {
+        assert_eq!(Some(Op::LoadRange(0x04, 0x01)), decode(0x5413))
+    }
+
+    #[test]
+    fn ld_i_long() {
+        assert_eq!(Some(Op::LdILong), decode(0xF000))
+    }
+
+    #[test]
+    fn plane() {
+        assert_eq!(Some(Op::Plane(0x03)), decode(0xF301))
+    }
+
+    #[test]
+    fn audio() {
+        assert_eq!(Some(Op::Audio), decode(0xF002))
+    }
+
+    #[test]
+    fn pitch() {
+        assert_eq!(Some(Op::Pitch(0x05)), decode(0xF53A))
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(None, decode(0x5671));
+        assert_eq!(None, decode(0xE000));
+        assert_eq!(None, decode(0xFFFF));
     }
 }
@@ -0,0 +1,138 @@
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct Quirks {
+    pub shift_uses_vy: bool,
+    pub load_store_increments_i: bool,
+    pub jump_uses_vx: bool,
+    pub draw_wraps: bool,
+    pub logic_resets_vf: bool,
+    /// With `load_store_increments_i`, Fx55/Fx65 leave I at I+X (CHIP-48) instead of I+X+1.
+    pub load_store_increments_i_by_x: bool,
+    /// DXYN waits for the next frame, so a ROM draws at most one sprite per frame.
+    pub display_wait: bool,
+    /// XO-CHIP: 64 KiB of memory instead of 4 KiB, and skips step over a whole F000 NNNN.
+    pub xo_chip: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        draw_wraps: false,
+        logic_resets_vf: true,
+        load_store_increments_i_by_x: false,
+        display_wait: true,
+        xo_chip: false,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: true,
+        jump_uses_vx: true,
+        draw_wraps: false,
+        logic_resets_vf: false,
+        load_store_increments_i_by_x: true,
+        display_wait: false,
+        xo_chip: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        draw_wraps: false,
+        logic_resets_vf: false,
+        load_store_increments_i_by_x: false,
+        display_wait: false,
+        xo_chip: false,
+    };
+
+    pub const OCTO: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        draw_wraps: true,
+        logic_resets_vf: false,
+        load_store_increments_i_by_x: false,
+        display_wait: false,
+        xo_chip: false,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        draw_wraps: true,
+        logic_resets_vf: false,
+        load_store_increments_i_by_x: false,
+        display_wait: false,
+        xo_chip: true,
+    };
+
+    pub fn to_bits(&self) -> u8 {
+        (self.shift_uses_vy as u8)
+            | (self.load_store_increments_i as u8) << 1
+            | (self.jump_uses_vx as u8) << 2
+            | (self.draw_wraps as u8) << 3
+            | (self.logic_resets_vf as u8) << 4
+            | (self.load_store_increments_i_by_x as u8) << 5
+            | (self.display_wait as u8) << 6
+            | (self.xo_chip as u8) << 7
+    }
+
+    pub fn from_bits(bits: u8) -> Option<Quirks> {
+        Some(Quirks {
+            shift_uses_vy: bits & 1 != 0,
+            load_store_increments_i: bits & (1 << 1) != 0,
+            jump_uses_vx: bits & (1 << 2) != 0,
+            draw_wraps: bits & (1 << 3) != 0,
+            logic_resets_vf: bits & (1 << 4) != 0,
+            load_store_increments_i_by_x: bits & (1 << 5) != 0,
+            display_wait: bits & (1 << 6) != 0,
+            xo_chip: bits & (1 << 7) != 0,
+        })
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP_48),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::SUPER_CHIP),
+            "octo" | "modern" => Some(Quirks::OCTO),
+            "xochip" | "xo-chip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_name() {
+        assert_eq!(Some(Quirks::COSMAC_VIP), Quirks::from_name("vip"));
+        assert_eq!(Some(Quirks::CHIP_48), Quirks::from_name("CHIP-48"));
+        assert_eq!(Some(Quirks::SUPER_CHIP), Quirks::from_name("schip"));
+        assert_eq!(Some(Quirks::OCTO), Quirks::from_name("modern"));
+        assert_eq!(Some(Quirks::XO_CHIP), Quirks::from_name("xo-chip"));
+        assert_eq!(None, Quirks::from_name("chip-9"));
+    }
+
+    #[test]
+    fn bits_round_trip() {
+        for quirks in &[Quirks::default(), Quirks::COSMAC_VIP, Quirks::CHIP_48, Quirks::SUPER_CHIP,
+                        Quirks::OCTO, Quirks::XO_CHIP] {
+            assert_eq!(Some(*quirks), Quirks::from_bits(quirks.to_bits()));
+        }
+        assert_eq!(Some(Quirks::XO_CHIP), Quirks::from_bits(Quirks::XO_CHIP.to_bits()));
+    }
+
+    #[test]
+    fn presets_differ() {
+        let presets = [Quirks::COSMAC_VIP, Quirks::CHIP_48, Quirks::SUPER_CHIP, Quirks::OCTO, Quirks::XO_CHIP];
+        for (n, a) in presets.iter().enumerate() {
+            assert!(presets[n + 1..].iter().all(|b| a != b), "{:?}", a);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use super::state::{StateError, StateReader, StateWriter};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RandomMode {
+    SplitMix,
+    /// Approximates the COSMAC VIP interpreter: a byte of the interpreter page
+    /// selected by the high counter byte is added to the low byte, which the
+    /// 60 Hz interrupt increments. The page is `VIP_PAGE`, not emulated memory.
+    CosmacVip,
+}
+
+/// Stands in for the second page (0x100-0x1FF) of the VIP CHIP-8 interpreter, which is
+/// not bundled: a fixed byte permutation, so the generator cycles through every value.
+/// Replace it with a dump of the interpreter to reproduce the VIP's exact sequence.
+pub const VIP_PAGE: [u8; 256] = vip_page();
+
+const fn vip_page() -> [u8; 256] {
+    let mut page = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        // 167 is odd, so n -> 167n + 59 is a permutation of the bytes.
+        page[n] = (n as u8).wrapping_mul(167).wrapping_add(59);
+        n += 1;
+    }
+    page
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RandomSource {
+    mode: RandomMode,
+    state: u64,
+}
+
+impl RandomSource {
+    pub fn new(seed: u64) -> RandomSource {
+        RandomSource { mode: RandomMode::SplitMix, state: seed }
+    }
+
+    pub fn cosmac_vip(seed: u64) -> RandomSource {
+        RandomSource { mode: RandomMode::CosmacVip, state: seed & 0xFFFF }
+    }
+
+    pub fn mode(&self) -> RandomMode {
+        self.mode
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        match self.mode {
+            RandomMode::SplitMix => {
+                self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut z = self.state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                (z ^ (z >> 31)) as u8
+            }
+            RandomMode::CosmacVip => {
+                let (high, low) = ((self.state >> 8) as u8, self.state as u8);
+                let value = VIP_PAGE[high as usize].wrapping_add(low);
+                self.state = (value as u64) << 8 | low as u64;
+                value
+            }
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if self.mode == RandomMode::CosmacVip {
+            let low = (self.state as u8).wrapping_add(1);
+            self.state = (self.state & 0xFF00) | low as u64;
+        }
+    }
+
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        writer.u8(match self.mode {
+            RandomMode::SplitMix => 0,
+            RandomMode::CosmacVip => 1,
+        });
+        writer.u64(self.state);
+    }
+
+    pub(crate) fn load(reader: &mut StateReader) -> Result<RandomSource, StateError> {
+        let mode = match reader.u8()? {
+            0 => RandomMode::SplitMix,
+            1 => RandomMode::CosmacVip,
+            _ => return Err(StateError::InvalidField("random mode")),
+        };
+        let state = reader.u64()?;
+        if mode == RandomMode::CosmacVip && state > 0xFFFF {
+            return Err(StateError::InvalidField("random state"));
+        }
+        Ok(RandomSource { mode, state })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let (mut a, mut b) = (RandomSource::new(42), RandomSource::new(42));
+        let first: Vec<u8> = (0..32).map(|_| a.next_byte()).collect();
+        let second: Vec<u8> = (0..32).map(|_| b.next_byte()).collect();
+        assert_eq!(first, second);
+        assert_ne!(first, (0..32).map(|_| RandomSource::new(43).next_byte()).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn cosmac_vip() {
+        let mut r = RandomSource::cosmac_vip(0x1205);
+        let first = VIP_PAGE[0x12].wrapping_add(0x05);
+        assert_eq!(first, r.next_byte());
+        r.tick();
+        assert_eq!(VIP_PAGE[first as usize].wrapping_add(0x06), r.next_byte());
+    }
+
+    #[test]
+    fn save_load() {
+        let mut r = RandomSource::cosmac_vip(0x1234);
+        r.tick();
+        let mut w = StateWriter::new();
+        r.save(&mut w);
+        let data = w.into_inner();
+        assert_eq!(Ok(r), RandomSource::load(&mut StateReader::new(&data)));
+
+        let mut w = StateWriter::new();
+        w.u8(1);
+        w.u64(0x1_0000);
+        let data = w.into_inner();
+        assert_eq!(Err(StateError::InvalidField("random state")), RandomSource::load(&mut StateReader::new(&data)));
+    }
+}
@@ -0,0 +1,200 @@
+use super::{cpu::Cpu, state::StateError};
+
+use std::collections::VecDeque;
+
+struct Delta {
+    len: usize,
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    fn between(older: &[u8], newer: &[u8]) -> Delta {
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut offset = 0;
+        while offset < older.len() {
+            if newer.get(offset) == Some(&older[offset]) {
+                offset += 1;
+                continue;
+            }
+            let start = offset;
+            while offset < older.len() && newer.get(offset) != Some(&older[offset]) {
+                offset += 1;
+            }
+            runs.push((start, older[start..offset].to_vec()));
+        }
+        Delta { len: older.len(), runs }
+    }
+
+    fn apply(&self, newer: &[u8]) -> Vec<u8> {
+        let mut older = newer.to_vec();
+        older.resize(self.len, 0);
+        for (start, bytes) in self.runs.iter() {
+            older[*start..*start + bytes.len()].copy_from_slice(bytes);
+        }
+        older
+    }
+
+    fn size(&self) -> usize {
+        self.runs.iter().map(|(_, bytes)| bytes.len()).sum()
+    }
+}
+
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    history: VecDeque<Delta>,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer { capacity, latest: None, history: VecDeque::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.latest.as_ref().map_or(0, |l| l.len()) + self.history.iter().map(Delta::size).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.history.clear();
+    }
+
+    pub fn push(&mut self, cpu: &Cpu) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.push_state(cpu.save_state());
+    }
+
+    fn push_state(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            self.history.push_back(Delta::between(&previous, &state));
+        }
+        self.latest = Some(state);
+        while self.len() > self.capacity {
+            self.history.pop_front();
+        }
+    }
+
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        self.latest = self.history.pop_back().map(|delta| delta.apply(&latest));
+        Some(latest)
+    }
+
+    /// Goes back `steps` snapshots before the current state and keeps the restored one as
+    /// the latest snapshot.
+    pub fn rewind(&mut self, cpu: &mut Cpu, steps: usize) -> Result<bool, StateError> {
+        // The snapshot of the frame that just ran is the state we are rewinding from.
+        let current = cpu.save_state();
+        if self.latest.as_ref() == Some(&current) {
+            if self.history.is_empty() {
+                return Ok(false);
+            }
+            self.pop();
+        }
+        let mut state = None;
+        for _ in 0..steps.max(1) {
+            match self.pop() {
+                Some(s) => state = Some(s),
+                None => break,
+            }
+        }
+        match state {
+            Some(s) => {
+                cpu.load_state(&s)?;
+                self.push_state(s);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use keypad::KeyPad;
+
+    const PROGRAM: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    fn cpu() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&PROGRAM).unwrap();
+        cpu
+    }
+
+    fn v0(cpu: &Cpu) -> u8 {
+        cpu.registers()[0]
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let older = vec![1, 2, 3, 4, 5, 6];
+        let newer = vec![1, 9, 9, 4, 5, 7, 8];
+        let delta = Delta::between(&older, &newer);
+        assert_eq!(3, delta.size());
+        assert_eq!(older, delta.apply(&newer));
+    }
+
+    #[test]
+    fn rewind_steps() {
+        let mut c = cpu();
+        let mut buffer = RewindBuffer::new(100);
+        for _ in 0..10 {
+            c.cycle(&KeyPad::new()).unwrap();
+            c.cycle(&KeyPad::new()).unwrap();
+            buffer.push(&c);
+        }
+        assert_eq!(10, v0(&c));
+        assert!(buffer.rewind(&mut c, 1).unwrap());
+        assert_eq!(9, v0(&c));
+        assert!(buffer.rewind(&mut c, 3).unwrap());
+        assert_eq!(6, v0(&c));
+        assert_eq!(6, buffer.len());
+
+        // Running on from a rewound state pushes new frames after it.
+        c.cycle(&KeyPad::new()).unwrap();
+        c.cycle(&KeyPad::new()).unwrap();
+        buffer.push(&c);
+        assert!(buffer.rewind(&mut c, 1).unwrap());
+        assert_eq!(6, v0(&c));
+    }
+
+    #[test]
+    fn capacity() {
+        let mut c = cpu();
+        let mut buffer = RewindBuffer::new(3);
+        for _ in 0..10 {
+            c.cycle(&KeyPad::new()).unwrap();
+            c.cycle(&KeyPad::new()).unwrap();
+            buffer.push(&c);
+        }
+        assert_eq!(3, buffer.len());
+        assert!(buffer.rewind(&mut c, 10).unwrap());
+        assert_eq!(8, v0(&c));
+        assert_eq!(1, buffer.len());
+        assert!(!buffer.rewind(&mut c, 1).unwrap());
+        assert_eq!(8, v0(&c));
+    }
+
+    #[test]
+    fn deltas_are_small() {
+        let mut c = cpu();
+        let mut buffer = RewindBuffer::new(100);
+        for _ in 0..100 {
+            c.cycle(&KeyPad::new()).unwrap();
+            buffer.push(&c);
+        }
+        let full = c.save_state().len();
+        assert!(buffer.memory_usage() < full * 2);
+    }
+}
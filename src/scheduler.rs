@@ -0,0 +1,172 @@
+use cpu::{Cpu, CpuError};
+use keypad::KeyPad;
+use opcodes::Op;
+
+use std::time::{Duration, Instant};
+
+pub const FRAME_RATE: u32 = 60;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Speed {
+    InstructionsPerFrame(usize),
+    ClockHz(u32),
+}
+
+impl Speed {
+    pub fn cycles_in_frame(&self, frame: u64) -> usize {
+        match *self {
+            Speed::InstructionsPerFrame(n) => n,
+            Speed::ClockHz(hz) => {
+                let hz = hz as u64;
+                let fps = FRAME_RATE as u64;
+                (hz * (frame + 1) / fps - hz * frame / fps) as usize
+            }
+        }
+    }
+
+    /// A speed that never runs an instruction.
+    pub fn is_stopped(&self) -> bool {
+        match *self {
+            Speed::InstructionsPerFrame(n) => n == 0,
+            Speed::ClockHz(hz) => hz == 0,
+        }
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Speed {
+        Speed::InstructionsPerFrame(10)
+    }
+}
+
+pub struct InstructionClock {
+    speed: Speed,
+    frame: u64,
+    cycles_left: usize,
+}
+
+impl InstructionClock {
+    pub fn new(speed: Speed) -> InstructionClock {
+        InstructionClock { speed, frame: 0, cycles_left: speed.cycles_in_frame(0) }
+    }
+
+    pub fn step(&mut self, cpu: &mut Cpu, keypad: &KeyPad) -> Result<Op, CpuError> {
+        let op = cpu.cycle(keypad)?;
+        self.cycles_left = self.cycles_left.saturating_sub(1);
+        while self.cycles_left == 0 && !self.speed.is_stopped() {
+            cpu.update_timers();
+            self.frame += 1;
+            self.cycles_left = self.speed.cycles_in_frame(self.frame);
+        }
+        Ok(op)
+    }
+}
+
+pub struct FramePacer {
+    frame_duration: Duration,
+    next_frame: Instant,
+    max_catch_up: u32,
+}
+
+impl FramePacer {
+    pub fn new(start: Instant) -> FramePacer {
+        FramePacer {
+            frame_duration: Duration::from_secs(1) / FRAME_RATE,
+            next_frame: start,
+            max_catch_up: 4,
+        }
+    }
+
+    pub fn with_max_catch_up(mut self, frames: u32) -> FramePacer {
+        self.max_catch_up = frames.max(1);
+        self
+    }
+
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+        if now < self.next_frame {
+            return 0;
+        }
+        let late = now - self.next_frame;
+        let due = (late.as_nanos() / self.frame_duration.as_nanos()) as u32 + 1;
+        if due > self.max_catch_up {
+            self.next_frame = now + self.frame_duration;
+            return self.max_catch_up;
+        }
+        self.next_frame += self.frame_duration * due;
+        due
+    }
+
+    pub fn time_until_next_frame(&self, now: Instant) -> Duration {
+        if self.next_frame > now { self.next_frame - now } else { Duration::from_secs(0) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn instructions_per_frame() {
+        assert_eq!(7, Speed::InstructionsPerFrame(7).cycles_in_frame(12));
+    }
+
+    #[test]
+    fn clock_hz() {
+        let speed = Speed::ClockHz(500);
+        let total: usize = (0..60).map(|f| speed.cycles_in_frame(f)).sum();
+        assert_eq!(500, total);
+        assert!((0..60).all(|f| speed.cycles_in_frame(f) == 8 || speed.cycles_in_frame(f) == 9));
+    }
+
+    #[test]
+    fn instruction_clock_updates_timers() {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04]).unwrap();
+        let mut clock = InstructionClock::new(Speed::InstructionsPerFrame(2));
+        for _ in 0..4 {
+            clock.step(&mut cpu, &KeyPad::new()).unwrap();
+        }
+        assert_eq!(3, cpu.delay_timer());
+    }
+
+    #[test]
+    fn instruction_clock_stopped() {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&[0x12, 0x00]).unwrap();
+        assert!(Speed::ClockHz(0).is_stopped());
+        assert!(!Speed::ClockHz(1).is_stopped());
+        let mut clock = InstructionClock::new(Speed::InstructionsPerFrame(0));
+        clock.step(&mut cpu, &KeyPad::new()).unwrap();
+    }
+
+    #[test]
+    fn frames_due_on_time() {
+        let start = Instant::now();
+        let frame = Duration::from_secs(1) / 60;
+        let mut pacer = FramePacer::new(start);
+        assert_eq!(1, pacer.frames_due(start));
+        assert_eq!(0, pacer.frames_due(start + frame / 2));
+        assert_eq!(frame / 2, pacer.time_until_next_frame(start + frame / 2));
+        assert_eq!(1, pacer.frames_due(start + frame));
+    }
+
+    #[test]
+    fn frames_due_catch_up() {
+        let start = Instant::now();
+        let frame = Duration::from_secs(1) / 60;
+        let mut pacer = FramePacer::new(start);
+        assert_eq!(3, pacer.frames_due(start + frame * 2));
+        assert_eq!(0, pacer.frames_due(start + frame * 2));
+        assert_eq!(1, pacer.frames_due(start + frame * 3));
+    }
+
+    #[test]
+    fn frames_due_resync() {
+        let start = Instant::now();
+        let frame = Duration::from_secs(1) / 60;
+        let mut pacer = FramePacer::new(start).with_max_catch_up(2);
+        assert_eq!(2, pacer.frames_due(start + frame * 30));
+        assert_eq!(0, pacer.frames_due(start + frame * 30));
+        assert_eq!(1, pacer.frames_due(start + frame * 31));
+    }
+}
@@ -1,8 +1,15 @@
+use super::state::{StateError, StateReader, StateWriter};
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
 pub struct Pixel {
     x: usize,
     y: usize,
-    on: bool,
+    planes: u8,
 }
 
 impl Pixel {
@@ -15,43 +22,158 @@ impl Pixel {
     }
 
     pub fn on(&self) -> bool {
-        self.on
+        self.planes != 0
+    }
+
+    pub fn color(&self) -> u8 {
+        self.planes
     }
 }
 
 pub struct Screen {
-    pixels: [Pixel; 2048],
+    width: usize,
+    height: usize,
+    pixels: Vec<Pixel>,
+    selected_planes: u8,
+}
+
+impl Default for Screen {
+    fn default() -> Screen {
+        Screen::new()
+    }
 }
 
 impl Screen {
     pub fn new() -> Screen {
-        let mut pixels = [Pixel::default(); 2048];
-        for y in 0..32 {
-            for x in 0..64 {
-                let p = &mut pixels[(y * 64) + x];
+        Screen::with_size(LORES_WIDTH, LORES_HEIGHT)
+    }
+
+    fn with_size(width: usize, height: usize) -> Screen {
+        let mut pixels = vec![Pixel::default(); width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let p = &mut pixels[(y * width) + x];
                 p.x = x;
                 p.y = y;
             }
         }
-        Screen { pixels }
+        Screen { width, height, pixels, selected_planes: 1 }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
+
+    pub fn set_hires(&mut self, hires: bool) {
+        let selected_planes = self.selected_planes;
+        *self = if hires {
+            Screen::with_size(HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            Screen::with_size(LORES_WIDTH, LORES_HEIGHT)
+        };
+        self.selected_planes = selected_planes;
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & 0b11;
     }
 
     pub fn clear(&mut self) {
-        self.pixels.iter_mut().for_each(|p| p.on = false)
+        let mask = !self.selected_planes;
+        self.pixels.iter_mut().for_each(|p| p.planes &= mask)
     }
 
     pub fn pixels(&self) -> impl Iterator<Item = &Pixel> {
         self.pixels.iter()
     }
 
+    pub fn is_on(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[(y * self.width) + x].on()
+    }
+
     pub fn set_pixel_value(&mut self, x: usize, y: usize, on: bool) -> bool {
-        let index = ((y % 32) * 64) + (x % 64);
-        if on && self.pixels[index].on  {
-            self.pixels[index].on = false;
-            return true
+        self.set_plane_pixel_value(1, x, y, on)
+    }
+
+    pub fn set_plane_pixel_value(&mut self, plane: u8, x: usize, y: usize, on: bool) -> bool {
+        let index = ((y % self.height) * self.width) + (x % self.width);
+        let pixel = &mut self.pixels[index];
+        if on && (pixel.planes & plane) != 0 {
+            pixel.planes &= !plane;
+            true
         } else {
-            self.pixels[index].on = on;
-            return false
+            if on {
+                pixel.planes |= plane;
+            } else {
+                pixel.planes &= !plane;
+            }
+            false
+        }
+    }
+
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.is_hires());
+        writer.u8(self.selected_planes);
+        self.pixels.iter().for_each(|p| writer.u8(p.planes));
+    }
+
+    pub(crate) fn load(reader: &mut StateReader) -> Result<Screen, StateError> {
+        let mut screen = Screen::new();
+        screen.set_hires(reader.bool("screen resolution")?);
+        screen.selected_planes = reader.u8()?;
+        if screen.selected_planes > 0b11 {
+            return Err(StateError::InvalidField("selected planes"));
+        }
+        let planes = reader.bytes(screen.pixels.len())?;
+        for (p, value) in screen.pixels.iter_mut().zip(planes) {
+            if *value > 0b11 {
+                return Err(StateError::InvalidField("screen pixels"));
+            }
+            p.planes = *value;
+        }
+        Ok(screen)
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll(0, lines as isize)
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll(0, -(lines as isize))
+    }
+
+    pub fn scroll_right(&mut self) {
+        self.scroll(4, 0)
+    }
+
+    pub fn scroll_left(&mut self) {
+        self.scroll(-4, 0)
+    }
+
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let mask = self.selected_planes;
+        let previous: Vec<u8> = self.pixels.iter().map(|p| p.planes).collect();
+        let (width, height) = (self.width as isize, self.height as isize);
+        for p in self.pixels.iter_mut() {
+            let (src_x, src_y) = (p.x as isize - dx, p.y as isize - dy);
+            let moved = if src_x >= 0 && src_x < width && src_y >= 0 && src_y < height {
+                previous[(src_y * width + src_x) as usize] & mask
+            } else {
+                0
+            };
+            p.planes = (p.planes & !mask) | moved;
         }
     }
 }
@@ -83,9 +205,9 @@ mod test {
                     let mut screen = Screen::new();
                     let collision = screen.set_pixel_value(x, y, true);
                     let on: Vec<&Pixel> = screen.pixels.iter().filter(|x| x.on()).collect();
-                    assert_eq!(false, collision);
+                    assert!(!collision);
                     assert_eq!(1, on.len());
-                    assert_eq!(&Pixel { x, y, on: true }, on[0]);
+                    assert_eq!(&Pixel { x, y, planes: 1 }, on[0]);
                 }
             }
         }
@@ -99,9 +221,9 @@ mod test {
                     let mut screen = all_on_screen();
                     let collision = screen.set_pixel_value(x, y, false);
                     let off: Vec<&Pixel> = screen.pixels.iter().filter(|x| !x.on()).collect();
-                    assert_eq!(false, collision);
+                    assert!(!collision);
                     assert_eq!(1, off.len());
-                    assert_eq!(&Pixel { x, y, on: false }, off[0]);
+                    assert_eq!(&Pixel { x, y, planes: 0 }, off[0]);
                 }
             }
         }
@@ -122,10 +244,92 @@ mod test {
         s.set_pixel_value(0, 0, true);
         s.clear();
         for p in s.pixels.iter() {
-            assert!(!p.on)
+            assert!(!p.on())
         }
     }
 
+    #[test]
+    fn set_hires() {
+        let mut s = Screen::new();
+        s.set_pixel_value(0, 0, true);
+        s.set_hires(true);
+        assert!(s.is_hires());
+        assert_eq!((128, 64), (s.width(), s.height()));
+        assert_eq!(128 * 64, s.pixels().count());
+        assert!(!s.is_on(0, 0));
+        s.set_pixel_value(127, 63, true);
+        assert!(s.is_on(127, 63));
+        s.set_hires(false);
+        assert_eq!((64, 32), (s.width(), s.height()));
+    }
+
+    #[test]
+    fn scroll_down() {
+        let mut s = Screen::new();
+        s.set_pixel_value(3, 0, true);
+        s.set_pixel_value(3, 31, true);
+        s.scroll_down(2);
+        assert!(s.is_on(3, 2));
+        assert!(!s.is_on(3, 0));
+        assert_eq!(1, s.pixels().filter(|p| p.on()).count());
+    }
+
+    #[test]
+    fn scroll_up() {
+        let mut s = Screen::new();
+        s.set_pixel_value(3, 0, true);
+        s.set_pixel_value(3, 31, true);
+        s.scroll_up(2);
+        assert!(s.is_on(3, 29));
+        assert!(!s.is_on(3, 31));
+        assert_eq!(1, s.pixels().filter(|p| p.on()).count());
+    }
+
+    #[test]
+    fn scroll_left_right() {
+        let mut s = Screen::new();
+        s.set_pixel_value(5, 1, true);
+        s.scroll_right();
+        assert!(s.is_on(9, 1));
+        s.scroll_left();
+        s.scroll_left();
+        assert!(s.is_on(1, 1));
+        s.scroll_left();
+        assert_eq!(0, s.pixels().filter(|p| p.on()).count());
+    }
+
+    #[test]
+    fn planes() {
+        let mut s = Screen::new();
+        s.set_plane_pixel_value(1, 4, 4, true);
+        s.set_plane_pixel_value(2, 4, 4, true);
+        assert_eq!(Some(3), s.pixels().find(|p| p.x() == 4 && p.y() == 4).map(|p| p.color()));
+        assert!(s.set_plane_pixel_value(2, 4, 4, true));
+        assert_eq!(Some(1), s.pixels().find(|p| p.x() == 4 && p.y() == 4).map(|p| p.color()));
+    }
+
+    #[test]
+    fn clear_selected_planes() {
+        let mut s = Screen::new();
+        s.set_plane_pixel_value(1, 1, 1, true);
+        s.set_plane_pixel_value(2, 2, 2, true);
+        s.select_planes(2);
+        s.clear();
+        assert!(s.is_on(1, 1));
+        assert!(!s.is_on(2, 2));
+    }
+
+    #[test]
+    fn scroll_selected_planes() {
+        let mut s = Screen::new();
+        s.set_plane_pixel_value(1, 1, 1, true);
+        s.set_plane_pixel_value(2, 1, 1, true);
+        s.select_planes(2);
+        s.scroll_down(1);
+        assert!(s.is_on(1, 1));
+        assert!(s.is_on(1, 2));
+    }
+
     fn all_on_screen() -> Screen {
         let mut s = Screen::new();
         for y in 0..32 {
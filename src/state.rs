@@ -0,0 +1,273 @@
+use super::screen::Screen;
+
+use std::{
+    error::Error,
+    fmt
+};
+
+pub const STATE_VERSION: u16 = 3;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const THUMBNAIL_WIDTH: usize = 64;
+const THUMBNAIL_HEIGHT: usize = 32;
+const THUMBNAIL_SIZE: usize = THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT / 8;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    RomMismatch { expected: u64, found: u64 },
+    Truncated,
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(v) =>
+                write!(f, "unsupported save state version {} (expected {})", v, STATE_VERSION),
+            StateError::RomMismatch { expected, found } =>
+                write!(f, "save state was made with ROM {:016X}, loaded ROM is {:016X}", found, expected),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::InvalidField(name) => write!(f, "invalid value for {} in save state", name),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Thumbnail {
+    bits: [u8; THUMBNAIL_SIZE],
+}
+
+impl Thumbnail {
+    pub fn from_screen(screen: &Screen) -> Thumbnail {
+        let mut bits = [0; THUMBNAIL_SIZE];
+        let (scale_x, scale_y) =
+            (screen.width() / THUMBNAIL_WIDTH, screen.height() / THUMBNAIL_HEIGHT);
+        for p in screen.pixels().filter(|p| p.on()) {
+            let index = (p.y() / scale_y) * THUMBNAIL_WIDTH + p.x() / scale_x;
+            bits[index / 8] |= 0x80 >> (index % 8);
+        }
+        Thumbnail { bits }
+    }
+
+    pub fn width(&self) -> usize {
+        THUMBNAIL_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        THUMBNAIL_HEIGHT
+    }
+
+    pub fn is_on(&self, x: usize, y: usize) -> bool {
+        let index = y * THUMBNAIL_WIDTH + x;
+        x < THUMBNAIL_WIDTH && y < THUMBNAIL_HEIGHT && self.bits[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StateHeader {
+    pub version: u16,
+    pub rom_hash: u64,
+    pub thumbnail: Thumbnail,
+}
+
+impl StateHeader {
+    pub fn new(rom_hash: u64, screen: &Screen) -> StateHeader {
+        StateHeader { version: STATE_VERSION, rom_hash, thumbnail: Thumbnail::from_screen(screen) }
+    }
+
+    pub fn read(reader: &mut StateReader) -> Result<StateHeader, StateError> {
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let rom_hash = reader.u64()?;
+        let mut bits = [0; THUMBNAIL_SIZE];
+        bits.copy_from_slice(reader.bytes(THUMBNAIL_SIZE)?);
+        Ok(StateHeader { version, rom_hash, thumbnail: Thumbnail { bits } })
+    }
+
+    pub fn write(&self, writer: &mut StateWriter) {
+        writer.bytes(MAGIC);
+        writer.u16(self.version);
+        writer.u64(self.rom_hash);
+        writer.bytes(&self.thumbnail.bits);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self, field: &'static str) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidField(field)),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rom_hash_differs() {
+        assert_eq!(rom_hash(&[1, 2, 3]), rom_hash(&[1, 2, 3]));
+        assert_ne!(rom_hash(&[1, 2, 3]), rom_hash(&[1, 2, 4]));
+    }
+
+    #[test]
+    fn reader_writer_round_trip() {
+        let mut w = StateWriter::new();
+        w.u8(1);
+        w.bool(true);
+        w.u16(0x1234);
+        w.u32(0xDEAD_BEEF);
+        w.u64(u64::MAX);
+        let data = w.into_inner();
+        let mut r = StateReader::new(&data);
+        assert_eq!(Ok(1), r.u8());
+        assert_eq!(Ok(true), r.bool("flag"));
+        assert_eq!(Ok(0x1234), r.u16());
+        assert_eq!(Ok(0xDEAD_BEEF), r.u32());
+        assert_eq!(Ok(u64::MAX), r.u64());
+        assert!(r.is_empty());
+        assert_eq!(Err(StateError::Truncated), r.u8());
+    }
+
+    #[test]
+    fn invalid_bool() {
+        assert_eq!(Err(StateError::InvalidField("flag")), StateReader::new(&[2]).bool("flag"));
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let mut screen = Screen::new();
+        screen.set_pixel_value(3, 4, true);
+        let header = StateHeader::new(42, &screen);
+        let mut w = StateWriter::new();
+        header.write(&mut w);
+        let data = w.into_inner();
+        let read = StateHeader::read(&mut StateReader::new(&data)).unwrap();
+        assert_eq!(header, read);
+        assert!(read.thumbnail.is_on(3, 4));
+        assert!(!read.thumbnail.is_on(4, 4));
+    }
+
+    #[test]
+    fn header_bad_magic() {
+        assert_eq!(Err(StateError::BadMagic),
+                   StateHeader::read(&mut StateReader::new(b"NOPE")));
+    }
+
+    #[test]
+    fn header_bad_version() {
+        let mut w = StateWriter::new();
+        w.bytes(MAGIC);
+        w.u16(STATE_VERSION + 1);
+        assert_eq!(Err(StateError::UnsupportedVersion(STATE_VERSION + 1)),
+                   StateHeader::read(&mut StateReader::new(&w.into_inner())));
+    }
+
+    #[test]
+    fn thumbnail_hires() {
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+        screen.set_pixel_value(127, 63, true);
+        assert!(Thumbnail::from_screen(&screen).is_on(63, 31));
+    }
+}
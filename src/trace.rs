@@ -0,0 +1,281 @@
+use cpu::{Cpu, CpuError};
+use keypad::KeyPad;
+use opcodes::{decode, Op};
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    ops::RangeInclusive
+};
+
+const MAGIC: &[u8; 4] = b"C8TR";
+const VERSION: u8 = 1;
+const RECORD_SIZE: usize = 40;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    /// A header followed by fixed-size little-endian records, see `TraceEntry::to_bytes`.
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "binary" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// One executed instruction, with the machine state after it ran.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub frame: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceEntry {
+    /// The entry for the instruction at `pc`, once `cpu` ran it.
+    pub(crate) fn after(cycle: u64, frame: u64, pc: usize, opcode: u16, cpu: &Cpu) -> TraceEntry {
+        TraceEntry {
+            cycle,
+            frame,
+            pc: pc as u16,
+            opcode,
+            v: *cpu.registers(),
+            i: cpu.i,
+            delay_timer: cpu.delay_timer(),
+            sound_timer: cpu.sound_timer(),
+        }
+    }
+
+    pub fn op(&self) -> Option<Op> {
+        decode(self.opcode)
+    }
+
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.frame.to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[18..20].copy_from_slice(&self.opcode.to_le_bytes());
+        bytes[20..36].copy_from_slice(&self.v);
+        bytes[36..38].copy_from_slice(&self.i.to_le_bytes());
+        bytes[38] = self.delay_timer;
+        bytes[39] = self.sound_timer;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> TraceEntry {
+        let u64_at = |at: usize| {
+            let mut word = [0; 8];
+            word.copy_from_slice(&bytes[at..at + 8]);
+            u64::from_le_bytes(word)
+        };
+        let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let mut v = [0; 16];
+        v.copy_from_slice(&bytes[20..36]);
+        TraceEntry {
+            cycle: u64_at(0),
+            frame: u64_at(8),
+            pc: u16_at(16),
+            opcode: u16_at(18),
+            v,
+            i: u16_at(36),
+            delay_timer: bytes[38],
+            sound_timer: bytes[39],
+        }
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = self.op().map_or_else(|| "?".to_string(), |op| format!("{:?}", op));
+        let v: String = self.v.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{:>10} {:>8} {:04X} {:04X} {:<18} v={} i={:04X} dt={:02X} st={:02X}",
+               self.cycle, self.frame, self.pc, self.opcode, op, v, self.i,
+               self.delay_timer, self.sound_timer)
+    }
+}
+
+/// Reads back a trace written in the binary format.
+pub fn read_binary<R: Read>(mut reader: R) -> io::Result<Vec<TraceEntry>> {
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != MAGIC || header[4] != VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a binary trace"));
+    }
+    let mut entries = Vec::new();
+    let mut record = [0; RECORD_SIZE];
+    loop {
+        match reader.read_exact(&mut record) {
+            Ok(()) => entries.push(TraceEntry::from_bytes(&record)),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(entries),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs the CPU like `Cpu::cycle` and `Cpu::run_frame` while logging every instruction.
+///
+/// Write errors do not stop the emulation, the first one is returned by `finish`.
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    addresses: Option<RangeInclusive<usize>>,
+    frames: Option<RangeInclusive<u64>>,
+    cycle: u64,
+    frame: u64,
+    started: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W, format: TraceFormat) -> Tracer<W> {
+        Tracer {
+            writer,
+            format,
+            addresses: None,
+            frames: None,
+            cycle: 0,
+            frame: 0,
+            started: false,
+            error: None,
+        }
+    }
+
+    /// Only logs instructions whose address is in the range.
+    pub fn addresses(mut self, range: RangeInclusive<usize>) -> Tracer<W> {
+        self.addresses = Some(range);
+        self
+    }
+
+    /// Only logs instructions run during the frames in the range, counting from 0.
+    pub fn frames(mut self, range: RangeInclusive<u64>) -> Tracer<W> {
+        self.frames = Some(range);
+        self
+    }
+
+    pub fn cycle(&mut self, cpu: &mut Cpu, keypad: &KeyPad) -> Result<Op, CpuError> {
+        let pc = cpu.pc();
+        let opcode = cpu.opcode()?;
+        let op = cpu.cycle(keypad)?;
+        let logged = self.addresses.as_ref().map_or(true, |range| range.contains(&pc))
+            && self.frames.as_ref().map_or(true, |range| range.contains(&self.frame));
+        if logged {
+            let entry = TraceEntry::after(self.cycle, self.frame, pc, opcode, cpu);
+            self.write(&entry);
+        }
+        self.cycle += 1;
+        Ok(op)
+    }
+
+    pub fn run_frame(&mut self, cpu: &mut Cpu, keypad: &KeyPad, cycles: usize) -> Result<(), CpuError> {
+        for _ in 0..cycles {
+            if cpu.has_exited() {
+                break;
+            }
+            self.cycle(cpu, keypad)?;
+        }
+        cpu.update_timers();
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.format == TraceFormat::Binary && !self.started {
+            self.writer.write_all(MAGIC)?;
+            self.writer.write_all(&[VERSION])?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write(&mut self, entry: &TraceEntry) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", entry),
+            TraceFormat::Binary if !self.started => self.writer.write_all(MAGIC)
+                .and_then(|_| self.writer.write_all(&[VERSION]))
+                .and_then(|_| self.writer.write_all(&entry.to_bytes())),
+            TraceFormat::Binary => self.writer.write_all(&entry.to_bytes()),
+        };
+        self.started = true;
+        self.error = result.err();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 200: LD V0, 5    202: LD DT, V0    204: ADD V1, 1    206: JP 204
+    const PROGRAM: [u8; 8] = [0x60, 0x05, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04];
+
+    fn cpu() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load_program(&PROGRAM).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn text_format() {
+        let mut cpu = cpu();
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Text);
+        tracer.run_frame(&mut cpu, &KeyPad::new(), 2).unwrap();
+        tracer.run_frame(&mut cpu, &KeyPad::new(), 1).unwrap();
+        let text = String::from_utf8(tracer.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!("         0        0 0200 6005 Ld(0, 5)           \
+v=05000000000000000000000000000000 i=0200 dt=00 st=00", lines[0]);
+        assert!(lines[1].contains(" 0202 F015 SetDT(0) "));
+        assert!(lines[2].starts_with("         2        1 0204 7101 Add(1, 1) "));
+        assert!(lines[2].ends_with("dt=04 st=00"));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut cpu = cpu();
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Binary);
+        tracer.run_frame(&mut cpu, &KeyPad::new(), 4).unwrap();
+        let bytes = tracer.finish().unwrap();
+        assert_eq!(5 + 4 * RECORD_SIZE, bytes.len());
+        let entries = read_binary(&bytes[..]).unwrap();
+        assert_eq!(vec![0x200, 0x202, 0x204, 0x206], entries.iter().map(|e| e.pc).collect::<Vec<_>>());
+        assert_eq!(Some(Op::Jp(0x204)), entries[3].op());
+        assert_eq!(1, entries[3].v[1]);
+        assert!(read_binary(&b"nope!"[..]).is_err());
+
+        let empty = Tracer::new(Vec::new(), TraceFormat::Binary).finish().unwrap();
+        assert_eq!(Vec::<TraceEntry>::new(), read_binary(&empty[..]).unwrap());
+    }
+
+    #[test]
+    fn filters() {
+        let mut cpu = cpu();
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Binary)
+            .addresses(0x204..=0x205)
+            .frames(1..=2);
+        for _ in 0..4 {
+            tracer.run_frame(&mut cpu, &KeyPad::new(), 4).unwrap();
+        }
+        let entries = read_binary(&tracer.finish().unwrap()[..]).unwrap();
+        assert_eq!(vec![(4, 1), (6, 1), (8, 2), (10, 2)],
+                   entries.iter().map(|e| (e.cycle, e.frame)).collect::<Vec<_>>());
+        assert!(entries.iter().all(|e| e.pc == 0x204));
+    }
+}
@@ -0,0 +1,186 @@
+//! Runs CHIP-8 test ROMs until they halt and compares the final screen with the bitmaps in
+//! `tests/conformance/*.txt`, drawn like `image::ascii` (`.` off, `#` on).
+//!
+//! `roms/BC_test.ch8` is checked against a screen from a known-good emulator. The other
+//! ROMs are CHIPPER sources in `tests/conformance/`, assembled with the case's defines: they
+//! compare what they observe with values from the CHIP-8 specification and draw a filled
+//! box per passed check and an X per failure, so their expected screens only hold boxes.
+//! `CHIP8_BLESS=1 cargo test --test conformance` writes the current screens as the expected
+//! bitmaps, check them by eye before committing.
+
+extern crate chip8;
+
+use chip8::{asm::Assembler, headless::InputScript, image, Cpu, KeyPad, Op, Quirks, RandomSource};
+
+use std::{env, fs, path::Path};
+
+const CYCLES_PER_FRAME: usize = 20;
+
+struct Case {
+    /// Also the name of the expected bitmap, unless `expected` is set.
+    name: &'static str,
+    /// A binary ROM, or a CHIPPER source if it ends with `.src`.
+    rom: &'static str,
+    /// Symbols defined when assembling a source.
+    defines: &'static [&'static str],
+    expected: Option<&'static str>,
+    quirks: Quirks,
+    input: &'static str,
+    frames: u64,
+}
+
+impl Default for Case {
+    fn default() -> Case {
+        Case {
+            name: "",
+            rom: "",
+            defines: &[],
+            expected: None,
+            quirks: Quirks::default(),
+            input: "",
+            frames: 600,
+        }
+    }
+}
+
+enum Outcome {
+    Halted(u64),
+    Exited(u64),
+}
+
+/// A jump to itself, the usual way test ROMs stop.
+fn is_halted(cpu: &Cpu) -> bool {
+    match cpu.fetch_opcode() {
+        Ok(Op::Jp(addr)) => addr as usize == cpu.pc(),
+        _ => false,
+    }
+}
+
+fn run(case: &Case, cpu: &mut Cpu) -> Result<Outcome, String> {
+    let script = InputScript::parse(case.input).map_err(|e| format!("input: {}", e))?;
+    let mut keypad = KeyPad::new();
+    for frame in 0..case.frames {
+        script.apply(frame, &mut keypad);
+        for _ in 0..CYCLES_PER_FRAME {
+            if cpu.has_exited() {
+                return Ok(Outcome::Exited(frame));
+            }
+            if is_halted(cpu) {
+                return Ok(Outcome::Halted(frame));
+            }
+            cpu.cycle(&keypad).map_err(|e| format!("CPU fault at frame {}: {}", frame, e))?;
+        }
+        cpu.update_timers();
+    }
+    Err(format!("still running after {} frames", case.frames))
+}
+
+/// Expected and actual rows side by side, differing rows marked with `!`.
+fn side_by_side(expected: &str, actual: &str) -> String {
+    let mut out = format!("  {:<64} | actual\n", "expected");
+    let (expected, actual): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), actual.lines().collect());
+    for row in 0..expected.len().max(actual.len()) {
+        let (e, a) = (expected.get(row).cloned().unwrap_or(""), actual.get(row).cloned().unwrap_or(""));
+        out += &format!("{} {:<64} | {}\n", if e == a { ' ' } else { '!' }, e, a);
+    }
+    out
+}
+
+fn load_rom(case: &Case) -> Vec<u8> {
+    let rom_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(case.rom);
+    let rom = fs::read(&rom_path).unwrap_or_else(|e| panic!("{}: {}", rom_path.display(), e));
+    if !case.rom.ends_with(".src") {
+        return rom;
+    }
+    let assembler = case.defines.iter().fold(Assembler::new(), |a, name| a.define(name));
+    assembler.assemble(&String::from_utf8_lossy(&rom))
+        .unwrap_or_else(|e| panic!("{}: {}", rom_path.display(), e))
+}
+
+fn check(case: Case) {
+    let rom = load_rom(&case);
+
+    let mut cpu = Cpu::with_quirks(case.quirks);
+    cpu.set_random_source(RandomSource::new(0));
+    cpu.load_program(&rom).unwrap();
+    let outcome = run(&case, &mut cpu);
+    let actual = image::ascii(&cpu.screen);
+
+    let expected_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/conformance")
+        .join(format!("{}.txt", case.expected.unwrap_or(case.name)));
+    if env::var_os("CHIP8_BLESS").is_some() {
+        fs::write(&expected_path, &actual).unwrap();
+    }
+    let expected = fs::read_to_string(&expected_path)
+        .unwrap_or_else(|e| panic!("{}: {}: {}\nactual screen:\n{}", case.name, expected_path.display(), e, actual));
+
+    match outcome {
+        Ok(Outcome::Halted(_)) | Ok(Outcome::Exited(_)) if expected == actual => (),
+        Ok(Outcome::Halted(frame)) | Ok(Outcome::Exited(frame)) => panic!(
+            "{}: halted at frame {} with the wrong screen, pc={:04X}\n{}",
+            case.name, frame, cpu.pc(), side_by_side(&expected, &actual)),
+        Err(e) => panic!("{}: {}, pc={:04X}\n{}", case.name, e, cpu.pc(), side_by_side(&expected, &actual)),
+    }
+}
+
+#[test]
+fn bc_test_super_chip() {
+    check(Case {
+        name: "BC_test-schip",
+        rom: "roms/BC_test.ch8",
+        expected: Some("BC_test"),
+        quirks: Quirks::SUPER_CHIP,
+        ..Case::default()
+    });
+}
+
+#[test]
+fn opcodes() {
+    check(Case {
+        name: "opcodes",
+        rom: "tests/conformance/opcodes.src",
+        ..Case::default()
+    });
+}
+
+#[test]
+fn flags() {
+    check(Case {
+        name: "flags",
+        rom: "tests/conformance/flags.src",
+        ..Case::default()
+    });
+}
+
+/// The quirks each platform is expected to have, listed independently of the `Quirks`
+/// presets so that a wrong preset fails too.
+#[test]
+fn quirks() {
+    let platforms: &[(&str, Quirks, &[&str])] = &[
+        ("quirks-vip", Quirks::COSMAC_VIP, &["VF_RESET", "MEMORY_INC", "DISPLAY_WAIT", "SHIFT_VY"]),
+        ("quirks-chip48", Quirks::CHIP_48, &["MEMORY_INC_X", "JUMP_VX"]),
+        ("quirks-schip", Quirks::SUPER_CHIP, &["JUMP_VX"]),
+        ("quirks-xochip", Quirks::XO_CHIP, &["MEMORY_INC", "WRAP", "SHIFT_VY"]),
+    ];
+    for &(name, quirks, defines) in platforms {
+        check(Case {
+            name,
+            rom: "tests/conformance/quirks.src",
+            defines,
+            expected: Some("quirks"),
+            quirks,
+            ..Case::default()
+        });
+    }
+}
+
+#[test]
+fn keypad() {
+    check(Case {
+        name: "keypad",
+        rom: "tests/conformance/keypad.src",
+        input: "10 press 5\n30 release 5",
+        ..Case::default()
+    });
+}
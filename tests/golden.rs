@@ -0,0 +1,158 @@
+//! Replays a scenario for every ROM in `roms/` and checks hashes of the screen at chosen
+//! frames. Scenarios live in `tests/golden/<rom stem>.txt`:
+//!
+//! ```text
+//! seed 1          # for CXNN, 0 by default
+//! quirks vip      # optional, like --quirks
+//! frames 600      # how long to run
+//! 30 press 5      # input script lines, see headless::InputScript
+//! 32 release 5
+//! screen 300 0123456789ABCDEF
+//! ```
+//!
+//! `screen FRAME HASH` is checked after FRAME frames ran. `CHIP8_BLESS=1 cargo test --test
+//! golden` rewrites the hashes after an intentional change.
+
+extern crate chip8;
+
+use chip8::{headless::InputScript, image, state, Cpu, KeyPad, Quirks, RandomSource, Speed};
+
+use std::{env, fs, path::Path};
+
+struct Scenario {
+    seed: u64,
+    quirks: Quirks,
+    frames: u64,
+    input: InputScript,
+    screens: Vec<(u64, u64)>,
+}
+
+fn parse(text: &str) -> Result<Scenario, String> {
+    let mut scenario = Scenario {
+        seed: 0,
+        quirks: Quirks::default(),
+        frames: 0,
+        input: InputScript::default(),
+        screens: Vec::new(),
+    };
+    // Directives are blanked out so input errors keep their line numbers.
+    let mut input = String::new();
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+        let words: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        let number = |word: Option<&&str>, radix| word.and_then(|w| u64::from_str_radix(w, radix).ok());
+        match words.first() {
+            Some(&"seed") => scenario.seed = number(words.get(1), 10)
+                .ok_or_else(|| error("expected a seed"))?,
+            Some(&"frames") => scenario.frames = number(words.get(1), 10)
+                .ok_or_else(|| error("expected a frame count"))?,
+            Some(&"quirks") => scenario.quirks = words.get(1).and_then(|name| Quirks::from_name(name))
+                .ok_or_else(|| error("expected vip, chip48, schip, octo or xochip"))?,
+            Some(&"screen") => match (number(words.get(1), 10), number(words.get(2), 16)) {
+                (Some(frame), _) if scenario.screens.last().is_some_and(|&(last, _)| frame <= last) =>
+                    return Err(error("screens must be in frame order")),
+                (Some(frame), Some(hash)) => scenario.screens.push((frame, hash)),
+                _ => return Err(error("expected a frame and a hash")),
+            },
+            _ => {
+                input.push_str(line);
+                input.push('\n');
+                continue;
+            }
+        }
+        input.push('\n');
+    }
+    scenario.input = InputScript::parse(&input).map_err(|e| e.to_string())?;
+    if scenario.screens.is_empty() {
+        return Err("no screen to check".to_string());
+    }
+    if let Some(&(frame, _)) = scenario.screens.iter().find(|&&(frame, _)| frame > scenario.frames) {
+        return Err(format!("screen {} is after the last frame", frame));
+    }
+    Ok(scenario)
+}
+
+/// Runs the scenario and returns every checked screen as ASCII art.
+fn replay(rom: &[u8], scenario: &Scenario) -> Result<Vec<(u64, String)>, String> {
+    let mut cpu = Cpu::with_quirks(scenario.quirks);
+    cpu.set_random_source(RandomSource::new(scenario.seed));
+    cpu.load_program(rom)?;
+    let mut keypad = KeyPad::new();
+    let mut screens = Vec::new();
+    for frame in 0..=scenario.frames {
+        for &(checked, _) in scenario.screens.iter().filter(|&&(checked, _)| checked == frame) {
+            screens.push((checked, image::ascii(&cpu.screen)));
+        }
+        if frame == scenario.frames || cpu.has_exited() {
+            continue;
+        }
+        scenario.input.apply(frame, &mut keypad);
+        cpu.run_frame(&keypad, Speed::default().cycles_in_frame(frame))
+            .map_err(|e| format!("CPU fault at frame {}: {}\n{}", frame, e, image::ascii(&cpu.screen)))?;
+    }
+    Ok(screens)
+}
+
+fn hash(screen: &str) -> u64 {
+    state::rom_hash(screen.as_bytes())
+}
+
+fn bless(text: &str, screens: &[(u64, String)]) -> String {
+    let mut screens = screens.iter();
+    let mut out = String::new();
+    for line in text.lines() {
+        if line.split_whitespace().next() == Some("screen") {
+            let (frame, ref screen) = *screens.next().unwrap();
+            out += &format!("screen {} {:016X}\n", frame, hash(screen));
+        } else {
+            out += line;
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[test]
+fn bundled_roms() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let blessing = env::var_os("CHIP8_BLESS").is_some();
+    let mut roms: Vec<_> = fs::read_dir(root.join("roms")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    roms.sort();
+
+    let mut failures = Vec::new();
+    for rom_path in roms {
+        let name = rom_path.file_stem().unwrap().to_string_lossy().into_owned();
+        let path = root.join("tests/golden").join(format!("{}.txt", name));
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                failures.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+        };
+        let rom = fs::read(&rom_path).unwrap();
+        let result = parse(&text)
+            .and_then(|scenario| replay(&rom, &scenario).map(|screens| (scenario, screens)));
+        let (scenario, screens) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                failures.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+        };
+        if blessing {
+            fs::write(&path, bless(&text, &screens)).unwrap();
+            continue;
+        }
+        let mismatch = scenario.screens.iter().zip(&screens)
+            .find(|&(&(_, expected), (_, screen))| hash(screen) != expected);
+        if let Some((&(frame, expected), (_, screen))) = mismatch {
+            failures.push(format!("{}: screen at frame {} hashes to {:016X}, expected {:016X}\n{}",
+                                  name, frame, hash(screen), expected, screen));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}